@@ -0,0 +1,210 @@
+//! Local code metrics attached to refactor-plan prompts
+//!
+//! Gives the planning agent a starting point (file sizes, function counts and
+//! line coverage) without it having to measure the code itself.
+
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Directories never worth measuring
+const SKIP_DIRS: &[&str] = &["target", "node_modules"];
+
+/// Metrics for one source file
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileStats {
+    /// Path relative to the repository root
+    pub path: String,
+    pub lines: usize,
+    pub functions: usize,
+    /// Line coverage percentage, when a report covers the file
+    pub coverage: Option<f64>,
+}
+
+/// Measure every Rust file under `target` (a file or directory in `repo_path`),
+/// joining in line coverage from a Cobertura report if given
+pub fn collect(repo_path: &Path, target: &str, coverage: Option<&Path>) -> Result<Vec<FileStats>> {
+    let root = repo_path.join(target);
+    if !root.exists() {
+        bail!("{} does not exist", root.display());
+    }
+
+    let coverage = match coverage {
+        Some(report) => parse_cobertura(
+            &std::fs::read_to_string(report)
+                .with_context(|| format!("Failed to read coverage report: {}", report.display()))?,
+        ),
+        None => HashMap::new(),
+    };
+
+    let mut stats = Vec::new();
+    for file in rust_files(&root) {
+        let Ok(source) = std::fs::read_to_string(&file) else {
+            continue;
+        };
+        let path = file
+            .strip_prefix(repo_path)
+            .unwrap_or(&file)
+            .to_string_lossy()
+            .into_owned();
+        stats.push(FileStats {
+            coverage: coverage_for(&coverage, &path),
+            lines: source.lines().count(),
+            functions: count_functions(&source),
+            path,
+        });
+    }
+
+    Ok(stats)
+}
+
+/// Every `.rs` file at or under `path`, skipping hidden and build directories
+pub fn rust_files(path: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    walk(path, &mut files);
+    files.sort();
+    files
+}
+
+fn walk(path: &Path, files: &mut Vec<PathBuf>) {
+    if path.is_file() {
+        if path.extension().is_some_and(|ext| ext == "rs") {
+            files.push(path.to_path_buf());
+        }
+        return;
+    }
+
+    let Ok(entries) = std::fs::read_dir(path) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with('.') || SKIP_DIRS.contains(&name.as_ref()) {
+            continue;
+        }
+        walk(&entry.path(), files);
+    }
+}
+
+/// Count `fn` items, including methods and nested functions
+fn count_functions(source: &str) -> usize {
+    let re = regex::Regex::new(
+        r"(?m)^\s*(pub(\([^)]*\))?\s+)?((const|async|unsafe|extern\s+\S+)\s+)*fn\s+\w+",
+    )
+    .unwrap();
+    re.find_iter(source).count()
+}
+
+/// Line coverage percentage per file from a Cobertura XML report
+fn parse_cobertura(xml: &str) -> HashMap<String, f64> {
+    let class_re = regex::Regex::new(r"<class\s[^>]*>").unwrap();
+    let attr_re = regex::Regex::new(r#"([\w-]+)="([^"]*)""#).unwrap();
+
+    let mut coverage = HashMap::new();
+    for class in class_re.find_iter(xml) {
+        let attrs: HashMap<&str, &str> = attr_re
+            .captures_iter(class.as_str())
+            .filter_map(|cap| Some((cap.get(1)?.as_str(), cap.get(2)?.as_str())))
+            .collect();
+        if let (Some(file), Some(rate)) = (attrs.get("filename"), attrs.get("line-rate")) {
+            if let Ok(rate) = rate.parse::<f64>() {
+                coverage.insert(file.to_string(), rate * 100.0);
+            }
+        }
+    }
+    coverage
+}
+
+/// Report filenames may be relative to the crate or absolute, so match on
+/// path suffix
+fn coverage_for(coverage: &HashMap<String, f64>, path: &str) -> Option<f64> {
+    coverage
+        .iter()
+        .find(|(file, _)| path.ends_with(file.as_str()) || file.ends_with(path))
+        .map(|(_, rate)| *rate)
+}
+
+/// Template context for the refactor-plan prompt
+pub fn refactor_context(path: &str, plan_file: &str, files: &[FileStats]) -> Value {
+    let files: Vec<Value> = files
+        .iter()
+        .map(|file| {
+            json!({
+                "path": file.path,
+                "lines": file.lines,
+                "functions": file.functions,
+                "coverage": file.coverage.map(|c| format!("{c:.1}%")),
+            })
+        })
+        .collect();
+
+    json!({
+        "path": path,
+        "plan_file": plan_file,
+        "files": files,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_count_functions() {
+        let source = r#"
+fn private() {}
+pub fn public() {}
+pub(crate) async fn run() {}
+impl Foo {
+    pub const fn new() -> Self { Self }
+}
+// fn commented_out() is ignored
+let f = "fn in a string";
+"#;
+        assert_eq!(count_functions(source), 4);
+    }
+
+    #[test]
+    fn test_parse_cobertura() {
+        let xml = r#"<coverage line-rate="0.5">
+            <class name="lexer" filename="src/parser/lexer.rs" line-rate="0.825" branch-rate="0">
+            <class name="other" line-rate="1" filename="src/other.rs">
+        </coverage>"#;
+
+        let coverage = parse_cobertura(xml);
+        assert_eq!(coverage.len(), 2);
+        assert_eq!(coverage["src/parser/lexer.rs"], 82.5);
+        assert_eq!(coverage_for(&coverage, "src/other.rs"), Some(100.0));
+    }
+
+    #[test]
+    fn test_collect_walks_target_path() {
+        let repo = TempDir::new().unwrap();
+        let parser = repo.path().join("src/parser");
+        std::fs::create_dir_all(parser.join("target")).unwrap();
+        std::fs::write(parser.join("lexer.rs"), "fn a() {}\nfn b() {}\n").unwrap();
+        std::fs::write(parser.join("README.md"), "fn not_rust() {}").unwrap();
+        std::fs::write(parser.join("target/gen.rs"), "fn skipped() {}").unwrap();
+        let report = repo.path().join("cobertura.xml");
+        std::fs::write(
+            &report,
+            r#"<class filename="src/parser/lexer.rs" line-rate="0.5">"#,
+        )
+        .unwrap();
+
+        let stats = collect(repo.path(), "src/parser", Some(&report)).unwrap();
+        assert_eq!(
+            stats,
+            vec![FileStats {
+                path: "src/parser/lexer.rs".to_string(),
+                lines: 2,
+                functions: 2,
+                coverage: Some(50.0),
+            }]
+        );
+        assert!(collect(repo.path(), "src/missing", None).is_err());
+    }
+}
@@ -0,0 +1,164 @@
+//! Policy for approving workflow runs (`approve`)
+//!
+//! Runs waiting for approval get the repository's secrets once approved, so
+//! rerunning every one of them would let any PR that edits
+//! `.github/workflows` exfiltrate them. A run is only approved when its actor,
+//! workflow file and head branch are allowed by `[approve]` and it is for the
+//! head commit of a PR that leaves the protected paths alone.
+
+use crate::config::ApproveConfig;
+use crate::forge::{PullRequest, WorkflowRun};
+
+/// What became of a run waiting for approval
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Approval {
+    Approved,
+    /// The rerun request was refused
+    Failed,
+    /// The policy doesn't allow it, and why
+    Skipped(String),
+}
+
+/// Why `run` must not be approved, or `None` if the policy allows it; `pr` is
+/// the open PR the run belongs to, if one was found
+pub fn skip_reason(
+    config: &ApproveConfig,
+    run: &WorkflowRun,
+    pr: Option<&PullRequest>,
+) -> Option<String> {
+    if !config.actors.is_empty()
+        && !config
+            .actors
+            .iter()
+            .any(|actor| actor.eq_ignore_ascii_case(&run.actor))
+    {
+        return Some(format!("actor @{} is not allowed", run.actor));
+    }
+    if !config.workflows.is_empty() && !config.workflows.contains(&run.workflow_path) {
+        return Some(format!("workflow {} is not allowed", run.workflow_path));
+    }
+    if !config.branches.is_empty()
+        && !config
+            .branches
+            .iter()
+            .any(|prefix| run.head_branch.starts_with(prefix.as_str()))
+    {
+        return Some(format!("branch {} is not allowed", run.head_branch));
+    }
+
+    // The PR's files are only what its current head changes; a run for an
+    // earlier commit may run code a later push reverted
+    if let Some(pr) = pr.filter(|pr| {
+        !run.head_sha.is_empty() && !pr.head_sha.is_empty() && run.head_sha != pr.head_sha
+    }) {
+        return Some(format!("run is for an older commit than PR #{}", pr.number));
+    }
+
+    if config.protected_paths.is_empty() {
+        return None;
+    }
+    let Some(pr) = pr else {
+        return Some("no open PR found to check its changed paths".to_string());
+    };
+    if pr.truncated {
+        return Some(format!("PR #{} changes too many files to check", pr.number));
+    }
+    pr.files
+        .iter()
+        .find(|file| config.protected_paths.iter().any(|p| is_within(file, p)))
+        .map(|file| format!("PR #{} changes protected path {file}", pr.number))
+}
+
+/// Whether `path` is `protected`, or inside it if it ends with `/`
+fn is_within(path: &str, protected: &str) -> bool {
+    if protected.ends_with('/') {
+        path.starts_with(protected)
+    } else {
+        path == protected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_skip_reasons() {
+        let config = ApproveConfig {
+            workflows: vec![".github/workflows/ci.yml".to_string()],
+            branches: vec!["copilot/".to_string()],
+            ..ApproveConfig::default()
+        };
+        let run = WorkflowRun {
+            id: 100,
+            actor: "copilot".to_string(),
+            head_branch: "copilot/fix-hover".to_string(),
+            workflow_path: ".github/workflows/ci.yml".to_string(),
+            ..WorkflowRun::default()
+        };
+        let pr = PullRequest {
+            number: 15,
+            files: vec!["src/lib.rs".to_string()],
+            ..PullRequest::default()
+        };
+        let reason = |run: &WorkflowRun, pr: Option<&PullRequest>| skip_reason(&config, run, pr);
+
+        assert_eq!(reason(&run, Some(&pr)), None);
+        assert_eq!(
+            reason(&run, None).unwrap(),
+            "no open PR found to check its changed paths"
+        );
+
+        let stranger = WorkflowRun {
+            actor: "mallory".to_string(),
+            ..run.clone()
+        };
+        assert_eq!(
+            reason(&stranger, Some(&pr)).unwrap(),
+            "actor @mallory is not allowed"
+        );
+        let release = WorkflowRun {
+            workflow_path: ".github/workflows/release.yml".to_string(),
+            ..run.clone()
+        };
+        assert!(reason(&release, Some(&pr)).unwrap().starts_with("workflow"));
+        let branch = WorkflowRun {
+            head_branch: "main".to_string(),
+            ..run.clone()
+        };
+        assert!(reason(&branch, Some(&pr)).unwrap().starts_with("branch"));
+
+        let pushed = PullRequest {
+            head_sha: "bbb".to_string(),
+            ..pr.clone()
+        };
+        let stale = WorkflowRun {
+            head_sha: "aaa".to_string(),
+            ..run.clone()
+        };
+        assert_eq!(
+            reason(&stale, Some(&pushed)).unwrap(),
+            "run is for an older commit than PR #15"
+        );
+        assert_eq!(reason(&stale, Some(&pr)), None);
+
+        let edits_ci = PullRequest {
+            files: vec![
+                "src/lib.rs".to_string(),
+                ".github/workflows/ci.yml".to_string(),
+            ],
+            ..pr.clone()
+        };
+        assert_eq!(
+            reason(&run, Some(&edits_ci)).unwrap(),
+            "PR #15 changes protected path .github/workflows/ci.yml"
+        );
+        let huge = PullRequest {
+            truncated: true,
+            ..pr.clone()
+        };
+        assert!(reason(&run, Some(&huge))
+            .unwrap()
+            .contains("too many files"));
+    }
+}
@@ -0,0 +1,261 @@
+//! Global concurrency budget for agent tasks
+//!
+//! Each workflow has its own `max_agents`, but cron runs several workflows
+//! against several repositories, so together they can exceed the Copilot
+//! quota or bury reviewers in PRs. Active work is measured from the forge
+//! (open agent-authored PRs plus running agent tasks) before spawning, and
+//! every spawn site stops once the `[budget]` caps are reached. A forge only
+//! lists its own repository's PRs, so `sweep` counts the agent PRs of every
+//! manifest repository up front and hands them to each repository's budget.
+//!
+//! When agent tasks can't be listed (the endpoint or `gh agent-task list
+//! --json` is unavailable) the active work is unknown, since tasks spawned
+//! moments ago have no PR yet, so the budget has no room left until they can
+//! be listed again. With both caps off nothing needs counting.
+
+use crate::config::BudgetConfig;
+use crate::forge::{AgentTaskState, Forge};
+use anyhow::{bail, Result};
+use std::collections::HashSet;
+use std::fmt;
+
+/// Active agent work against the configured caps
+#[derive(Debug, Clone)]
+pub struct Budget {
+    repo: String,
+    per_repo: u32,
+    total: u32,
+    /// Agent PRs and running tasks in the forge's repository
+    repo_active: usize,
+    /// `repo_active` plus the agent PRs and running tasks of every other
+    /// repository
+    total_active: usize,
+    /// Agent tasks couldn't be listed, so the counts above are too low
+    tasks_unlisted: bool,
+}
+
+impl Budget {
+    /// Count the active agent work visible through `forge`, plus the agent
+    /// PRs of the other repositories in `config.other_agent_prs`
+    pub fn measure(forge: &dyn Forge, config: &BudgetConfig) -> Result<Self> {
+        let repo = forge.repo_name()?;
+        let agent_prs: HashSet<u32> = agent_prs(forge, config)?.into_iter().collect();
+        let other_prs = |other: &str| config.other_agent_prs.get(other).filter(|_| other != repo);
+
+        let mut repo_active = agent_prs.len();
+        let mut elsewhere: usize = config
+            .other_agent_prs
+            .keys()
+            .filter_map(|other| other_prs(other))
+            .map(Vec::len)
+            .sum();
+        let mut tasks_unlisted = false;
+        let tasks = forge.list_agent_tasks().unwrap_or_else(|e| {
+            println!("⚠️  Agent tasks can't be listed, so no agents will be spawned: {e:#}");
+            tasks_unlisted = true;
+            Vec::new()
+        });
+        for task in tasks {
+            if task.state != AgentTaskState::Running {
+                continue;
+            }
+            if task.repo.as_deref().is_none_or(|r| r == repo) {
+                // A task that already opened its PR is counted through the PR
+                if task.pr_number.is_none_or(|pr| !agent_prs.contains(&pr)) {
+                    repo_active += 1;
+                }
+            } else if task
+                .repo
+                .as_deref()
+                .and_then(other_prs)
+                .zip(task.pr_number)
+                .is_none_or(|(prs, pr)| !prs.contains(&pr))
+            {
+                elsewhere += 1;
+            }
+        }
+
+        Ok(Self {
+            repo,
+            per_repo: config.per_repo,
+            total: config.total,
+            repo_active,
+            total_active: repo_active + elsewhere,
+            tasks_unlisted,
+        })
+    }
+
+    /// How many more agents may be spawned right now
+    pub fn remaining(&self) -> usize {
+        let left = |cap: u32, active: usize| match cap {
+            0 => usize::MAX,
+            cap => (cap as usize).saturating_sub(active),
+        };
+        let left = left(self.per_repo, self.repo_active).min(left(self.total, self.total_active));
+        if self.tasks_unlisted && left != usize::MAX {
+            return 0;
+        }
+        left
+    }
+
+    /// Fail if no agent may be spawned
+    pub fn check(&self) -> Result<()> {
+        if self.remaining() == 0 {
+            bail!("Concurrency budget exhausted ({self})");
+        }
+        Ok(())
+    }
+
+    /// Count an agent spawned since the budget was measured
+    pub fn spend(&mut self) {
+        self.repo_active += 1;
+        self.total_active += 1;
+    }
+}
+
+impl fmt::Display for Budget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cap = |cap: u32| match cap {
+            0 => "∞".to_string(),
+            cap => cap.to_string(),
+        };
+        write!(
+            f,
+            "{}/{} active in {}, {}/{} overall",
+            self.repo_active,
+            cap(self.per_repo),
+            self.repo,
+            self.total_active,
+            cap(self.total)
+        )?;
+        if self.tasks_unlisted {
+            write!(f, ", not counting agent tasks, which can't be listed")?;
+        }
+        Ok(())
+    }
+}
+
+/// Numbers of the open PRs authored by an agent in `forge`'s repository
+pub fn agent_prs(forge: &dyn Forge, config: &BudgetConfig) -> Result<Vec<u32>> {
+    Ok(forge
+        .list_open_prs()?
+        .into_iter()
+        .filter(|pr| is_agent(&pr.author, &config.agent_authors))
+        .map(|pr| pr.number)
+        .collect())
+}
+
+/// Whether a PR author is one of the configured agents; `gh` reports app
+/// authors as `app/<name>`
+pub fn is_agent(author: &str, agents: &[String]) -> bool {
+    let author = author.strip_prefix("app/").unwrap_or(author);
+    agents
+        .iter()
+        .any(|agent| agent.eq_ignore_ascii_case(author))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::forge::fake::FakeForge;
+
+    #[test]
+    fn test_measure_counts_agent_prs_and_running_tasks() {
+        let forge = FakeForge::from_json(
+            r#"{
+                "prs": [
+                    { "number": 1, "title": "a", "author": "app/copilot-swe-agent" },
+                    { "number": 2, "title": "b", "author": "Copilot" },
+                    { "number": 3, "title": "c", "author": "octocat" }
+                ],
+                "agent_tasks": [
+                    { "id": "t1", "state": "in_progress", "pr_number": 2 },
+                    { "id": "t2", "state": "queued" },
+                    { "id": "t3", "state": "completed" },
+                    { "id": "t4", "state": "in_progress", "repo": "acme/other" }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let config = BudgetConfig {
+            per_repo: 4,
+            total: 5,
+            ..BudgetConfig::default()
+        };
+        let mut budget = Budget::measure(&forge, &config).unwrap();
+        assert_eq!((budget.repo_active, budget.total_active), (3, 4));
+        assert_eq!(budget.remaining(), 1);
+        assert!(budget.check().is_ok());
+
+        budget.spend();
+        assert_eq!(budget.remaining(), 0);
+        let err = budget.check().unwrap_err().to_string();
+        assert!(err.contains("4/4 active in acme/widgets, 5/5 overall"));
+
+        let unlimited = BudgetConfig {
+            per_repo: 0,
+            total: 0,
+            ..BudgetConfig::default()
+        };
+        assert_eq!(
+            Budget::measure(&forge, &unlimited).unwrap().remaining(),
+            usize::MAX
+        );
+    }
+
+    #[test]
+    fn test_unlistable_tasks_leave_no_room() {
+        let forge = FakeForge::from_json(
+            r#"{
+                "prs": [{ "number": 1, "title": "a", "author": "Copilot" }],
+                "agent_tasks": [{ "id": "t1", "state": "queued" }],
+                "agent_tasks_unavailable": true
+            }"#,
+        )
+        .unwrap();
+
+        let budget = Budget::measure(&forge, &BudgetConfig::default()).unwrap();
+        assert_eq!((budget.repo_active, budget.total_active), (1, 1));
+        assert_eq!(budget.remaining(), 0);
+        let err = budget.check().unwrap_err().to_string();
+        assert!(err.contains("agent tasks, which can't be listed"));
+
+        let unlimited = BudgetConfig {
+            per_repo: 0,
+            total: 0,
+            ..BudgetConfig::default()
+        };
+        let budget = Budget::measure(&forge, &unlimited).unwrap();
+        assert_eq!(budget.remaining(), usize::MAX);
+    }
+
+    #[test]
+    fn test_total_counts_other_repositories_agent_prs() {
+        let forge = FakeForge::from_json(
+            r#"{
+                "prs": [{ "number": 1, "title": "a", "author": "Copilot" }],
+                "agent_tasks": [
+                    { "id": "t1", "state": "in_progress", "repo": "acme/other", "pr_number": 7 },
+                    { "id": "t2", "state": "in_progress", "repo": "acme/other", "pr_number": 9 }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let config = BudgetConfig {
+            total: 6,
+            other_agent_prs: [
+                ("acme/widgets".to_string(), vec![1]),
+                ("acme/other".to_string(), vec![7, 8]),
+                ("acme/gadgets".to_string(), vec![3]),
+            ]
+            .into(),
+            ..BudgetConfig::default()
+        };
+        // #1 once, #7 (whose task is counted through it), #8, #3 and t2
+        let budget = Budget::measure(&forge, &config).unwrap();
+        assert_eq!((budget.repo_active, budget.total_active), (1, 5));
+        assert_eq!(budget.remaining(), 1);
+    }
+}
@@ -0,0 +1,170 @@
+//! Pull the actionable lines out of a failed CI job's log
+//!
+//! Job logs run to thousands of lines of dependency downloads and passing
+//! tests. Nudge comments only quote what tells the agent what to fix:
+//! compiler and clippy diagnostics, failed test names and their panics, and
+//! `cargo fmt --check` diffs. When none of those are recognised the last few
+//! lines of the log are quoted instead.
+
+use regex::Regex;
+use serde::Serialize;
+
+/// At most this many entries of each kind are quoted
+const MAX_ENTRIES: usize = 5;
+/// Longest diagnostic or diff quoted, in lines
+const MAX_BLOCK_LINES: usize = 12;
+/// Lines quoted when nothing was recognised
+const TAIL_LINES: usize = 15;
+
+/// What went wrong, according to a job log
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Excerpt {
+    /// rustc and clippy errors, each with its location and notes
+    pub errors: Vec<String>,
+    pub failed_tests: Vec<String>,
+    /// `thread '…' panicked at …` with the panic message
+    pub panics: Vec<String>,
+    /// `cargo fmt --check` hunks
+    pub format_diffs: Vec<String>,
+    /// End of the log, only when nothing above was found
+    pub log_tail: String,
+}
+
+/// Extract the failures from a raw job log
+pub fn extract(log: &str) -> Excerpt {
+    let lines = clean(log);
+    let mut excerpt = Excerpt::default();
+
+    let diagnostic = Regex::new(r"^error(\[E\d+\])?: ").unwrap();
+    let summary = Regex::new(
+        r"^error: (could not compile|aborting due to|test failed|process didn't exit|\d+ previous error|Recipe|Process completed)",
+    )
+    .unwrap();
+    let failed_test = Regex::new(r"^test (\S+) \.\.\. FAILED$").unwrap();
+    let panic = Regex::new(r"^thread '[^']+' panicked at ").unwrap();
+
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i].as_str();
+
+        if diagnostic.is_match(line) && !summary.is_match(line) {
+            let block = take_block(&lines[i..], |l| !l.trim().is_empty());
+            i += block.len();
+            push(&mut excerpt.errors, block.join("\n"));
+            continue;
+        }
+        if let Some(caps) = failed_test.captures(line) {
+            push(&mut excerpt.failed_tests, caps[1].to_string());
+        } else if panic.is_match(line) {
+            // Since Rust 1.73 the message follows on its own line
+            let message = match lines.get(i + 1) {
+                Some(next) if line.ends_with(':') => format!("{line}\n{next}"),
+                _ => line.to_string(),
+            };
+            push(&mut excerpt.panics, message);
+        } else if line.starts_with("Diff in ") {
+            let block = take_block(&lines[i + 1..], |l| {
+                l.starts_with([' ', '+', '-']) && !l.starts_with("---- ")
+            });
+            i += block.len();
+            let mut hunk = vec![line.to_string()];
+            hunk.extend(block);
+            push(&mut excerpt.format_diffs, hunk.join("\n"));
+        }
+        i += 1;
+    }
+
+    if excerpt == Excerpt::default() {
+        let tail: Vec<&str> = lines
+            .iter()
+            .map(|l| l.as_str())
+            .filter(|l| !l.trim().is_empty())
+            .collect();
+        excerpt.log_tail = tail[tail.len().saturating_sub(TAIL_LINES)..].join("\n");
+    }
+
+    excerpt
+}
+
+/// Strip Actions timestamps, colours and `##[group]`-style markers
+fn clean(log: &str) -> Vec<String> {
+    let timestamp = Regex::new(r"^\d{4}-\d{2}-\d{2}T\d{2}:\d{2}:\d{2}(\.\d+)?Z ?").unwrap();
+    let ansi = Regex::new(r"\x1b\[[0-9;]*[A-Za-z]").unwrap();
+
+    log.lines()
+        .map(|line| {
+            let line = timestamp.replace(line, "");
+            ansi.replace_all(&line, "").trim_end().to_string()
+        })
+        .filter(|line| !line.starts_with("##["))
+        .collect()
+}
+
+/// Leading lines matching `keep`, capped at [`MAX_BLOCK_LINES`]
+fn take_block(lines: &[String], keep: impl Fn(&str) -> bool) -> Vec<String> {
+    lines
+        .iter()
+        .take_while(|l| keep(l))
+        .take(MAX_BLOCK_LINES)
+        .cloned()
+        .collect()
+}
+
+fn push(entries: &mut Vec<String>, entry: String) {
+    if entries.len() < MAX_ENTRIES && !entries.contains(&entry) {
+        entries.push(entry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extracts_diagnostics_tests_and_format_diffs() {
+        let log = "\
+2024-03-01T10:00:00.1234567Z ##[group]Run cargo test
+2024-03-01T10:00:01.0000000Z    Compiling widgets v0.1.0
+2024-03-01T10:00:02.0000000Z \x1b[1m\x1b[31merror[E0308]\x1b[0m: mismatched types
+2024-03-01T10:00:02.0000000Z   --> src/lib.rs:3:5
+2024-03-01T10:00:02.0000000Z    |
+2024-03-01T10:00:02.0000000Z 3  |     \"one\"
+2024-03-01T10:00:02.0000000Z    |     ^^^^^ expected `u32`, found `&str`
+2024-03-01T10:00:02.0000000Z
+2024-03-01T10:00:02.0000000Z error: could not compile `widgets` (lib) due to 1 previous error
+2024-03-01T10:00:03.0000000Z test parser::tests::empty ... ok
+2024-03-01T10:00:03.0000000Z test parser::tests::unicode ... FAILED
+2024-03-01T10:00:03.0000000Z ---- parser::tests::unicode stdout ----
+2024-03-01T10:00:03.0000000Z thread 'parser::tests::unicode' panicked at src/parser.rs:40:9:
+2024-03-01T10:00:03.0000000Z assertion `left == right` failed
+2024-03-01T10:00:04.0000000Z Diff in /home/runner/work/widgets/src/lib.rs at line 1:
+2024-03-01T10:00:04.0000000Z -fn one()->u32{1}
+2024-03-01T10:00:04.0000000Z +fn one() -> u32 {
+2024-03-01T10:00:04.0000000Z +    1
+2024-03-01T10:00:04.0000000Z +}
+2024-03-01T10:00:04.0000000Z ##[error]Process completed with exit code 1.
+";
+        let excerpt = extract(log);
+
+        assert_eq!(
+            excerpt.errors,
+            ["error[E0308]: mismatched types\n  --> src/lib.rs:3:5\n   |\n3  |     \"one\"\n   |     ^^^^^ expected `u32`, found `&str`"]
+        );
+        assert_eq!(excerpt.failed_tests, ["parser::tests::unicode"]);
+        assert_eq!(
+            excerpt.panics,
+            ["thread 'parser::tests::unicode' panicked at src/parser.rs:40:9:\nassertion `left == right` failed"]
+        );
+        assert_eq!(excerpt.format_diffs.len(), 1);
+        assert!(excerpt.format_diffs[0].ends_with("+    1\n+}"));
+        assert!(excerpt.log_tail.is_empty());
+    }
+
+    #[test]
+    fn test_unrecognised_logs_fall_back_to_the_tail() {
+        let log: String = (1..=40).map(|n| format!("line {n}\n")).collect();
+        let excerpt = extract(&log);
+        assert!(excerpt.log_tail.starts_with("line 26\n"));
+        assert!(excerpt.log_tail.ends_with("line 40"));
+    }
+}
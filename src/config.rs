@@ -0,0 +1,593 @@
+//! Per-repository configuration (`chore-bot.toml`)
+//!
+//! Every setting has a built-in default matching chore-bot's historical
+//! behaviour; a config file only needs to list what it changes. Tables are
+//! merged key by key, so `[workflows.test] max_agents = 2` keeps the default
+//! label and prompt.
+//!
+//! Any `[workflows.<name>]` table defines a workflow runnable with
+//! `chore-bot run <name>`; only `label` is required.
+
+use crate::forge::MergeMethod;
+use crate::prompts::PromptResolver;
+use crate::template;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// File names searched for in the target repository, in order
+const CONFIG_FILES: &[&str] = &["chore-bot.toml", ".chore-bot/config.toml"];
+
+/// How issues are grouped into agent tasks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Batching {
+    /// One agent per issue
+    Single,
+    /// One agent per module referenced by the issue titles
+    Module,
+    /// Fixed-size chunks of `batch_size` issues
+    Fixed,
+}
+
+/// Settings for a label-driven workflow
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WorkflowConfig {
+    /// Issue label to query
+    pub label: String,
+    /// Issues carrying any of these labels are skipped
+    pub exclude_labels: Vec<String>,
+    /// Maximum agents spawned per run
+    pub max_agents: u8,
+    /// Prompt name (`agents/<name>/prompt.md`) or path to a `.md` template;
+    /// defaults to the workflow name
+    pub prompt: String,
+    /// How issues are grouped into agent tasks
+    pub batching: Batching,
+    /// Issues per agent when `batching = "fixed"`
+    pub batch_size: u8,
+}
+
+impl Default for WorkflowConfig {
+    fn default() -> Self {
+        Self {
+            label: String::new(),
+            exclude_labels: Vec::new(),
+            max_agents: 3,
+            prompt: String::new(),
+            batching: Batching::Single,
+            batch_size: 5,
+        }
+    }
+}
+
+impl WorkflowConfig {
+    fn new(label: &str, max_agents: u8, prompt: &str, batching: Batching) -> Self {
+        Self {
+            label: label.to_string(),
+            max_agents,
+            prompt: prompt.to_string(),
+            batching,
+            ..Self::default()
+        }
+    }
+
+    /// Whether an issue's labels rule it out for this workflow
+    pub fn excludes(&self, labels: &[String]) -> bool {
+        labels.iter().any(|l| self.exclude_labels.contains(l))
+    }
+}
+
+/// Weights used to rank open issues before they are batched; an issue's
+/// score is the sum of its label points, age, reactions and milestone points
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PriorityConfig {
+    /// Points for each label an issue carries
+    pub labels: BTreeMap<String, i64>,
+    /// Points per day since the issue was opened
+    pub per_day: i64,
+    /// Age stops adding points after this many days
+    pub max_age_days: u64,
+    /// Points per reaction on the issue
+    pub per_reaction: i64,
+    /// Points for belonging to any milestone
+    pub milestone: i64,
+}
+
+impl Default for PriorityConfig {
+    fn default() -> Self {
+        let labels = [
+            ("priority-critical", 100),
+            ("priority-high", 50),
+            ("priority-medium", 20),
+            ("priority-low", 0),
+        ]
+        .into_iter()
+        .map(|(label, points)| (label.to_string(), points))
+        .collect();
+
+        Self {
+            labels,
+            per_day: 1,
+            max_age_days: 30,
+            per_reaction: 2,
+            milestone: 10,
+        }
+    }
+}
+
+/// Caps on concurrently active agent work, counted as open agent-authored
+/// PRs plus running agent tasks
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BudgetConfig {
+    /// Active agents allowed in one repository (0 for no cap)
+    pub per_repo: u32,
+    /// Active agents allowed across every repository (0 for no cap)
+    pub total: u32,
+    /// PR authors counted as agents, compared case-insensitively
+    pub agent_authors: Vec<String>,
+    /// Open agent PRs of the repositories swept alongside this one, by
+    /// `owner/name` (measured by `sweep`, not read from the file)
+    #[serde(skip)]
+    pub other_agent_prs: BTreeMap<String, Vec<u32>>,
+}
+
+impl Default for BudgetConfig {
+    fn default() -> Self {
+        Self {
+            per_repo: 10,
+            total: 30,
+            agent_authors: vec!["Copilot".to_string(), "copilot-swe-agent".to_string()],
+            other_agent_prs: BTreeMap::new(),
+        }
+    }
+}
+
+/// Which workflow runs waiting for approval `approve` may rerun
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ApproveConfig {
+    /// Logins whose runs may be approved, compared case-insensitively
+    /// (empty allows anyone)
+    pub actors: Vec<String>,
+    /// Workflow files that may be approved, e.g. `.github/workflows/ci.yml`
+    /// (empty allows any)
+    pub workflows: Vec<String>,
+    /// Head branch prefixes runs must come from, e.g. `copilot/` (empty allows
+    /// any)
+    pub branches: Vec<String>,
+    /// Runs whose PR changes any of these paths are never approved; a
+    /// trailing `/` covers a whole directory
+    pub protected_paths: Vec<String>,
+}
+
+impl Default for ApproveConfig {
+    fn default() -> Self {
+        Self {
+            actors: vec!["Copilot".to_string(), "copilot-swe-agent".to_string()],
+            workflows: Vec::new(),
+            branches: Vec::new(),
+            protected_paths: vec![
+                ".github/workflows/".to_string(),
+                ".github/actions/".to_string(),
+            ],
+        }
+    }
+}
+
+/// A comment template posted on PRs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommentConfig {
+    pub comment: String,
+}
+
+/// What happens to a PR whose checks keep failing despite nudges
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Escalation {
+    /// Add `escalation_label`
+    Label,
+    /// Mention the `ping` users in the escalation comment
+    Ping,
+    /// Close the PR and free its issues to be respawned
+    Close,
+}
+
+/// Nudges on PRs with failing checks, and when to stop nudging
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NudgeConfig {
+    pub comment: String,
+    /// Escalate once this many nudges, each on a different commit, haven't
+    /// turned the checks green (0 never escalates)
+    pub escalate_after: u32,
+    pub escalation: Escalation,
+    /// Label added by the `label` escalation
+    pub escalation_label: String,
+    /// Users or teams mentioned by the `ping` escalation, without `@`
+    pub ping: Vec<String>,
+    /// Comment posted when escalating (`{{nudges}}`, `{{mentions}}`)
+    pub escalation_comment: String,
+}
+
+impl Default for NudgeConfig {
+    fn default() -> Self {
+        Self {
+            comment: r#"@copilot This PR has failing CI checks.
+{{#each checks}}
+
+### ❌ {{name}}
+{{#if failed_tests}}
+
+Failing tests:
+{{#each failed_tests}}
+- `{{this}}`
+{{/each}}
+{{/if}}
+{{#if panics}}
+
+```text
+{{#each panics}}
+{{this}}
+{{/each}}
+```
+{{/if}}
+{{#if errors}}
+
+```text
+{{#each errors}}
+{{this}}
+{{/each}}
+```
+{{/if}}
+{{#if format_diffs}}
+
+`cargo fmt` wants:
+
+```diff
+{{#each format_diffs}}
+{{this}}
+{{/each}}
+```
+{{/if}}
+{{#if log_tail}}
+
+End of the log:
+
+```text
+{{log_tail}}
+```
+{{/if}}
+{{/each}}
+
+Please fix these and push again. Run `{{verify_command}}` locally to verify before pushing."#
+                .to_string(),
+            escalate_after: 3,
+            escalation: Escalation::Label,
+            escalation_label: "needs-human".to_string(),
+            ping: Vec::new(),
+            escalation_comment: "This PR's checks are still failing after {{nudges}} nudges, \
+                so chore-bot has stopped nudging it. {{mentions}}"
+                .to_string(),
+        }
+    }
+}
+
+/// Which agent PRs `merge` may merge, and how
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MergeConfig {
+    /// `squash`, `rebase` or `merge`
+    pub strategy: MergeMethod,
+    /// Approving reviews a PR needs; GitHub's own review requirements must
+    /// be met as well
+    pub required_approvals: u32,
+    /// PRs merged per run at most (0 for no cap)
+    pub max_per_run: u32,
+}
+
+impl Default for MergeConfig {
+    fn default() -> Self {
+        Self {
+            strategy: MergeMethod::Squash,
+            required_approvals: 1,
+            max_per_run: 5,
+        }
+    }
+}
+
+/// How open PRs are tied to the issues they work on
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LinksConfig {
+    /// Regex matched against PR titles and bodies whose first capture group
+    /// is an issue number, e.g. `#(\d+)`, for repositories whose PRs don't
+    /// use closing keywords. Unset, only closing keywords and GitHub's own
+    /// links count.
+    pub fallback_pattern: Option<String>,
+}
+
+/// Top-level configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Config {
+    /// Command agents must run before opening a PR (`{{verify_command}}`)
+    pub verify_command: String,
+    pub workflows: BTreeMap<String, WorkflowConfig>,
+    pub priority: PriorityConfig,
+    pub budget: BudgetConfig,
+    pub approve: ApproveConfig,
+    pub nudge: NudgeConfig,
+    pub conflicts: CommentConfig,
+    pub links: LinksConfig,
+    pub merge: MergeConfig,
+    /// Where workflow prompts are looked up (set from the CLI, not the file)
+    #[serde(skip)]
+    pub prompts: PromptResolver,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let workflows = [
+            (
+                "test",
+                WorkflowConfig::new("testing", 5, "test", Batching::Module),
+            ),
+            (
+                "feature",
+                WorkflowConfig::new("enhancement", 3, "feature", Batching::Single),
+            ),
+            (
+                "bug",
+                WorkflowConfig::new("bug", 3, "bug", Batching::Single),
+            ),
+            (
+                "chore",
+                WorkflowConfig::new("chore", 5, "chore", Batching::Single),
+            ),
+        ]
+        .into_iter()
+        .map(|(name, workflow)| (name.to_string(), workflow))
+        .collect();
+
+        Self {
+            verify_command: "make run-guidelines".to_string(),
+            workflows,
+            priority: PriorityConfig::default(),
+            budget: BudgetConfig::default(),
+            approve: ApproveConfig::default(),
+            nudge: NudgeConfig::default(),
+            conflicts: CommentConfig {
+                comment: r#"@copilot This PR has merge conflicts.
+{{#if conflicted_files}}
+
+Rebasing it conflicts in:
+{{#each conflicted_files}}
+- `{{this}}`
+{{/each}}
+{{/if}}
+
+Please rebase on main and resolve the conflicts, then push again."#
+                    .to_string(),
+            },
+            links: LinksConfig::default(),
+            merge: MergeConfig::default(),
+            prompts: PromptResolver::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Load `explicit` if given, otherwise the first config file found in
+    /// `repo_path`, otherwise the defaults
+    pub fn discover(explicit: Option<&Path>, repo_path: &Path) -> Result<Self> {
+        if let Some(path) = explicit {
+            return Self::load(path);
+        }
+
+        CONFIG_FILES
+            .iter()
+            .map(|name| repo_path.join(name))
+            .find(|path| path.exists())
+            .map(|path| Self::load(&path))
+            .unwrap_or_else(|| Ok(Self::default()))
+    }
+
+    /// Load a config file, layering it over the defaults
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file: {}", path.display()))?;
+        let mut config = Self::parse(&content)
+            .with_context(|| format!("Invalid config file: {}", path.display()))?;
+
+        // Template paths are relative to the config file
+        let base = path.parent().unwrap_or(Path::new("."));
+        for workflow in config.workflows.values_mut() {
+            if workflow.prompt.ends_with(".md") {
+                workflow.prompt = base.join(&workflow.prompt).to_string_lossy().into_owned();
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Parse config text, layering it over the defaults
+    pub fn parse(content: &str) -> Result<Self> {
+        Self::default().with_overrides(toml::from_str(content)?)
+    }
+
+    /// Layer parsed TOML over this config, table by table
+    pub fn with_overrides(&self, overrides: toml::Value) -> Result<Self> {
+        let mut merged = toml::Value::try_from(self)?;
+        merge(&mut merged, overrides);
+        let mut config: Self = merged.try_into()?;
+        config.prompts = self.prompts.clone();
+
+        template::validate(&config.nudge.comment).context("Invalid [nudge] comment")?;
+        template::validate(&config.nudge.escalation_comment)
+            .context("Invalid [nudge] escalation_comment")?;
+        if config.nudge.escalation == Escalation::Ping && config.nudge.ping.is_empty() {
+            bail!("[nudge] escalation = \"ping\" needs someone to ping");
+        }
+        template::validate(&config.conflicts.comment).context("Invalid [conflicts] comment")?;
+        if let Some(pattern) = &config.links.fallback_pattern {
+            let regex = regex::Regex::new(pattern).context("Invalid [links] fallback_pattern")?;
+            if regex.captures_len() < 2 {
+                bail!("[links] fallback_pattern needs a capture group for the issue number");
+            }
+        }
+
+        for (name, workflow) in &mut config.workflows {
+            if workflow.label.is_empty() {
+                bail!("Workflow {name:?} needs a label");
+            }
+            if workflow.prompt.is_empty() {
+                workflow.prompt = name.clone();
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Settings for the named workflow
+    pub fn workflow(&self, name: &str) -> Result<&WorkflowConfig> {
+        self.workflows
+            .get(name)
+            .with_context(|| format!("No workflow named {name:?} in config"))
+    }
+
+    /// Mutable settings for the named workflow
+    pub fn workflow_mut(&mut self, name: &str) -> Result<&mut WorkflowConfig> {
+        self.workflows
+            .get_mut(name)
+            .with_context(|| format!("No workflow named {name:?} in config"))
+    }
+}
+
+/// Path of the template a workflow prompt refers to, if it is a file path
+pub fn prompt_path(prompt: &str) -> Option<PathBuf> {
+    prompt.ends_with(".md").then(|| PathBuf::from(prompt))
+}
+
+/// Recursively merge `overrides` into `base`, table by table
+fn merge(base: &mut toml::Value, overrides: toml::Value) {
+    match (base, overrides) {
+        (toml::Value::Table(base), toml::Value::Table(overrides)) => {
+            for (key, value) in overrides {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, value) => *base = value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_defaults_match_builtin_workflows() {
+        let config = Config::default();
+        assert_eq!(config.workflow("test").unwrap().label, "testing");
+        assert_eq!(config.workflow("feature").unwrap().max_agents, 3);
+        assert_eq!(config.verify_command, "make run-guidelines");
+        assert!(config.workflow("docs").is_err());
+    }
+
+    #[test]
+    fn test_partial_override_keeps_other_defaults() {
+        let config = Config::parse(
+            r#"
+            verify_command = "just ci"
+
+            [workflows.test]
+            max_agents = 2
+            batching = "fixed"
+
+            [priority]
+            per_day = 0
+
+            [priority.labels]
+            "priority-low" = -10
+
+            [nudge]
+            comment = "please fix"
+            "#,
+        )
+        .unwrap();
+
+        let test = config.workflow("test").unwrap();
+        assert_eq!(test.max_agents, 2);
+        assert_eq!(test.batching, Batching::Fixed);
+        assert_eq!(test.label, "testing");
+        assert_eq!(config.verify_command, "just ci");
+        assert_eq!(config.nudge.comment, "please fix");
+        assert!(config.conflicts.comment.contains("merge conflicts"));
+        assert_eq!(config.priority.per_day, 0);
+        assert_eq!(config.priority.labels["priority-low"], -10);
+        assert_eq!(config.priority.labels["priority-critical"], 100);
+    }
+
+    #[test]
+    fn test_discover_finds_repo_config_and_resolves_prompt_paths() {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::create_dir(temp_dir.path().join(".chore-bot")).unwrap();
+        std::fs::write(
+            temp_dir.path().join(".chore-bot/config.toml"),
+            "[workflows.bug]\nlabel = \"type: bug\"\nprompt = \"prompts/bug.md\"\n",
+        )
+        .unwrap();
+
+        let config = Config::discover(None, temp_dir.path()).unwrap();
+        let bug = config.workflow("bug").unwrap();
+        assert_eq!(bug.label, "type: bug");
+        assert_eq!(
+            prompt_path(&bug.prompt).unwrap(),
+            temp_dir.path().join(".chore-bot/prompts/bug.md")
+        );
+    }
+
+    #[test]
+    fn test_invalid_values_are_rejected() {
+        assert!(Config::parse("[workflows.test]\nbatching = \"random\"\n").is_err());
+        assert!(Config::parse("[workflows.docs]\nmax_agents = 2\n").is_err());
+        assert!(Config::parse("[nudge]\ncomment = \"{{#if x}}\"\n").is_err());
+        assert!(Config::parse("[links]\nfallback_pattern = \"#(\"\n").is_err());
+        assert!(Config::parse("[links]\nfallback_pattern = '#\\d+'\n").is_err());
+        assert!(Config::parse("[merge]\nstrategy = \"octopus\"\n").is_err());
+    }
+
+    #[test]
+    fn test_user_defined_workflow_fills_defaults() {
+        let config = Config::parse(
+            r#"
+            [workflows.docs]
+            label = "documentation"
+            exclude_labels = ["wontfix"]
+            "#,
+        )
+        .unwrap();
+
+        let docs = config.workflow("docs").unwrap();
+        assert_eq!(docs.prompt, "docs");
+        assert_eq!(docs.max_agents, 3);
+        assert_eq!(docs.batching, Batching::Single);
+        assert!(docs.excludes(&["wontfix".to_string()]));
+        assert!(config.workflow("bug").is_ok());
+    }
+
+    #[test]
+    fn test_example_config_parses() {
+        let config = Config::parse(include_str!("../chore-bot.example.toml")).unwrap();
+        assert_eq!(config.workflows.len(), 4);
+    }
+}
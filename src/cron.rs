@@ -0,0 +1,211 @@
+//! Five-field cron expressions, evaluated in UTC
+//!
+//! Supports `*`, numbers, ranges (`1-5`), steps (`*/15`, `0-30/10`), lists
+//! (`1,15`) and the `@hourly`/`@daily`/`@weekly`/`@monthly` shorthands. As in
+//! classic cron, when both day-of-month and day-of-week are restricted a time
+//! matches if either does.
+
+use anyhow::{bail, Context, Result};
+use std::fmt;
+use std::str::FromStr;
+
+const MINUTE: u64 = 60;
+const DAY: u64 = 24 * 60 * MINUTE;
+/// Give up looking for a match after this long (covers `0 0 29 2 *`)
+const SEARCH_LIMIT: u64 = 5 * 366 * DAY;
+
+/// A parsed cron schedule
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cron {
+    source: String,
+    minutes: Vec<bool>,
+    hours: Vec<bool>,
+    days: Vec<bool>,
+    months: Vec<bool>,
+    weekdays: Vec<bool>,
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+impl FromStr for Cron {
+    type Err = anyhow::Error;
+
+    fn from_str(source: &str) -> Result<Self> {
+        Self::parse(source).with_context(|| format!("Invalid cron expression {source:?}"))
+    }
+}
+
+impl fmt::Display for Cron {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl Cron {
+    fn parse(source: &str) -> Result<Self> {
+        let expanded = match source.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            other => other,
+        };
+        let fields: Vec<&str> = expanded.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            bail!("expected 5 fields");
+        };
+
+        let mut weekday_set = field(weekdays, 0, 7).context("day of week")?;
+        // Both 0 and 7 mean Sunday
+        weekday_set[0] |= weekday_set[7];
+        weekday_set.truncate(7);
+
+        Ok(Self {
+            source: source.trim().to_string(),
+            minutes: field(minutes, 0, 59).context("minute")?,
+            hours: field(hours, 0, 23).context("hour")?,
+            days: field(days, 1, 31).context("day of month")?,
+            months: field(months, 1, 12).context("month")?,
+            weekdays: weekday_set,
+            // Like classic cron, `*` and `*/n` don't count as restrictions
+            days_restricted: !days.starts_with('*'),
+            weekdays_restricted: !weekdays.starts_with('*'),
+        })
+    }
+
+    /// First matching minute strictly after `after` (Unix seconds)
+    pub fn next_after(&self, after: u64) -> Option<u64> {
+        let mut t = (after / MINUTE + 1) * MINUTE;
+        let limit = after + SEARCH_LIMIT;
+
+        while t <= limit {
+            let (_, month, day) = civil_from_days(t / DAY);
+            let weekday = ((t / DAY + 4) % 7) as usize; // 1970-01-01 was a Thursday
+
+            let day_matches = match (self.days_restricted, self.weekdays_restricted) {
+                (true, true) => self.days[day as usize] || self.weekdays[weekday],
+                _ => self.days[day as usize] && self.weekdays[weekday],
+            };
+            if !self.months[month as usize] || !day_matches {
+                t = (t / DAY + 1) * DAY;
+                continue;
+            }
+
+            let hour = (t % DAY / 3600) as usize;
+            if !self.hours[hour] {
+                t = (t / 3600 + 1) * 3600;
+                continue;
+            }
+
+            if self.minutes[(t % 3600 / MINUTE) as usize] {
+                return Some(t);
+            }
+            t += MINUTE;
+        }
+
+        None
+    }
+}
+
+/// Values allowed by one field, indexed by value (so `len() == max + 1`)
+fn field(spec: &str, min: u32, max: u32) -> Result<Vec<bool>> {
+    let mut allowed = vec![false; max as usize + 1];
+
+    for part in spec.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().context("bad step")?),
+            None => (part, 1),
+        };
+        if step == 0 {
+            bail!("step must be positive");
+        }
+
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((a, b)) => (a.parse()?, b.parse()?),
+                None => {
+                    let value: u32 = range
+                        .parse()
+                        .with_context(|| format!("bad value {range:?}"))?;
+                    // `5/10` means every 10 starting at 5
+                    (value, if part.contains('/') { max } else { value })
+                }
+            },
+        };
+        if start < min || end > max || start > end {
+            bail!("{range:?} is outside {min}-{max}");
+        }
+
+        for value in (start..=end).step_by(step as usize) {
+            allowed[value as usize] = true;
+        }
+    }
+
+    Ok(allowed)
+}
+
+/// Proleptic Gregorian (year, month, day) of a count of days since 1970-01-01
+fn civil_from_days(days: u64) -> (i64, u32, u32) {
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// `YYYY-MM-DD HH:MM UTC` for log lines
+pub fn format_time(t: u64) -> String {
+    let (year, month, day) = civil_from_days(t / DAY);
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02} UTC",
+        t % DAY / 3600,
+        t % 3600 / MINUTE
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2024-03-01 00:00 UTC, a Friday
+    const MARCH_1: u64 = 1_709_251_200;
+
+    fn next(expr: &str, after: u64) -> String {
+        let cron: Cron = expr.parse().unwrap();
+        format_time(cron.next_after(after).unwrap())
+    }
+
+    #[test]
+    fn test_next_after() {
+        assert_eq!(format_time(MARCH_1), "2024-03-01 00:00 UTC");
+        assert_eq!(next("*/15 * * * *", MARCH_1), "2024-03-01 00:15 UTC");
+        assert_eq!(next("0 */6 * * *", MARCH_1 + 60), "2024-03-01 06:00 UTC");
+        assert_eq!(next("30 9 * * 1-5", MARCH_1), "2024-03-01 09:30 UTC");
+        // Saturday and Sunday are skipped
+        assert_eq!(next("30 9 * * 1-5", MARCH_1 + DAY), "2024-03-04 09:30 UTC");
+        assert_eq!(next("0 0 29 2 *", MARCH_1), "2028-02-29 00:00 UTC");
+        assert_eq!(next("@weekly", MARCH_1), "2024-03-03 00:00 UTC");
+        // Day of month OR day of week when both are restricted
+        assert_eq!(next("0 12 15 * 7", MARCH_1), "2024-03-03 12:00 UTC");
+    }
+
+    #[test]
+    fn test_invalid_expressions() {
+        for expr in [
+            "* * * *",
+            "60 * * * *",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "a * * * *",
+        ] {
+            assert!(expr.parse::<Cron>().is_err(), "{expr}");
+        }
+    }
+}
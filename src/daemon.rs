@@ -0,0 +1,257 @@
+//! Long-running scheduler (`chore-bot daemon`)
+//!
+//! Runs each command from a sweep manifest on its own cron schedule, plus a
+//! random delay of up to `jitter_secs` so many daemons don't hit the API at
+//! the same instant. Commands run one tick at a time, so runs never overlap:
+//! a command whose time passed while a slow run was in progress runs once,
+//! late, however many of its ticks were missed. On shutdown the run in
+//! progress finishes before the daemon exits.
+
+use crate::cron::{self, Cron};
+use crate::state;
+use crate::sweep::Step;
+use anyhow::{bail, Result};
+use std::future::Future;
+use std::time::Duration;
+
+/// Source of the current time, replaceable in tests
+pub trait Clock {
+    /// Current Unix time in seconds
+    fn now(&self) -> u64;
+
+    /// Wait until the given Unix time
+    fn sleep_until(&self, when: u64) -> impl Future<Output = ()>;
+}
+
+/// Wall-clock time on the tokio timer
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        state::unix_now()
+    }
+
+    async fn sleep_until(&self, when: u64) {
+        let wait = when.saturating_sub(self.now());
+        tokio::time::sleep(Duration::from_secs(wait)).await;
+    }
+}
+
+/// A command on a cron schedule
+#[derive(Debug)]
+struct Job {
+    step: Step,
+    cron: Cron,
+    /// Unix time of the next run, jitter included
+    next: u64,
+}
+
+/// When each scheduled command is next due
+#[derive(Debug)]
+pub struct Scheduler {
+    jobs: Vec<Job>,
+    jitter_secs: u64,
+    /// xorshift state for the jitter
+    rng: u64,
+}
+
+impl Scheduler {
+    /// Schedule every command's first run after `now`
+    pub fn new(
+        schedules: Vec<(Step, Cron)>,
+        jitter_secs: u64,
+        seed: u64,
+        now: u64,
+    ) -> Result<Self> {
+        if schedules.is_empty() {
+            bail!("No [schedules] in the manifest");
+        }
+
+        let mut scheduler = Self {
+            jobs: Vec::new(),
+            jitter_secs,
+            rng: seed | 1,
+        };
+        for (step, cron) in schedules {
+            let Some(next) = cron.next_after(now) else {
+                bail!("Schedule {cron:?} for {step} never fires");
+            };
+            let next = next + scheduler.jitter();
+            scheduler.jobs.push(Job { step, cron, next });
+        }
+
+        Ok(scheduler)
+    }
+
+    /// When the next command is due
+    pub fn next_due(&self) -> Option<u64> {
+        self.jobs.iter().map(|job| job.next).min()
+    }
+
+    /// Commands due at `now`, in manifest order, each rescheduled after `now`
+    pub fn take_due(&mut self, now: u64) -> Vec<Step> {
+        let mut due = Vec::new();
+        for i in 0..self.jobs.len() {
+            if self.jobs[i].next > now {
+                continue;
+            }
+            due.push(self.jobs[i].step.clone());
+            let jitter = self.jitter();
+            let job = &mut self.jobs[i];
+            // A schedule that stops matching just stops running
+            job.next = job.cron.next_after(now).map_or(u64::MAX, |t| t + jitter);
+        }
+        due
+    }
+
+    fn jitter(&mut self) -> u64 {
+        if self.jitter_secs == 0 {
+            return 0;
+        }
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng % (self.jitter_secs + 1)
+    }
+}
+
+/// Run due commands with `tick` until `shutdown` resolves
+pub async fn run<C: Clock>(
+    clock: &C,
+    scheduler: &mut Scheduler,
+    shutdown: impl Future<Output = ()>,
+    mut tick: impl FnMut(&[Step]) -> Result<()>,
+) {
+    tokio::pin!(shutdown);
+
+    while let Some(when) = scheduler.next_due().filter(|&t| t != u64::MAX) {
+        println!("💤 Next run at {}", cron::format_time(when));
+        tokio::select! {
+            biased;
+            _ = &mut shutdown => {
+                println!("👋 Shutting down");
+                return;
+            }
+            _ = clock.sleep_until(when) => {}
+        }
+
+        let now = clock.now();
+        let steps = scheduler.take_due(now);
+        if steps.is_empty() {
+            continue;
+        }
+
+        let names: Vec<String> = steps.iter().map(|s| s.to_string()).collect();
+        println!("\n⏰ {}: {}\n", cron::format_time(now), names.join(", "));
+        if let Err(e) = tick(&steps) {
+            println!("❌ {e:#}");
+        }
+    }
+
+    println!("No schedules left to run");
+}
+
+/// Resolves on Ctrl-C, or SIGTERM on Unix
+pub async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut term) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = term.recv() => {}
+                }
+                return;
+            }
+            Err(e) => println!("⚠️  Cannot listen for SIGTERM: {e}"),
+        }
+    }
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    /// 2024-03-01 00:00 UTC
+    const START: u64 = 1_709_251_200;
+
+    /// Clock that jumps straight to whatever time is slept until
+    struct MockClock(Cell<u64>);
+
+    impl Clock for MockClock {
+        fn now(&self) -> u64 {
+            self.0.get()
+        }
+
+        async fn sleep_until(&self, when: u64) {
+            self.0.set(self.0.get().max(when));
+        }
+    }
+
+    fn schedules() -> Vec<(Step, Cron)> {
+        vec![
+            (Step::Approve, "*/30 * * * *".parse().unwrap()),
+            (Step::Workflow("test".into()), "0 * * * *".parse().unwrap()),
+        ]
+    }
+
+    #[tokio::test]
+    async fn test_runs_each_command_on_its_schedule() {
+        let clock = MockClock(Cell::new(START));
+        let mut scheduler = Scheduler::new(schedules(), 0, 1, START).unwrap();
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let mut stop = Some(stop);
+        let mut ticks = Vec::new();
+
+        run(
+            &clock,
+            &mut scheduler,
+            async {
+                let _ = stopped.await;
+            },
+            |steps| {
+                let names: Vec<String> = steps.iter().map(|s| s.to_string()).collect();
+                ticks.push(format!(
+                    "{} {}",
+                    cron::format_time(clock.now()),
+                    names.join(",")
+                ));
+                // A slow run until 02:50: three missed ticks become one late run
+                if ticks.len() == 2 {
+                    clock.0.set(clock.0.get() + 110 * 60);
+                }
+                if ticks.len() == 3 {
+                    stop.take().unwrap().send(()).unwrap();
+                }
+                Ok(())
+            },
+        )
+        .await;
+
+        assert_eq!(
+            ticks,
+            [
+                "2024-03-01 00:30 UTC approve",
+                "2024-03-01 01:00 UTC approve,test",
+                "2024-03-01 02:50 UTC approve,test",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_jitter_stays_in_bounds() {
+        let mut scheduler = Scheduler::new(schedules(), 300, 42, START).unwrap();
+        for _ in 0..50 {
+            let due = scheduler.next_due().unwrap();
+            let now = due;
+            let steps = scheduler.take_due(now);
+            assert!(!steps.is_empty());
+            // Every jittered time lands at most 5 minutes after a cron time
+            assert!(scheduler.jobs.iter().all(|job| job.next % 1800 <= 300));
+        }
+        assert!(Scheduler::new(Vec::new(), 0, 1, START).is_err());
+    }
+}
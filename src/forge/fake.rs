@@ -0,0 +1,378 @@
+//! In-memory forge for deterministic tests
+//!
+//! Seeded from a JSON fixture describing issues, PRs and workflow runs. Every
+//! mutation the bot attempts is recorded so tests can assert on it.
+
+use super::{
+    AgentTask, AgentTaskResult, AgentTaskState, CheckRun, ClosingIssue, Forge, Issue, MergeMethod,
+    MergeState, PullRequest, ReviewDecision, WorkflowRun,
+};
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// A mutation attempted against the fake forge
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mutation {
+    Comment { pr: u32, body: String },
+    ClosePr { pr: u32 },
+    Merge { pr: u32, method: MergeMethod },
+    AddLabel { pr: u32, label: String },
+    Rerun { run_id: u64 },
+    SpawnAgent { task: String },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct FixtureIssue {
+    number: u32,
+    title: String,
+    #[serde(default)]
+    body: String,
+    #[serde(default)]
+    labels: Vec<String>,
+    #[serde(default)]
+    created_at: Option<String>,
+    #[serde(default)]
+    reactions: u32,
+    #[serde(default)]
+    milestone: Option<String>,
+}
+
+impl FixtureIssue {
+    fn to_issue(&self) -> Issue {
+        Issue {
+            number: self.number,
+            title: self.title.clone(),
+            body: self.body.clone(),
+            labels: self.labels.clone(),
+            created_at: self.created_at.clone(),
+            reactions: self.reactions,
+            milestone: self.milestone.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct FixtureCheck {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    conclusion: Option<String>,
+    #[serde(default)]
+    job_id: Option<u64>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct FixturePr {
+    number: u32,
+    title: String,
+    #[serde(default)]
+    body: String,
+    #[serde(default)]
+    author: String,
+    #[serde(default)]
+    head_sha: String,
+    #[serde(default)]
+    head_ref: String,
+    #[serde(default)]
+    base_ref: String,
+    #[serde(default)]
+    mergeable: Option<String>,
+    #[serde(default)]
+    checks: Vec<FixtureCheck>,
+    #[serde(default)]
+    files: Vec<String>,
+    /// Existing comment bodies; comments posted during the test are appended
+    #[serde(default)]
+    comments: Vec<String>,
+    /// Issues in the fixture's repository the PR is linked to as closing
+    #[serde(default)]
+    closing_issues: Vec<u32>,
+    /// `APPROVED`, `CHANGES_REQUESTED` or `REVIEW_REQUIRED`
+    #[serde(default)]
+    review_decision: Option<String>,
+    #[serde(default)]
+    approvals: u32,
+    #[serde(default)]
+    truncated: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct FixtureRun {
+    id: u64,
+    #[serde(default)]
+    conclusion: Option<String>,
+    #[serde(default)]
+    actor: String,
+    #[serde(default)]
+    head_branch: String,
+    #[serde(default)]
+    head_sha: String,
+    #[serde(default)]
+    path: String,
+    #[serde(default)]
+    pr: Option<u32>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct FixtureTask {
+    id: String,
+    state: String,
+    /// Defaults to the fixture's repository
+    #[serde(default)]
+    repo: Option<String>,
+    #[serde(default)]
+    pr_number: Option<u32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Fixture {
+    #[serde(default = "default_repo")]
+    repo: String,
+    #[serde(default)]
+    issues: Vec<FixtureIssue>,
+    #[serde(default)]
+    prs: Vec<FixturePr>,
+    #[serde(default)]
+    workflow_runs: Vec<FixtureRun>,
+    #[serde(default)]
+    agent_tasks: Vec<FixtureTask>,
+    /// Make `list_agent_tasks` fail, as when the forge can't list them
+    #[serde(default)]
+    agent_tasks_unavailable: bool,
+    /// Actions job logs by job id
+    #[serde(default)]
+    job_logs: HashMap<u64, String>,
+}
+
+fn default_repo() -> String {
+    "acme/widgets".to_string()
+}
+
+/// [`Forge`] implementation backed by in-memory fixture data
+pub struct FakeForge {
+    state: Mutex<Fixture>,
+    mutations: Mutex<Vec<Mutation>>,
+}
+
+impl FakeForge {
+    pub fn from_json(json: &str) -> Result<Self> {
+        let fixture: Fixture = serde_json::from_str(json).context("Failed to parse fixture")?;
+        Ok(Self {
+            state: Mutex::new(fixture),
+            mutations: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// All mutations recorded so far, in order
+    pub fn mutations(&self) -> Vec<Mutation> {
+        self.mutations.lock().unwrap().clone()
+    }
+
+    /// Task descriptions of every spawned agent, in order
+    pub fn spawned_tasks(&self) -> Vec<String> {
+        self.mutations()
+            .into_iter()
+            .filter_map(|m| match m {
+                Mutation::SpawnAgent { task } => Some(task),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn record(&self, mutation: Mutation) {
+        self.mutations.lock().unwrap().push(mutation);
+    }
+}
+
+impl Forge for FakeForge {
+    fn repo_name(&self) -> Result<String> {
+        Ok(self.state.lock().unwrap().repo.clone())
+    }
+
+    fn fetch_issue(&self, number: u32) -> Result<Option<Issue>> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .issues
+            .iter()
+            .find(|i| i.number == number)
+            .map(FixtureIssue::to_issue))
+    }
+
+    fn list_issues_by_label(&self, label: &str) -> Result<Vec<Issue>> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .issues
+            .iter()
+            .filter(|i| i.labels.iter().any(|l| l == label))
+            .map(FixtureIssue::to_issue)
+            .collect())
+    }
+
+    fn list_open_prs(&self) -> Result<Vec<PullRequest>> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .prs
+            .iter()
+            .map(|pr| PullRequest {
+                number: pr.number,
+                title: pr.title.clone(),
+                body: pr.body.clone(),
+                author: pr.author.clone(),
+                head_sha: pr.head_sha.clone(),
+                head_ref: pr.head_ref.clone(),
+                base_ref: pr.base_ref.clone(),
+                closing_issues: pr
+                    .closing_issues
+                    .iter()
+                    .map(|&number| ClosingIssue {
+                        repo: state.repo.clone(),
+                        number,
+                    })
+                    .collect(),
+                mergeable: match pr.mergeable.as_deref() {
+                    Some("MERGEABLE") => MergeState::Mergeable,
+                    Some("CONFLICTING") => MergeState::Conflicting,
+                    _ => MergeState::Unknown,
+                },
+                checks: pr
+                    .checks
+                    .iter()
+                    .map(|c| CheckRun {
+                        name: c.name.clone(),
+                        conclusion: c.conclusion.clone(),
+                        job_id: c.job_id,
+                    })
+                    .collect(),
+                files: pr.files.clone(),
+                review_decision: pr
+                    .review_decision
+                    .as_deref()
+                    .map_or(ReviewDecision::Unknown, ReviewDecision::parse),
+                approvals: pr.approvals,
+                truncated: pr.truncated,
+            })
+            .collect())
+    }
+
+    fn comment_on_pr(&self, number: u32, body: &str) -> Result<bool> {
+        self.record(Mutation::Comment {
+            pr: number,
+            body: body.to_string(),
+        });
+        let mut state = self.state.lock().unwrap();
+        match state.prs.iter_mut().find(|pr| pr.number == number) {
+            Some(pr) => {
+                pr.comments.push(body.to_string());
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn list_pr_comments(&self, number: u32) -> Result<Vec<String>> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .prs
+            .iter()
+            .find(|pr| pr.number == number)
+            .map(|pr| pr.comments.clone())
+            .unwrap_or_default())
+    }
+
+    fn add_label(&self, number: u32, label: &str) -> Result<bool> {
+        self.record(Mutation::AddLabel {
+            pr: number,
+            label: label.to_string(),
+        });
+        let state = self.state.lock().unwrap();
+        Ok(state.prs.iter().any(|pr| pr.number == number))
+    }
+
+    fn close_pr(&self, number: u32) -> Result<bool> {
+        self.record(Mutation::ClosePr { pr: number });
+        let mut state = self.state.lock().unwrap();
+        let before = state.prs.len();
+        state.prs.retain(|pr| pr.number != number);
+        Ok(state.prs.len() < before)
+    }
+
+    fn merge_pr(&self, number: u32, method: MergeMethod, head_sha: &str) -> Result<bool> {
+        self.record(Mutation::Merge { pr: number, method });
+        let mut state = self.state.lock().unwrap();
+        let before = state.prs.len();
+        state
+            .prs
+            .retain(|pr| pr.number != number || pr.head_sha != head_sha);
+        Ok(state.prs.len() < before)
+    }
+
+    fn list_workflow_runs(&self, status: &str) -> Result<Vec<WorkflowRun>> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .workflow_runs
+            .iter()
+            .filter(|run| run.conclusion.as_deref() == Some(status))
+            .map(|run| WorkflowRun {
+                id: run.id,
+                actor: run.actor.clone(),
+                head_branch: run.head_branch.clone(),
+                head_sha: run.head_sha.clone(),
+                workflow_path: run.path.clone(),
+                pr: run.pr,
+            })
+            .collect())
+    }
+
+    fn fetch_job_log(&self, job_id: u64) -> Result<Option<String>> {
+        let state = self.state.lock().unwrap();
+        Ok(state.job_logs.get(&job_id).cloned())
+    }
+
+    fn rerun_workflow(&self, run_id: u64) -> Result<bool> {
+        self.record(Mutation::Rerun { run_id });
+        let state = self.state.lock().unwrap();
+        Ok(state.workflow_runs.iter().any(|run| run.id == run_id))
+    }
+
+    fn spawn_agent(&self, task: &str) -> Result<AgentTaskResult> {
+        self.record(Mutation::SpawnAgent {
+            task: task.to_string(),
+        });
+        Ok(AgentTaskResult {
+            success: true,
+            message: "spawned".to_string(),
+            task_id: Some(format!("task-{}", self.spawned_tasks().len())),
+        })
+    }
+
+    fn list_agent_tasks(&self) -> Result<Vec<AgentTask>> {
+        let state = self.state.lock().unwrap();
+        if state.agent_tasks_unavailable {
+            bail!("agent tasks are unavailable");
+        }
+        let mut tasks: Vec<AgentTask> = state
+            .agent_tasks
+            .iter()
+            .map(|task| AgentTask {
+                id: task.id.clone(),
+                state: AgentTaskState::parse(&task.state),
+                repo: Some(task.repo.clone().unwrap_or_else(|| state.repo.clone())),
+                pr_number: task.pr_number,
+            })
+            .collect();
+        drop(state);
+
+        // Tasks spawned during the test are still running
+        let spawned = self.spawned_tasks().len();
+        tasks.extend((1..=spawned).map(|n| AgentTask {
+            id: format!("task-{n}"),
+            state: AgentTaskState::Running,
+            repo: Some(self.repo_name().unwrap_or_default()),
+            pr_number: None,
+        }));
+
+        Ok(tasks)
+    }
+}
@@ -0,0 +1,559 @@
+//! Forge backed by the GitHub CLI (`gh`)
+//!
+//! Every call shells out to `gh` in the repository directory, so `gh` must be
+//! installed and authenticated.
+
+use super::{
+    actions_job_id, AgentTask, AgentTaskResult, AgentTaskState, CheckRun, ClosingIssue, Forge,
+    Issue, MergeMethod, MergeState, PullRequest, ReviewDecision, WorkflowRun,
+};
+use anyhow::{bail, Result};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::OnceLock;
+
+/// [`Forge`] implementation that shells out to `gh`
+pub struct GhCliForge {
+    repo_path: PathBuf,
+    repo_name: OnceLock<String>,
+}
+
+impl GhCliForge {
+    pub fn new(repo_path: &Path) -> Self {
+        Self {
+            repo_path: repo_path.to_path_buf(),
+            repo_name: OnceLock::new(),
+        }
+    }
+
+    fn gh(&self, args: &[&str]) -> Result<std::process::Output> {
+        Ok(Command::new("gh")
+            .args(args)
+            .current_dir(&self.repo_path)
+            .output()?)
+    }
+}
+
+impl Forge for GhCliForge {
+    fn repo_name(&self) -> Result<String> {
+        if let Some(name) = self.repo_name.get() {
+            return Ok(name.clone());
+        }
+
+        let output = self.gh(&[
+            "repo",
+            "view",
+            "--json",
+            "nameWithOwner",
+            "--jq",
+            ".nameWithOwner",
+        ])?;
+        let name = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        if !output.status.success() || name.is_empty() {
+            bail!(
+                "Failed to resolve repository: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+
+        Ok(self.repo_name.get_or_init(|| name).clone())
+    }
+
+    fn fetch_issue(&self, number: u32) -> Result<Option<Issue>> {
+        let output = self.gh(&["issue", "view", &number.to_string(), "--json", ISSUE_FIELDS])?;
+
+        if !output.status.success() {
+            return Ok(None);
+        }
+
+        let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap_or_default();
+
+        Ok(Some(parse_issue(&json)))
+    }
+
+    fn list_issues_by_label(&self, label: &str) -> Result<Vec<Issue>> {
+        let output = self.gh(&[
+            "issue",
+            "list",
+            "--label",
+            label,
+            "--state",
+            "open",
+            "--limit",
+            "150",
+            "--json",
+            ISSUE_FIELDS,
+        ])?;
+
+        if !output.status.success() {
+            bail!(
+                "Failed to list issues labelled {label:?}: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+
+        let json: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+        let issues = json
+            .as_array()
+            .map(|issues| issues.iter().map(parse_issue).collect())
+            .unwrap_or_default();
+
+        Ok(issues)
+    }
+
+    fn list_open_prs(&self) -> Result<Vec<PullRequest>> {
+        let output = self.gh(&[
+            "pr",
+            "list",
+            "--state",
+            "open",
+            "--limit",
+            "150",
+            "--json",
+            "number,title,body,author,headRefOid,headRefName,baseRefName,mergeable,statusCheckRollup,files,closingIssuesReferences,reviewDecision,latestReviews",
+        ])?;
+
+        // Budgets, dedup and scheduling all read this; an empty list on
+        // failure would look like no agent work in flight
+        if !output.status.success() {
+            bail!(
+                "Failed to list open PRs: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+
+        let json: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+        let prs = json
+            .as_array()
+            .map(|prs| prs.iter().map(parse_pr).collect())
+            .unwrap_or_default();
+
+        Ok(prs)
+    }
+
+    fn comment_on_pr(&self, number: u32, body: &str) -> Result<bool> {
+        let output = self.gh(&["pr", "comment", &number.to_string(), "--body", body])?;
+        Ok(output.status.success())
+    }
+
+    fn list_pr_comments(&self, number: u32) -> Result<Vec<String>> {
+        let output = self.gh(&["pr", "view", &number.to_string(), "--json", "comments"])?;
+
+        if !output.status.success() {
+            bail!(
+                "Failed to list comments on PR #{number}: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+
+        let json: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+        Ok(json
+            .get("comments")
+            .and_then(|c| c.as_array())
+            .map(|comments| {
+                comments
+                    .iter()
+                    .filter_map(|c| str_field(c, "body"))
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    fn add_label(&self, number: u32, label: &str) -> Result<bool> {
+        let output = self.gh(&["pr", "edit", &number.to_string(), "--add-label", label])?;
+        Ok(output.status.success())
+    }
+
+    fn close_pr(&self, number: u32) -> Result<bool> {
+        let output = self.gh(&["pr", "close", &number.to_string()])?;
+        Ok(output.status.success())
+    }
+
+    fn merge_pr(&self, number: u32, method: MergeMethod, head_sha: &str) -> Result<bool> {
+        let output = self.gh(&[
+            "pr",
+            "merge",
+            &number.to_string(),
+            &format!("--{}", method.as_str()),
+            "--match-head-commit",
+            head_sha,
+        ])?;
+        Ok(output.status.success())
+    }
+
+    fn list_workflow_runs(&self, status: &str) -> Result<Vec<WorkflowRun>> {
+        // `gh run list` doesn't report actors or workflow files
+        let output = self.gh(&[
+            "api",
+            &format!("repos/{{owner}}/{{repo}}/actions/runs?status={status}&per_page=100"),
+        ])?;
+
+        // An empty list would read as nothing waiting for approval
+        if !output.status.success() {
+            bail!(
+                "Failed to list {status} workflow runs: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+
+        let json: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+        let runs = json["workflow_runs"]
+            .as_array()
+            .map(|runs| runs.iter().filter_map(parse_run).collect())
+            .unwrap_or_default();
+
+        Ok(runs)
+    }
+
+    fn fetch_job_log(&self, job_id: u64) -> Result<Option<String>> {
+        let output = self.gh(&[
+            "api",
+            &format!("repos/{{owner}}/{{repo}}/actions/jobs/{job_id}/logs"),
+        ])?;
+
+        // Logs expire, and non-Actions checks have none
+        Ok(output
+            .status
+            .success()
+            .then(|| String::from_utf8_lossy(&output.stdout).into_owned()))
+    }
+
+    fn rerun_workflow(&self, run_id: u64) -> Result<bool> {
+        // Use API rerun endpoint - `gh run approve` only works for fork PRs,
+        // this also works for Copilot actor runs
+        let output = self.gh(&[
+            "api",
+            &format!("repos/{{owner}}/{{repo}}/actions/runs/{run_id}/rerun"),
+            "--method",
+            "POST",
+        ])?;
+        Ok(output.status.success())
+    }
+
+    fn spawn_agent(&self, task: &str) -> Result<AgentTaskResult> {
+        let output = self.gh(&["agent-task", "create", task])?;
+        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+
+        Ok(AgentTaskResult {
+            success: output.status.success(),
+            task_id: task_id_from_output(&stdout),
+            message: if output.status.success() {
+                stdout
+            } else {
+                String::from_utf8_lossy(&output.stderr).to_string()
+            },
+        })
+    }
+
+    fn list_agent_tasks(&self) -> Result<Vec<AgentTask>> {
+        let output = self.gh(&[
+            "agent-task",
+            "list",
+            "--limit",
+            "100",
+            "--json",
+            "id,state,repository,pullRequestNumber",
+        ])?;
+
+        if !output.status.success() {
+            bail!(
+                "Failed to list agent tasks: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+
+        let json: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+        Ok(json
+            .as_array()
+            .map(|tasks| tasks.iter().filter_map(Self::parse_agent_task).collect())
+            .unwrap_or_default())
+    }
+}
+
+impl GhCliForge {
+    fn parse_agent_task(task: &serde_json::Value) -> Option<AgentTask> {
+        let repo = task.get("repository").and_then(|r| {
+            r.as_str()
+                .or_else(|| r.get("nameWithOwner").and_then(|n| n.as_str()))
+                .map(|s| s.to_string())
+        });
+
+        Some(AgentTask {
+            id: str_field(task, "id")?,
+            state: AgentTaskState::parse(task.get("state")?.as_str()?),
+            repo,
+            pr_number: task
+                .get("pullRequestNumber")
+                .and_then(|n| n.as_u64())
+                .map(|n| n as u32),
+        })
+    }
+}
+
+/// `gh agent-task create` prints the session URL; its last segment is the task id
+fn task_id_from_output(stdout: &str) -> Option<String> {
+    let re = regex::Regex::new(r"https://\S+").unwrap();
+    let url = re.find_iter(stdout).last()?.as_str();
+    url.trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .map(|s| s.to_string())
+}
+
+/// Read a string field, treating empty strings as missing
+fn str_field(value: &serde_json::Value, key: &str) -> Option<String> {
+    value
+        .get(key)
+        .and_then(|v| v.as_str())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string())
+}
+
+/// `--json` fields read into an [`Issue`]
+/// gh lists at most this many files or checks per PR
+const LISTED_PER_PR: usize = 100;
+
+const ISSUE_FIELDS: &str = "number,title,body,labels,createdAt,reactionGroups,milestone";
+
+fn parse_issue(issue: &serde_json::Value) -> Issue {
+    Issue {
+        number: issue.get("number").and_then(|n| n.as_u64()).unwrap_or(0) as u32,
+        title: str_field(issue, "title").unwrap_or_default(),
+        body: str_field(issue, "body").unwrap_or_default(),
+        labels: issue
+            .get("labels")
+            .and_then(|l| l.as_array())
+            .map(|labels| {
+                labels
+                    .iter()
+                    .filter_map(|l| l.get("name").and_then(|n| n.as_str()))
+                    .map(|n| n.to_string())
+                    .collect()
+            })
+            .unwrap_or_default(),
+        created_at: str_field(issue, "createdAt"),
+        reactions: issue
+            .get("reactionGroups")
+            .and_then(|g| g.as_array())
+            .map(|groups| {
+                groups
+                    .iter()
+                    .filter_map(|g| g.pointer("/users/totalCount").and_then(|n| n.as_u64()))
+                    .sum::<u64>() as u32
+            })
+            .unwrap_or(0),
+        milestone: issue.get("milestone").and_then(|m| str_field(m, "title")),
+    }
+}
+
+/// Parse a workflow run from the REST API
+fn parse_run(run: &serde_json::Value) -> Option<WorkflowRun> {
+    Some(WorkflowRun {
+        id: run["id"].as_u64()?,
+        actor: str_field(&run["actor"], "login").unwrap_or_default(),
+        head_branch: str_field(run, "head_branch").unwrap_or_default(),
+        head_sha: str_field(run, "head_sha").unwrap_or_default(),
+        workflow_path: str_field(run, "path").unwrap_or_default(),
+        pr: run["pull_requests"][0]["number"]
+            .as_u64()
+            .and_then(|n| u32::try_from(n).ok()),
+    })
+}
+
+fn parse_pr(pr: &serde_json::Value) -> PullRequest {
+    let mergeable = match pr.get("mergeable").and_then(|m| m.as_str()) {
+        Some("MERGEABLE") => MergeState::Mergeable,
+        Some("CONFLICTING") => MergeState::Conflicting,
+        _ => MergeState::Unknown,
+    };
+
+    let checks: Vec<CheckRun> = pr
+        .get("statusCheckRollup")
+        .and_then(|v| v.as_array())
+        .map(|checks| {
+            checks
+                .iter()
+                .map(|check| CheckRun {
+                    name: str_field(check, "name")
+                        .or_else(|| str_field(check, "context"))
+                        .unwrap_or_default(),
+                    // Commit statuses report a state instead
+                    conclusion: str_field(check, "conclusion")
+                        .or_else(|| str_field(check, "state")),
+                    job_id: str_field(check, "detailsUrl").and_then(|url| actions_job_id(&url)),
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let files: Vec<String> = pr
+        .get("files")
+        .and_then(|v| v.as_array())
+        .map(|files| files.iter().filter_map(|f| str_field(f, "path")).collect())
+        .unwrap_or_default();
+
+    let closing_issues = pr
+        .get("closingIssuesReferences")
+        .and_then(|v| v.as_array())
+        .map(|issues| {
+            issues
+                .iter()
+                .filter_map(|issue| {
+                    let repo = &issue["repository"];
+                    Some(ClosingIssue {
+                        repo: format!(
+                            "{}/{}",
+                            repo["owner"]["login"].as_str()?,
+                            repo["name"].as_str()?
+                        ),
+                        number: u32::try_from(issue["number"].as_u64()?).ok()?,
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let approvals = pr
+        .get("latestReviews")
+        .and_then(|v| v.as_array())
+        .map(|reviews| {
+            reviews
+                .iter()
+                .filter(|review| review["state"] == "APPROVED")
+                .count() as u32
+        })
+        .unwrap_or(0);
+
+    // gh doesn't say whether it cut a list short, only where it would
+    let truncated = files.len() >= LISTED_PER_PR || checks.len() >= LISTED_PER_PR;
+
+    PullRequest {
+        number: pr.get("number").and_then(|n| n.as_u64()).unwrap_or(0) as u32,
+        title: str_field(pr, "title").unwrap_or_default(),
+        body: str_field(pr, "body").unwrap_or_default(),
+        author: pr
+            .get("author")
+            .and_then(|a| a.get("login"))
+            .and_then(|l| l.as_str())
+            .unwrap_or("")
+            .to_string(),
+        head_sha: str_field(pr, "headRefOid").unwrap_or_default(),
+        head_ref: str_field(pr, "headRefName").unwrap_or_default(),
+        base_ref: str_field(pr, "baseRefName").unwrap_or_default(),
+        mergeable,
+        checks,
+        files,
+        closing_issues,
+        review_decision: str_field(pr, "reviewDecision")
+            .map_or(ReviewDecision::Unknown, |d| ReviewDecision::parse(&d)),
+        approvals,
+        truncated,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_pr_reads_rollup_and_mergeable() {
+        let json = serde_json::json!({
+            "number": 12,
+            "title": "test: Add tests",
+            "body": "closes #4",
+            "author": { "login": "Copilot" },
+            "headRefOid": "0d1a26e",
+            "headRefName": "copilot/add-tests",
+            "baseRefName": "main",
+            "mergeable": "CONFLICTING",
+            "statusCheckRollup": [
+                { "name": "build", "conclusion": "SUCCESS" },
+                { "context": "ci/lint", "state": "FAILURE" },
+                {
+                    "name": "test",
+                    "conclusion": "FAILURE",
+                    "detailsUrl": "https://github.com/acme/widgets/actions/runs/11/job/22"
+                }
+            ],
+            "files": [{ "path": "src/lib.rs", "additions": 3, "deletions": 1 }],
+            "closingIssuesReferences": [{
+                "id": "I_kw",
+                "number": 4,
+                "repository": { "id": "R_kg", "name": "widgets", "owner": { "id": "O_kg", "login": "acme" } },
+                "url": "https://github.com/acme/widgets/issues/4"
+            }],
+            "reviewDecision": "APPROVED",
+            "latestReviews": [
+                { "author": { "login": "alice" }, "state": "APPROVED" },
+                { "author": { "login": "bob" }, "state": "COMMENTED" }
+            ]
+        });
+
+        let pr = parse_pr(&json);
+        assert_eq!(pr.number, 12);
+        assert_eq!(pr.author, "Copilot");
+        assert_eq!(pr.head_sha, "0d1a26e");
+        assert_eq!(
+            (pr.head_ref.as_str(), pr.base_ref.as_str()),
+            ("copilot/add-tests", "main")
+        );
+        assert_eq!(pr.mergeable, MergeState::Conflicting);
+        assert_eq!(pr.checks.len(), 3);
+        assert!(pr.checks.iter().any(|c| c.is_failure()));
+        assert_eq!(pr.checks[1].name, "ci/lint");
+        assert!(pr.checks[1].is_failure());
+        assert!(pr.checks[0].is_success());
+        assert_eq!(
+            (pr.checks[2].name.as_str(), pr.checks[2].job_id),
+            ("test", Some(22))
+        );
+        assert_eq!(pr.files, vec!["src/lib.rs"]);
+        assert_eq!(
+            pr.closing_issues,
+            [ClosingIssue {
+                repo: "acme/widgets".to_string(),
+                number: 4
+            }]
+        );
+        assert_eq!(
+            (pr.review_decision, pr.approvals),
+            (ReviewDecision::Approved, 1)
+        );
+    }
+
+    #[test]
+    fn test_task_id_from_output() {
+        let stdout =
+            "Created task\nhttps://github.com/acme/widgets/pull/5/agent-sessions/abc-123\n";
+        assert_eq!(task_id_from_output(stdout).as_deref(), Some("abc-123"));
+        assert_eq!(task_id_from_output("no url"), None);
+    }
+
+    #[test]
+    fn test_parse_pr_defaults_missing_fields() {
+        let pr = parse_pr(&serde_json::json!({ "number": 3 }));
+        assert_eq!(pr.number, 3);
+        assert!(pr.title.is_empty());
+        assert_eq!(pr.mergeable, MergeState::Unknown);
+        assert!(pr.checks.is_empty());
+    }
+
+    #[test]
+    fn test_parse_run_reads_actor_workflow_and_pr() {
+        let json = serde_json::json!({
+            "id": 100,
+            "head_branch": "copilot/fix-hover",
+            "head_sha": "0d1a26e",
+            "path": ".github/workflows/ci.yml",
+            "actor": { "login": "Copilot", "type": "Bot" },
+            "pull_requests": [{ "number": 15, "head": { "ref": "copilot/fix-hover" } }]
+        });
+
+        let run = parse_run(&json).unwrap();
+        assert_eq!(run.actor, "Copilot");
+        assert_eq!(run.head_branch, "copilot/fix-hover");
+        assert_eq!(run.workflow_path, ".github/workflows/ci.yml");
+        assert_eq!(run.pr, Some(15));
+        assert_eq!(parse_run(&serde_json::json!({ "id": 7 })).unwrap().pr, None);
+    }
+}
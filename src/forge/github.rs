@@ -0,0 +1,954 @@
+//! Forge backed by the GitHub REST and GraphQL APIs
+//!
+//! Authenticates with a token (usually `GITHUB_TOKEN`) and does not need the
+//! `gh` binary. List endpoints follow `Link` headers / GraphQL cursors so
+//! results are never silently truncated.
+
+use super::{
+    actions_job_id, AgentTask, AgentTaskResult, AgentTaskState, CheckRun, ClosingIssue, Forge,
+    Issue, MergeMethod, MergeState, PullRequest, ReviewDecision, WorkflowRun,
+};
+use anyhow::{bail, Context, Result};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::path::Path;
+use std::process::Command;
+
+const DEFAULT_API_URL: &str = "https://api.github.com";
+const DEFAULT_COPILOT_URL: &str = "https://api.githubcopilot.com";
+
+/// [`Forge`] implementation that talks to the GitHub HTTP APIs directly
+pub struct GitHubForge {
+    agent: ureq::Agent,
+    api_url: String,
+    graphql_url: String,
+    copilot_url: String,
+    owner: String,
+    repo: String,
+    token: String,
+}
+
+impl GitHubForge {
+    pub fn new(owner: &str, repo: &str, token: &str) -> Self {
+        Self {
+            agent: ureq::AgentBuilder::new().build(),
+            api_url: DEFAULT_API_URL.to_string(),
+            graphql_url: format!("{DEFAULT_API_URL}/graphql"),
+            copilot_url: DEFAULT_COPILOT_URL.to_string(),
+            owner: owner.to_string(),
+            repo: repo.to_string(),
+            token: token.to_string(),
+        }
+    }
+
+    /// Point every endpoint (REST, GraphQL and Copilot) at `base_url`
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        let base_url = base_url.trim_end_matches('/');
+        self.api_url = base_url.to_string();
+        self.graphql_url = format!("{base_url}/graphql");
+        self.copilot_url = base_url.to_string();
+        self
+    }
+
+    /// Build a client from the environment
+    ///
+    /// The token comes from `GITHUB_TOKEN` (or `GH_TOKEN`). The repository is
+    /// `repo` (`owner/name`) when given, else the `origin` remote of
+    /// `repo_path`, or `GITHUB_REPOSITORY` when it has none. `GITHUB_API_URL`,
+    /// `GITHUB_GRAPHQL_URL` and `COPILOT_API_URL` override the endpoints.
+    pub fn from_env(repo_path: &Path, repo: Option<&str>) -> Result<Self> {
+        let token = std::env::var("GITHUB_TOKEN")
+            .or_else(|_| std::env::var("GH_TOKEN"))
+            .context("GITHUB_TOKEN is not set")?;
+
+        let (owner, repo) = match repo {
+            Some(slug) => split_slug(slug)?,
+            None => resolve_repo(
+                repo_path,
+                std::env::var("GITHUB_REPOSITORY").ok().as_deref(),
+            )?,
+        };
+
+        let mut forge = Self::new(&owner, &repo, &token);
+        if let Ok(url) = std::env::var("GITHUB_API_URL") {
+            forge = forge.with_base_url(&url);
+            forge.copilot_url = DEFAULT_COPILOT_URL.to_string();
+        }
+        if let Ok(url) = std::env::var("GITHUB_GRAPHQL_URL") {
+            forge.graphql_url = url;
+        }
+        if let Ok(url) = std::env::var("COPILOT_API_URL") {
+            forge.copilot_url = url;
+        }
+
+        Ok(forge)
+    }
+
+    fn repo_url(&self, path: &str) -> String {
+        format!("{}/repos/{}/{}{path}", self.api_url, self.owner, self.repo)
+    }
+
+    fn request(&self, method: &str, url: &str) -> ureq::Request {
+        self.agent
+            .request(method, url)
+            .set("Authorization", &format!("Bearer {}", self.token))
+            .set("Accept", "application/vnd.github+json")
+            .set("X-GitHub-Api-Version", "2022-11-28")
+            .set("User-Agent", "chore-bot")
+    }
+
+    /// GET every page of a list endpoint, following `Link: rel="next"`
+    fn get_pages<T: DeserializeOwned>(&self, url: &str, query: &[(&str, &str)]) -> Result<Vec<T>> {
+        let mut pages = Vec::new();
+
+        let mut request = self.request("GET", url).query("per_page", "100");
+        for (key, value) in query {
+            request = request.query(key, value);
+        }
+
+        loop {
+            let response = match request.call() {
+                Ok(response) => response,
+                Err(ureq::Error::Status(code, response)) => {
+                    bail!(
+                        "GitHub API returned {code} for {url}: {}",
+                        response.into_string().unwrap_or_default()
+                    )
+                }
+                Err(e) => return Err(e.into()),
+            };
+
+            let next = response.header("link").and_then(next_link);
+            pages.push(
+                response
+                    .into_json()
+                    .with_context(|| format!("Failed to parse response from {url}"))?,
+            );
+
+            match next {
+                Some(next) => request = self.request("GET", &next),
+                None => break,
+            }
+        }
+
+        Ok(pages)
+    }
+
+    /// Send a mutation, mapping HTTP error statuses to `Ok(false)`
+    fn send(&self, method: &str, url: &str, body: serde_json::Value) -> Result<bool> {
+        match self.request(method, url).send_json(body) {
+            Ok(_) => Ok(true),
+            Err(ureq::Error::Status(_, _)) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn graphql<T: DeserializeOwned>(&self, query: &str, variables: serde_json::Value) -> Result<T> {
+        let response: GraphQlResponse<T> = self
+            .request("POST", &self.graphql_url)
+            .send_json(serde_json::json!({ "query": query, "variables": variables }))
+            .context("GraphQL request failed")?
+            .into_json()
+            .context("Failed to parse GraphQL response")?;
+
+        if let Some(errors) = response.errors.filter(|e| !e.is_empty()) {
+            let messages: Vec<_> = errors.into_iter().map(|e| e.message).collect();
+            bail!("GraphQL errors: {}", messages.join("; "));
+        }
+
+        response.data.context("GraphQL response has no data")
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiLabel {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiReactions {
+    total_count: u32,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiMilestone {
+    title: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiIssue {
+    number: u32,
+    title: String,
+    #[serde(default)]
+    body: Option<String>,
+    #[serde(default)]
+    labels: Vec<ApiLabel>,
+    #[serde(default)]
+    created_at: Option<String>,
+    #[serde(default)]
+    reactions: Option<ApiReactions>,
+    #[serde(default)]
+    milestone: Option<ApiMilestone>,
+    /// Present when the "issue" is actually a pull request
+    #[serde(default)]
+    pull_request: Option<serde_json::Value>,
+}
+
+impl From<ApiIssue> for Issue {
+    fn from(issue: ApiIssue) -> Self {
+        Issue {
+            number: issue.number,
+            title: issue.title,
+            body: issue.body.unwrap_or_default(),
+            labels: issue.labels.into_iter().map(|l| l.name).collect(),
+            created_at: issue.created_at,
+            reactions: issue.reactions.map(|r| r.total_count).unwrap_or(0),
+            milestone: issue.milestone.map(|m| m.title),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiComment {
+    #[serde(default)]
+    body: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiRunsPage {
+    workflow_runs: Vec<ApiRun>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiRun {
+    id: u64,
+    actor: Option<ApiActor>,
+    #[serde(default)]
+    head_branch: Option<String>,
+    #[serde(default)]
+    head_sha: String,
+    #[serde(default)]
+    path: String,
+    #[serde(default)]
+    pull_requests: Vec<ApiRunPr>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiActor {
+    login: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiRunPr {
+    number: u32,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphQlResponse<T> {
+    data: Option<T>,
+    errors: Option<Vec<GraphQlError>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphQlError {
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct PrQueryData {
+    repository: PrQueryRepository,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PrQueryRepository {
+    pull_requests: Connection<GqlPullRequest>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Connection<T> {
+    #[serde(default)]
+    page_info: Option<PageInfo>,
+    nodes: Vec<T>,
+}
+
+impl<T> Connection<T> {
+    /// Whether there are more nodes than this page holds
+    fn is_truncated(&self) -> bool {
+        self.page_info
+            .as_ref()
+            .is_some_and(|page| page.has_next_page)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PageInfo {
+    has_next_page: bool,
+    end_cursor: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GqlPullRequest {
+    number: u32,
+    title: String,
+    #[serde(default)]
+    body: String,
+    #[serde(default)]
+    head_ref_oid: String,
+    #[serde(default)]
+    head_ref_name: String,
+    #[serde(default)]
+    base_ref_name: String,
+    #[serde(default)]
+    mergeable: Option<String>,
+    author: Option<GqlActor>,
+    commits: Connection<GqlCommitNode>,
+    #[serde(default)]
+    files: Option<Connection<GqlFile>>,
+    #[serde(default)]
+    closing_issues_references: Option<Connection<GqlIssueRef>>,
+    #[serde(default)]
+    review_decision: Option<String>,
+    #[serde(default)]
+    latest_reviews: Option<Connection<GqlReview>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GqlReview {
+    state: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GqlActor {
+    login: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GqlCommitNode {
+    commit: GqlCommit,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GqlCommit {
+    status_check_rollup: Option<GqlRollup>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GqlRollup {
+    contexts: Connection<GqlCheck>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GqlFile {
+    path: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GqlIssueRef {
+    number: u32,
+    repository: GqlRepository,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GqlRepository {
+    name_with_owner: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GqlCheck {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    conclusion: Option<String>,
+    #[serde(default)]
+    details_url: Option<String>,
+    /// Commit statuses have a context and state instead of a name and
+    /// conclusion
+    #[serde(default)]
+    context: Option<String>,
+    #[serde(default)]
+    state: Option<String>,
+}
+
+const OPEN_PRS_QUERY: &str = r#"
+query($owner: String!, $name: String!, $cursor: String) {
+  repository(owner: $owner, name: $name) {
+    pullRequests(states: OPEN, first: 50, after: $cursor) {
+      pageInfo { hasNextPage endCursor }
+      nodes {
+        number
+        title
+        body
+        headRefOid
+        headRefName
+        baseRefName
+        mergeable
+        reviewDecision
+        author { login }
+        files(first: 100) { pageInfo { hasNextPage } nodes { path } }
+        closingIssuesReferences(first: 25) {
+          pageInfo { hasNextPage }
+          nodes { number repository { nameWithOwner } }
+        }
+        latestReviews(first: 50) { pageInfo { hasNextPage } nodes { state } }
+        commits(last: 1) {
+          nodes {
+            commit {
+              statusCheckRollup {
+                contexts(first: 100) {
+                  pageInfo { hasNextPage }
+                  nodes {
+                    ... on CheckRun { name conclusion detailsUrl }
+                    ... on StatusContext { context state }
+                  }
+                }
+              }
+            }
+          }
+        }
+      }
+    }
+  }
+}"#;
+
+impl Forge for GitHubForge {
+    fn repo_name(&self) -> Result<String> {
+        Ok(format!("{}/{}", self.owner, self.repo))
+    }
+
+    fn fetch_issue(&self, number: u32) -> Result<Option<Issue>> {
+        let url = self.repo_url(&format!("/issues/{number}"));
+        let issue: ApiIssue = match self.request("GET", &url).call() {
+            Ok(response) => response.into_json()?,
+            Err(ureq::Error::Status(404, _)) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(Some(issue.into()))
+    }
+
+    fn list_issues_by_label(&self, label: &str) -> Result<Vec<Issue>> {
+        let pages: Vec<Vec<ApiIssue>> = self.get_pages(
+            &self.repo_url("/issues"),
+            &[("labels", label), ("state", "open")],
+        )?;
+
+        Ok(pages
+            .into_iter()
+            .flatten()
+            .filter(|issue| issue.pull_request.is_none())
+            .map(Issue::from)
+            .collect())
+    }
+
+    fn list_open_prs(&self) -> Result<Vec<PullRequest>> {
+        let mut prs = Vec::new();
+        let mut cursor: Option<String> = None;
+
+        loop {
+            let data: PrQueryData = self.graphql(
+                OPEN_PRS_QUERY,
+                serde_json::json!({ "owner": self.owner, "name": self.repo, "cursor": cursor }),
+            )?;
+            let connection = data.repository.pull_requests;
+
+            prs.extend(connection.nodes.into_iter().map(|pr| {
+                // Only the PRs themselves are paginated; the lists inside
+                // each are capped, and a PR past a cap is flagged instead
+                let truncated = pr.files.as_ref().is_some_and(Connection::is_truncated)
+                    || pr
+                        .closing_issues_references
+                        .as_ref()
+                        .is_some_and(Connection::is_truncated)
+                    || pr
+                        .latest_reviews
+                        .as_ref()
+                        .is_some_and(Connection::is_truncated)
+                    || pr.commits.nodes.iter().any(|node| {
+                        node.commit
+                            .status_check_rollup
+                            .as_ref()
+                            .is_some_and(|rollup| rollup.contexts.is_truncated())
+                    });
+                PullRequest {
+                    number: pr.number,
+                    title: pr.title,
+                    body: pr.body,
+                    author: pr.author.map(|a| a.login).unwrap_or_default(),
+                    head_sha: pr.head_ref_oid,
+                    head_ref: pr.head_ref_name,
+                    base_ref: pr.base_ref_name,
+                    mergeable: match pr.mergeable.as_deref() {
+                        Some("MERGEABLE") => MergeState::Mergeable,
+                        Some("CONFLICTING") => MergeState::Conflicting,
+                        _ => MergeState::Unknown,
+                    },
+                    checks: pr
+                        .commits
+                        .nodes
+                        .into_iter()
+                        .filter_map(|node| node.commit.status_check_rollup)
+                        .flat_map(|rollup| rollup.contexts.nodes)
+                        .map(|check| CheckRun {
+                            job_id: check.details_url.as_deref().and_then(actions_job_id),
+                            name: check.context.unwrap_or(check.name),
+                            conclusion: check.conclusion.or(check.state),
+                        })
+                        .collect(),
+                    files: pr
+                        .files
+                        .map(|files| files.nodes.into_iter().map(|f| f.path).collect())
+                        .unwrap_or_default(),
+                    closing_issues: pr
+                        .closing_issues_references
+                        .map(|issues| {
+                            issues
+                                .nodes
+                                .into_iter()
+                                .map(|issue| ClosingIssue {
+                                    repo: issue.repository.name_with_owner,
+                                    number: issue.number,
+                                })
+                                .collect()
+                        })
+                        .unwrap_or_default(),
+                    review_decision: pr
+                        .review_decision
+                        .as_deref()
+                        .map_or(ReviewDecision::Unknown, ReviewDecision::parse),
+                    approvals: pr.latest_reviews.map_or(0, |reviews| {
+                        reviews
+                            .nodes
+                            .iter()
+                            .filter(|review| review.state == "APPROVED")
+                            .count() as u32
+                    }),
+                    truncated,
+                }
+            }));
+
+            match connection.page_info {
+                Some(PageInfo {
+                    has_next_page: true,
+                    end_cursor: Some(end),
+                }) => cursor = Some(end),
+                _ => break,
+            }
+        }
+
+        Ok(prs)
+    }
+
+    fn comment_on_pr(&self, number: u32, body: &str) -> Result<bool> {
+        self.send(
+            "POST",
+            &self.repo_url(&format!("/issues/{number}/comments")),
+            serde_json::json!({ "body": body }),
+        )
+    }
+
+    fn list_pr_comments(&self, number: u32) -> Result<Vec<String>> {
+        let pages: Vec<Vec<ApiComment>> =
+            self.get_pages(&self.repo_url(&format!("/issues/{number}/comments")), &[])?;
+
+        Ok(pages
+            .into_iter()
+            .flatten()
+            .filter_map(|comment| comment.body)
+            .collect())
+    }
+
+    fn add_label(&self, number: u32, label: &str) -> Result<bool> {
+        self.send(
+            "POST",
+            &self.repo_url(&format!("/issues/{number}/labels")),
+            serde_json::json!({ "labels": [label] }),
+        )
+    }
+
+    fn close_pr(&self, number: u32) -> Result<bool> {
+        self.send(
+            "PATCH",
+            &self.repo_url(&format!("/pulls/{number}")),
+            serde_json::json!({ "state": "closed" }),
+        )
+    }
+
+    fn merge_pr(&self, number: u32, method: MergeMethod, head_sha: &str) -> Result<bool> {
+        // Refused with 409 when the head moved on, 405 when not mergeable
+        self.send(
+            "PUT",
+            &self.repo_url(&format!("/pulls/{number}/merge")),
+            serde_json::json!({ "merge_method": method.as_str(), "sha": head_sha }),
+        )
+    }
+
+    fn list_workflow_runs(&self, status: &str) -> Result<Vec<WorkflowRun>> {
+        let pages: Vec<ApiRunsPage> =
+            self.get_pages(&self.repo_url("/actions/runs"), &[("status", status)])?;
+
+        Ok(pages
+            .into_iter()
+            .flat_map(|page| page.workflow_runs)
+            .map(|run| WorkflowRun {
+                id: run.id,
+                actor: run.actor.map(|a| a.login).unwrap_or_default(),
+                head_branch: run.head_branch.unwrap_or_default(),
+                head_sha: run.head_sha,
+                workflow_path: run.path,
+                pr: run.pull_requests.first().map(|pr| pr.number),
+            })
+            .collect())
+    }
+
+    fn fetch_job_log(&self, job_id: u64) -> Result<Option<String>> {
+        // Redirects to a short-lived download URL
+        let url = self.repo_url(&format!("/actions/jobs/{job_id}/logs"));
+        match self.request("GET", &url).call() {
+            Ok(response) => Ok(Some(response.into_string()?)),
+            Err(ureq::Error::Status(404 | 410, _)) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn rerun_workflow(&self, run_id: u64) -> Result<bool> {
+        self.send(
+            "POST",
+            &self.repo_url(&format!("/actions/runs/{run_id}/rerun")),
+            serde_json::json!({}),
+        )
+    }
+
+    fn spawn_agent(&self, task: &str) -> Result<AgentTaskResult> {
+        // Same Copilot jobs endpoint `gh agent-task create` talks to
+        let url = format!(
+            "{}/agents/swe/v1/jobs/{}/{}",
+            self.copilot_url, self.owner, self.repo
+        );
+        let body = serde_json::json!({ "problem_statement": task, "event_type": "chore_bot" });
+
+        Ok(match self.request("POST", &url).send_json(body) {
+            Ok(response) => {
+                let message = response.into_string().unwrap_or_default();
+                let job: serde_json::Value = serde_json::from_str(&message).unwrap_or_default();
+                AgentTaskResult {
+                    success: true,
+                    task_id: job["job_id"]
+                        .as_str()
+                        .or_else(|| job["id"].as_str())
+                        .map(|s| s.to_string()),
+                    message,
+                }
+            }
+            Err(ureq::Error::Status(code, response)) => AgentTaskResult {
+                success: false,
+                message: format!("{code}: {}", response.into_string().unwrap_or_default()),
+                task_id: None,
+            },
+            Err(e) => return Err(e.into()),
+        })
+    }
+
+    fn list_agent_tasks(&self) -> Result<Vec<AgentTask>> {
+        let url = format!(
+            "{}/agents/swe/v1/jobs/{}/{}",
+            self.copilot_url, self.owner, self.repo
+        );
+        let json: serde_json::Value = match self.request("GET", &url).call() {
+            Ok(response) => response.into_json()?,
+            Err(ureq::Error::Status(code, response)) => bail!(
+                "Copilot API returned {code}: {}",
+                response.into_string().unwrap_or_default()
+            ),
+            Err(e) => return Err(e.into()),
+        };
+
+        let jobs = json.get("jobs").unwrap_or(&json);
+        Ok(jobs
+            .as_array()
+            .map(|jobs| {
+                jobs.iter()
+                    .filter_map(|job| {
+                        Some(AgentTask {
+                            id: job["job_id"]
+                                .as_str()
+                                .or_else(|| job["id"].as_str())?
+                                .to_string(),
+                            state: AgentTaskState::parse(job["status"].as_str().unwrap_or("")),
+                            repo: Some(format!("{}/{}", self.owner, self.repo)),
+                            pr_number: job["pull_request"]["number"].as_u64().map(|n| n as u32),
+                        })
+                    })
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+}
+
+/// Extract the `rel="next"` URL from a `Link` header
+fn next_link(header: &str) -> Option<String> {
+    header.split(',').find_map(|part| {
+        let (url, rel) = part.split_once(';')?;
+        rel.contains("rel=\"next\"").then(|| {
+            url.trim()
+                .trim_start_matches('<')
+                .trim_end_matches('>')
+                .to_string()
+        })
+    })
+}
+
+/// Repository `repo_path` is a checkout of, falling back to `env_slug`
+/// (`GITHUB_REPOSITORY`) only when it has no `origin` remote: inside GitHub
+/// Actions that variable names the repository running the workflow, which
+/// needn't be the one being worked on
+fn resolve_repo(repo_path: &Path, env_slug: Option<&str>) -> Result<(String, String)> {
+    let output = Command::new("git")
+        .args(["remote", "get-url", "origin"])
+        .current_dir(repo_path)
+        .output()
+        .context("Failed to run git")?;
+    let url = String::from_utf8_lossy(&output.stdout);
+
+    match env_slug {
+        Some(slug) if !output.status.success() => split_slug(slug),
+        _ => parse_remote(url.trim())
+            .with_context(|| format!("Cannot determine GitHub repository from remote {url:?}")),
+    }
+}
+
+fn split_slug(slug: &str) -> Result<(String, String)> {
+    match slug.split_once('/') {
+        Some((owner, repo)) if !owner.is_empty() && !repo.is_empty() => {
+            Ok((owner.to_string(), repo.to_string()))
+        }
+        _ => bail!("Invalid repository {slug:?}, expected owner/name"),
+    }
+}
+
+/// Parse `owner/repo` out of an HTTPS or SSH GitHub remote URL
+fn parse_remote(url: &str) -> Result<(String, String)> {
+    let re = regex::Regex::new(r"github\.com[:/]([^/]+)/([^/]+?)(?:\.git)?/?$").unwrap();
+    let caps = re.captures(url).context("Not a GitHub remote")?;
+    Ok((caps[1].to_string(), caps[2].to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use tiny_http::{Header, Response, Server};
+
+    /// A recorded request: method, URL and body
+    type Recorded = (String, String, String);
+
+    /// Serve `responses` in order from a local server, returning its base URL
+    /// and a channel of the requests it received
+    fn mock_server(
+        responses: Vec<(u16, String, Option<String>)>,
+    ) -> (String, mpsc::Receiver<Recorded>) {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let base = format!("http://{}", server.server_addr().to_ip().unwrap());
+        let (tx, rx) = mpsc::channel();
+        let link_base = base.clone();
+
+        std::thread::spawn(move || {
+            for (status, body, link) in responses {
+                let Ok(mut request) = server.recv() else {
+                    return;
+                };
+                let mut content = String::new();
+                request.as_reader().read_to_string(&mut content).unwrap();
+                tx.send((
+                    request.method().to_string(),
+                    request.url().to_string(),
+                    content,
+                ))
+                .unwrap();
+
+                let mut response = Response::from_string(body).with_status_code(status);
+                if let Some(link) = link {
+                    let value = format!("<{link_base}{link}>; rel=\"next\"");
+                    response.add_header(Header::from_bytes("Link", value).unwrap());
+                }
+                request.respond(response).unwrap();
+            }
+        });
+
+        (base, rx)
+    }
+
+    fn forge(base: &str) -> GitHubForge {
+        GitHubForge::new("acme", "widgets", "secret").with_base_url(base)
+    }
+
+    #[test]
+    fn test_list_issues_follows_pagination_and_skips_prs() {
+        let (base, rx) = mock_server(vec![
+            (
+                200,
+                r#"[{"number": 1, "title": "a"}, {"number": 2, "title": "b", "pull_request": {}}]"#
+                    .to_string(),
+                Some("/repos/acme/widgets/issues?page=2".to_string()),
+            ),
+            (200, r#"[{"number": 3, "title": "c"}]"#.to_string(), None),
+        ]);
+
+        let issues = forge(&base).list_issues_by_label("good first").unwrap();
+        let numbers: Vec<u32> = issues.iter().map(|i| i.number).collect();
+        assert_eq!(numbers, vec![1, 3]);
+
+        let (_, first, _) = rx.recv().unwrap();
+        assert!(first.contains("labels=good%20first") || first.contains("labels=good+first"));
+        let (_, second, _) = rx.recv().unwrap();
+        assert!(second.ends_with("page=2"));
+    }
+
+    #[test]
+    fn test_list_open_prs_pages_graphql_cursor() {
+        let page = |number: u32, next: bool| {
+            serde_json::json!({ "data": { "repository": { "pullRequests": {
+                "pageInfo": { "hasNextPage": next, "endCursor": "c1" },
+                "nodes": [{
+                    "number": number, "title": "t", "body": "closes #4",
+                    "mergeable": "CONFLICTING", "author": { "login": "Copilot" },
+                    "closingIssuesReferences": { "nodes": [
+                        { "number": 4, "repository": { "nameWithOwner": "acme/widgets" } }
+                    ] },
+                    "reviewDecision": "CHANGES_REQUESTED",
+                    "latestReviews": { "nodes": [{ "state": "APPROVED" }, { "state": "CHANGES_REQUESTED" }] },
+                    "commits": { "nodes": [{ "commit": { "statusCheckRollup": {
+                        "contexts": { "pageInfo": { "hasNextPage": next }, "nodes": [
+                            { "conclusion": "FAILURE" },
+                            { "context": "ci/lint", "state": "SUCCESS" },
+                            {}
+                        ] }
+                    } } }] }
+                }]
+            } } } })
+            .to_string()
+        };
+        let (base, rx) = mock_server(vec![
+            (200, page(1, true), None),
+            (200, page(2, false), None),
+        ]);
+
+        let prs = forge(&base).list_open_prs().unwrap();
+        assert_eq!(prs.len(), 2);
+        assert_eq!(prs[0].author, "Copilot");
+        assert_eq!(prs[0].mergeable, MergeState::Conflicting);
+        assert_eq!(prs[0].closing_issues[0].number, 4);
+        assert!(prs[1].checks.iter().any(|c| c.is_failure()));
+        assert_eq!(prs[0].checks[1].name, "ci/lint");
+        assert!(prs[0].checks[1].is_success());
+        assert_eq!(prs[0].review_decision, ReviewDecision::ChangesRequested);
+        assert_eq!(prs[0].approvals, 1);
+        // More checks than the first page of contexts held
+        assert!(prs[0].truncated && !prs[1].truncated);
+
+        rx.recv().unwrap();
+        let (_, _, second) = rx.recv().unwrap();
+        assert!(second.contains("\"cursor\":\"c1\""));
+    }
+
+    #[test]
+    fn test_graphql_errors_are_reported() {
+        let (base, _rx) = mock_server(vec![(
+            200,
+            r#"{"errors": [{"message": "Bad credentials"}]}"#.to_string(),
+            None,
+        )]);
+
+        let err = forge(&base).list_open_prs().unwrap_err();
+        assert!(err.to_string().contains("Bad credentials"));
+    }
+
+    #[test]
+    fn test_comment_posts_body_and_maps_errors() {
+        let (base, rx) = mock_server(vec![
+            (201, "{}".to_string(), None),
+            (404, "{}".to_string(), None),
+        ]);
+        let forge = forge(&base);
+
+        assert!(forge.comment_on_pr(7, "hello").unwrap());
+        assert!(!forge.comment_on_pr(8, "hello").unwrap());
+
+        let (method, url, body) = rx.recv().unwrap();
+        assert_eq!(method, "POST");
+        assert_eq!(url, "/repos/acme/widgets/issues/7/comments");
+        assert!(body.contains("hello"));
+    }
+
+    #[test]
+    fn test_merge_pins_the_head_commit() {
+        let (base, rx) = mock_server(vec![
+            (200, r#"{"merged": true}"#.to_string(), None),
+            (409, "{}".to_string(), None),
+        ]);
+        let forge = forge(&base);
+
+        assert!(forge.merge_pr(7, MergeMethod::Squash, "abc").unwrap());
+        assert!(!forge.merge_pr(8, MergeMethod::Rebase, "def").unwrap());
+
+        let (method, url, body) = rx.recv().unwrap();
+        assert_eq!(method, "PUT");
+        assert_eq!(url, "/repos/acme/widgets/pulls/7/merge");
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({ "merge_method": "squash", "sha": "abc" })
+        );
+    }
+
+    #[test]
+    fn test_origin_remote_wins_over_github_repository() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let git = |args: &[&str]| {
+            let status = Command::new("git")
+                .args(args)
+                .current_dir(dir.path())
+                .status()
+                .unwrap();
+            assert!(status.success());
+        };
+        git(&["init", "--quiet"]);
+
+        let slug = |env: Option<&str>| resolve_repo(dir.path(), env).ok();
+        assert_eq!(
+            slug(Some("jade-codes/devops-agent")),
+            Some(("jade-codes".to_string(), "devops-agent".to_string()))
+        );
+        assert_eq!(slug(None), None);
+
+        git(&[
+            "remote",
+            "add",
+            "origin",
+            "https://github.com/acme/widgets.git",
+        ]);
+        assert_eq!(
+            slug(Some("jade-codes/devops-agent")),
+            Some(("acme".to_string(), "widgets".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_parse_remote() {
+        assert_eq!(
+            parse_remote("git@github.com:jade-codes/syster.git").unwrap(),
+            ("jade-codes".to_string(), "syster".to_string())
+        );
+        assert_eq!(
+            parse_remote("https://github.com/jade-codes/mother").unwrap(),
+            ("jade-codes".to_string(), "mother".to_string())
+        );
+        assert!(parse_remote("https://gitlab.com/a/b").is_err());
+    }
+}
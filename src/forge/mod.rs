@@ -0,0 +1,256 @@
+//! Forge backends
+//!
+//! A [`Forge`] is the code host chore-bot talks to. Workflows only ever go
+//! through this trait, so they can be pointed at a different host (or a fake
+//! one) without touching the orchestration logic.
+
+#[cfg(test)]
+pub mod fake;
+mod gh_cli;
+mod github;
+
+pub use gh_cli::GhCliForge;
+pub use github::GitHubForge;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+/// An issue with its title, body, labels and the signals used to rank it
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Issue {
+    pub number: u32,
+    pub title: String,
+    pub body: String,
+    pub labels: Vec<String>,
+    /// When the issue was opened (RFC 3339), if reported
+    pub created_at: Option<String>,
+    /// Total reactions on the issue
+    pub reactions: u32,
+    /// Title of the milestone the issue belongs to
+    pub milestone: Option<String>,
+}
+
+/// Mergeable state of a pull request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MergeState {
+    Mergeable,
+    Conflicting,
+    #[default]
+    Unknown,
+}
+
+/// A single entry of a PR's status check rollup
+#[derive(Debug, Clone, Default)]
+pub struct CheckRun {
+    pub name: String,
+    /// Conclusion as reported by the forge (e.g. `SUCCESS`, `FAILURE`)
+    pub conclusion: Option<String>,
+    /// GitHub Actions job behind the check, whose log can be fetched
+    pub job_id: Option<u64>,
+}
+
+impl CheckRun {
+    pub fn is_failure(&self) -> bool {
+        self.conclusion.as_deref() == Some("FAILURE")
+    }
+
+    /// Finished without blocking a merge; pending checks have no conclusion
+    pub fn is_success(&self) -> bool {
+        matches!(
+            self.conclusion.as_deref(),
+            Some("SUCCESS" | "NEUTRAL" | "SKIPPED")
+        )
+    }
+}
+
+/// Overall review state of a pull request (`reviewDecision`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReviewDecision {
+    Approved,
+    ChangesRequested,
+    ReviewRequired,
+    /// Not reported, as for repositories that don't require reviews
+    #[default]
+    Unknown,
+}
+
+impl ReviewDecision {
+    /// Parse the decision reported by the forge
+    pub fn parse(decision: &str) -> Self {
+        match decision {
+            "APPROVED" => Self::Approved,
+            "CHANGES_REQUESTED" => Self::ChangesRequested,
+            "REVIEW_REQUIRED" => Self::ReviewRequired,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+/// How a pull request is merged
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MergeMethod {
+    #[default]
+    Squash,
+    Rebase,
+    /// A merge commit
+    Merge,
+}
+
+impl MergeMethod {
+    /// Name used by the REST API's `merge_method` and `gh pr merge` flags
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Squash => "squash",
+            Self::Rebase => "rebase",
+            Self::Merge => "merge",
+        }
+    }
+}
+
+/// Job id in an Actions check's details URL
+/// (`…/actions/runs/<run>/job/<job>`)
+fn actions_job_id(details_url: &str) -> Option<u64> {
+    let re = regex::Regex::new(r"/actions/runs/\d+/job/(\d+)").unwrap();
+    re.captures(details_url)?[1].parse().ok()
+}
+
+/// An issue GitHub will close when a PR merges
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClosingIssue {
+    /// `owner/name` of the issue's repository
+    pub repo: String,
+    pub number: u32,
+}
+
+/// An open pull request
+#[derive(Debug, Clone, Default)]
+pub struct PullRequest {
+    pub number: u32,
+    pub title: String,
+    pub body: String,
+    pub author: String,
+    /// Commit the PR's head branch points at
+    pub head_sha: String,
+    /// Branch the PR is from
+    pub head_ref: String,
+    /// Branch the PR merges into
+    pub base_ref: String,
+    pub mergeable: MergeState,
+    pub checks: Vec<CheckRun>,
+    /// Paths changed by the PR, relative to the repository root
+    pub files: Vec<String>,
+    /// Issues linked as closed by the PR (`closingIssuesReferences`)
+    pub closing_issues: Vec<ClosingIssue>,
+    pub review_decision: ReviewDecision,
+    /// Reviewers whose latest review approves the PR
+    pub approvals: u32,
+    /// It has more files, checks, closing issues or reviews than the forge
+    /// listed, so those lists are incomplete
+    pub truncated: bool,
+}
+
+/// A GitHub Actions workflow run
+#[derive(Debug, Clone, Default)]
+pub struct WorkflowRun {
+    pub id: u64,
+    /// Login of the user or bot whose event triggered the run
+    pub actor: String,
+    pub head_branch: String,
+    pub head_sha: String,
+    /// Workflow file, e.g. `.github/workflows/ci.yml`
+    pub workflow_path: String,
+    /// PR the run belongs to, if GitHub reports one (it doesn't for forks)
+    pub pr: Option<u32>,
+}
+
+/// Lifecycle state of an agent task
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AgentTaskState {
+    /// Queued or in progress
+    Running,
+    Completed,
+    /// Failed, cancelled or timed out
+    Failed,
+    #[default]
+    Unknown,
+}
+
+impl AgentTaskState {
+    /// Parse the state string reported by the forge
+    pub fn parse(state: &str) -> Self {
+        match state.to_lowercase().as_str() {
+            "queued" | "pending" | "in_progress" | "running" => Self::Running,
+            "completed" | "succeeded" | "success" => Self::Completed,
+            "failed" | "error" | "cancelled" | "canceled" | "timed_out" => Self::Failed,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+/// An agent task as reported by the forge
+#[derive(Debug, Clone, Default)]
+pub struct AgentTask {
+    pub id: String,
+    pub state: AgentTaskState,
+    /// Repository the task runs against (`owner/name`), when reported
+    pub repo: Option<String>,
+    /// PR opened by the task, if any
+    pub pr_number: Option<u32>,
+}
+
+/// Response from spawning an agent task
+#[derive(Debug)]
+pub struct AgentTaskResult {
+    pub success: bool,
+    pub message: String,
+    /// Identifier of the created task, when the forge reports one
+    pub task_id: Option<String>,
+}
+
+/// Operations chore-bot needs from a code host, scoped to a single repository
+pub trait Forge {
+    /// Repository this forge is scoped to, as `owner/name`
+    fn repo_name(&self) -> Result<String>;
+
+    /// Fetch an issue, or `None` if it can't be read
+    fn fetch_issue(&self, number: u32) -> Result<Option<Issue>>;
+
+    /// List open issues carrying `label`
+    fn list_issues_by_label(&self, label: &str) -> Result<Vec<Issue>>;
+
+    /// List open pull requests with their check rollup, mergeable state and
+    /// changed files
+    fn list_open_prs(&self) -> Result<Vec<PullRequest>>;
+
+    /// Comment on a PR, returning whether the comment was posted
+    fn comment_on_pr(&self, number: u32, body: &str) -> Result<bool>;
+
+    /// Bodies of the comments on a PR, oldest first
+    fn list_pr_comments(&self, number: u32) -> Result<Vec<String>>;
+
+    /// Add a label to a PR, returning whether it was added
+    fn add_label(&self, number: u32, label: &str) -> Result<bool>;
+
+    /// Close a PR, returning whether it was closed
+    fn close_pr(&self, number: u32) -> Result<bool>;
+
+    /// Merge a PR if its head is still `head_sha`, returning whether it was
+    /// merged
+    fn merge_pr(&self, number: u32, method: MergeMethod, head_sha: &str) -> Result<bool>;
+
+    /// List workflow runs with the given status (e.g. `action_required`)
+    fn list_workflow_runs(&self, status: &str) -> Result<Vec<WorkflowRun>>;
+
+    /// Log of a GitHub Actions job, or `None` if it is no longer available
+    fn fetch_job_log(&self, job_id: u64) -> Result<Option<String>>;
+
+    /// Rerun a workflow run, returning whether the rerun was accepted
+    fn rerun_workflow(&self, run_id: u64) -> Result<bool>;
+
+    /// Spawn a Copilot agent task with the given task description
+    fn spawn_agent(&self, task: &str) -> Result<AgentTaskResult>;
+
+    /// List recent agent tasks
+    fn list_agent_tasks(&self) -> Result<Vec<AgentTask>>;
+}
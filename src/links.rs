@@ -0,0 +1,148 @@
+//! Which issues an open PR works on
+//!
+//! A PR is linked to an issue the way GitHub links them: through a closing
+//! keyword in its description (`Closes #4`, `fixes acme/widgets#4`,
+//! `Resolved: https://github.com/acme/widgets/issues/4`), as reported by the
+//! forge's `closingIssuesReferences`. Passing mentions like "see #4", colours
+//! like `#123456` and references to other repositories' issues don't count.
+//! Repositories whose PRs reference issues some other way can configure a
+//! fallback pattern.
+
+use crate::config::LinksConfig;
+use crate::forge::PullRequest;
+use anyhow::{Context, Result};
+use regex::Regex;
+
+/// Finds the issues of one repository that PRs are linked to
+pub struct Linker {
+    /// `owner/name`
+    repo: String,
+    closing: Regex,
+    fallback: Option<Regex>,
+}
+
+impl Linker {
+    pub fn new(repo: &str, config: &LinksConfig) -> Result<Self> {
+        let fallback = config
+            .fallback_pattern
+            .as_deref()
+            .map(Regex::new)
+            .transpose()
+            .context("Invalid [links] fallback_pattern")?;
+
+        Ok(Self {
+            repo: repo.to_string(),
+            closing: Regex::new(
+                r"(?i)\b(?:close[sd]?|fix(?:e[sd])?|resolve[sd]?):?\s+(?:https://github\.com/([\w.-]+/[\w.-]+)/issues/|([\w.-]+/[\w.-]+)?#)(\d+)\b",
+            )
+            .unwrap(),
+            fallback,
+        })
+    }
+
+    /// Issues in this repository the PR works on, in order of first mention
+    pub fn linked_issues(&self, pr: &PullRequest) -> Vec<u32> {
+        let mut linked = self.closing_issues(pr);
+        if let Some(fallback) = &self.fallback {
+            for text in [&pr.body, &pr.title] {
+                for caps in fallback.captures_iter(text) {
+                    if let Some(number) = caps.get(1).and_then(|m| m.as_str().parse().ok()) {
+                        if !linked.contains(&number) {
+                            linked.push(number);
+                        }
+                    }
+                }
+            }
+        }
+
+        linked
+    }
+
+    /// Issues in this repository that merging the PR closes on GitHub: the
+    /// forge's closing references and closing keywords, but not fallback
+    /// pattern matches, which GitHub knows nothing about
+    pub fn closing_issues(&self, pr: &PullRequest) -> Vec<u32> {
+        let mut closing = Vec::new();
+        let mut add = |number: u32| {
+            if !closing.contains(&number) {
+                closing.push(number);
+            }
+        };
+
+        for issue in &pr.closing_issues {
+            if self.is_this_repo(&issue.repo) {
+                add(issue.number);
+            }
+        }
+
+        for caps in self.closing.captures_iter(&pr.body) {
+            let repo = caps.get(1).or(caps.get(2)).map(|m| m.as_str());
+            if repo.is_none_or(|repo| self.is_this_repo(repo)) {
+                if let Ok(number) = caps[3].parse() {
+                    add(number);
+                }
+            }
+        }
+
+        closing
+    }
+
+    fn is_this_repo(&self, repo: &str) -> bool {
+        repo.eq_ignore_ascii_case(&self.repo)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::forge::ClosingIssue;
+
+    fn pr(title: &str, body: &str) -> PullRequest {
+        PullRequest {
+            title: title.to_string(),
+            body: body.to_string(),
+            ..PullRequest::default()
+        }
+    }
+
+    fn linker(fallback: Option<&str>) -> Linker {
+        let config = LinksConfig {
+            fallback_pattern: fallback.map(str::to_string),
+        };
+        Linker::new("acme/widgets", &config).unwrap()
+    }
+
+    #[test]
+    fn test_only_closing_keywords_link_issues() {
+        let body = "Fixes #4, closes #7.\n\
+            RESOLVED: #8 and resolves acme/Widgets#9\n\
+            Closes https://github.com/acme/widgets/issues/10\n\
+            See #5 for context; colour #123456 is unchanged.\n\
+            Fixes other/repo#11, fixes https://github.com/other/repo/issues/12\n\
+            prefixes #13, fixed #4 again, closes #14abc";
+        assert_eq!(
+            linker(None).linked_issues(&pr("Fix #6", body)),
+            [4, 7, 8, 9, 10]
+        );
+    }
+
+    #[test]
+    fn test_forge_links_and_fallback_pattern() {
+        let mut linked = pr("Tidy #6", "Part of #5");
+        linked.closing_issues = vec![
+            ClosingIssue {
+                repo: "acme/widgets".to_string(),
+                number: 3,
+            },
+            ClosingIssue {
+                repo: "other/repo".to_string(),
+                number: 2,
+            },
+        ];
+
+        assert_eq!(linker(None).linked_issues(&linked), [3]);
+        let with_fallback = linker(Some(r"(?:^|\s)#(\d+)\b"));
+        assert_eq!(with_fallback.linked_issues(&linked), [3, 5, 6]);
+        assert_eq!(with_fallback.closing_issues(&linked), [3]);
+    }
+}
@@ -0,0 +1,404 @@
+//! Offline checks for prompt and comment templates (`prompts check`)
+//!
+//! Every workflow's prompt is compared with the variables the workflow
+//! provides and rendered against a sample batch, so a broken edit fails CI
+//! instead of reaching an agent. It also has to ask for a closing keyword for
+//! every issue in the PR description: that is how `conflicts` and `merge`
+//! find the issues an agent's PR works on.
+
+use crate::analysis::{self, FileStats};
+use crate::config::{Batching, Config, LinksConfig};
+use crate::forge::{Issue, PullRequest};
+use crate::links::Linker;
+use crate::{subagent, template};
+use regex::Regex;
+use serde_json::{json, Value};
+
+/// Rough characters-per-token ratio used for the size budget
+const CHARS_PER_TOKEN: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// Result of checking one template
+#[derive(Debug)]
+pub struct TemplateCheck {
+    /// What uses the template, e.g. `workflow bug` or `[nudge] comment`
+    pub name: String,
+    pub source: String,
+    pub tokens: usize,
+    pub findings: Vec<(Severity, String)>,
+}
+
+impl TemplateCheck {
+    pub fn errors(&self) -> usize {
+        self.findings
+            .iter()
+            .filter(|(severity, _)| *severity == Severity::Error)
+            .count()
+    }
+}
+
+/// Check every workflow prompt, comment template and unattached prompt
+pub fn check_templates(config: &Config, max_tokens: usize) -> Vec<TemplateCheck> {
+    let mut checks = Vec::new();
+
+    for (name, workflow) in &config.workflows {
+        let name = format!("workflow {name}");
+        let context = sample_context(workflow.batching, &config.verify_command);
+        checks.push(match config.prompts.resolve(&workflow.prompt) {
+            Ok(prompt) => {
+                let mut check = check(
+                    name,
+                    &prompt.source(),
+                    &prompt.text,
+                    Usage::Prompt(&context),
+                    max_tokens,
+                );
+                if check.findings.is_empty() && !asks_to_close_issues(&prompt.text, &context) {
+                    check.findings.push((
+                        Severity::Error,
+                        "never asks for `Closes #N` in the PR description".to_string(),
+                    ));
+                }
+                check
+            }
+            Err(e) => TemplateCheck {
+                name,
+                source: workflow.prompt.clone(),
+                tokens: 0,
+                findings: vec![(Severity::Error, format!("{e:#}"))],
+            },
+        });
+    }
+
+    let conflicts_context = json!({
+        "verify_command": config.verify_command,
+        "conflicted_files": ["src/lib.rs"],
+    });
+    let nudge_context = json!({
+        "verify_command": config.verify_command,
+        "checks": [{
+            "name": "test",
+            "errors": ["error[E0308]: mismatched types\n  --> src/lib.rs:3:5"],
+            "failed_tests": ["parser::tests::unicode"],
+            "panics": ["thread 'parser::tests::unicode' panicked at src/parser.rs:40:9:"],
+            "format_diffs": ["Diff in src/lib.rs at line 1:\n-fn one()->u32{1}"],
+            "log_tail": "",
+        }],
+    });
+    let escalation_context = json!({ "nudges": 3, "mentions": "@octocat" });
+    for (name, comment, context) in [
+        ("[nudge] comment", &config.nudge.comment, &nudge_context),
+        (
+            "[nudge] escalation_comment",
+            &config.nudge.escalation_comment,
+            &escalation_context,
+        ),
+        (
+            "[conflicts] comment",
+            &config.conflicts.comment,
+            &conflicts_context,
+        ),
+    ] {
+        checks.push(check(
+            name.to_string(),
+            "config",
+            comment,
+            Usage::Comment(context),
+            max_tokens,
+        ));
+    }
+
+    let refactor_context = analysis::refactor_context(
+        "src/sample",
+        ".chore-bot/plans/src-sample.json",
+        &[FileStats {
+            path: "src/sample/mod.rs".to_string(),
+            lines: 100,
+            functions: 5,
+            coverage: Some(50.0),
+        }],
+    );
+    checks.push(match config.prompts.resolve("refactor-plan") {
+        Ok(prompt) => check(
+            "command refactor-plan".to_string(),
+            &prompt.source(),
+            &prompt.text,
+            Usage::Prompt(&refactor_context),
+            max_tokens,
+        ),
+        Err(e) => TemplateCheck {
+            name: "command refactor-plan".to_string(),
+            source: String::new(),
+            tokens: 0,
+            findings: vec![(Severity::Error, format!("{e:#}"))],
+        },
+    });
+
+    // Templates nothing uses still have to parse
+    let attached: Vec<&str> = config
+        .workflows
+        .values()
+        .map(|w| w.prompt.as_str())
+        .chain(["refactor-plan"])
+        .collect();
+    for name in config.prompts.names() {
+        if attached.contains(&name.as_str()) {
+            continue;
+        }
+        let check = match config.prompts.resolve(&name) {
+            Ok(prompt) => check(
+                format!("prompt {name}"),
+                &prompt.source(),
+                &prompt.text,
+                Usage::Unattached,
+                max_tokens,
+            ),
+            Err(e) => TemplateCheck {
+                name: format!("prompt {name}"),
+                source: String::new(),
+                tokens: 0,
+                findings: vec![(Severity::Error, format!("{e:#}"))],
+            },
+        };
+        checks.push(check);
+    }
+
+    checks
+}
+
+/// How a template is rendered
+enum Usage<'a> {
+    /// Workflow prompt: every provided variable should be used
+    Prompt(&'a Value),
+    /// PR comment: provided variables are optional
+    Comment(&'a Value),
+    /// Not attached to anything; syntax and size only
+    Unattached,
+}
+
+/// Check one template against the context it will be rendered with
+fn check(name: String, source: &str, text: &str, usage: Usage, max_tokens: usize) -> TemplateCheck {
+    let (context, report_unused) = match usage {
+        Usage::Prompt(context) => (Some(context), true),
+        Usage::Comment(context) => (Some(context), false),
+        Usage::Unattached => (None, false),
+    };
+    let mut findings = Vec::new();
+    let mut rendered_len = text.len();
+
+    match template::variables(text) {
+        Err(e) => findings.push((Severity::Error, format!("{e:#}"))),
+        Ok(vars) => {
+            if let Some(Value::Object(provided)) = context {
+                for missing in vars.required.iter().filter(|v| !provided.contains_key(*v)) {
+                    findings.push((
+                        Severity::Error,
+                        format!("`{{{{{missing}}}}}` is never provided"),
+                    ));
+                }
+                let unused = provided.keys().filter(|v| report_unused && !vars.uses(v));
+                for unused in unused {
+                    findings.push((
+                        Severity::Warning,
+                        format!("`{unused}` is provided but never used"),
+                    ));
+                }
+            }
+
+            // Rendering catches what static analysis can't, e.g. misspelt
+            // fields inside `#each` or lists used as plain variables
+            if let Some(context) = context.filter(|_| findings.is_empty()) {
+                match template::render(text, context) {
+                    Ok(rendered) => rendered_len = rendered.len(),
+                    Err(e) => findings.push((Severity::Error, format!("{e:#}"))),
+                }
+            }
+        }
+    }
+
+    let tokens = rendered_len.div_ceil(CHARS_PER_TOKEN);
+    if tokens > max_tokens {
+        findings.push((
+            Severity::Error,
+            format!("~{tokens} tokens exceeds the budget of {max_tokens}"),
+        ));
+    }
+
+    TemplateCheck {
+        name,
+        source: source.to_string(),
+        tokens,
+        findings,
+    }
+}
+
+/// Whether the rendered prompt has a line about the PR description (or body,
+/// or the pull request's) with a closing keyword for every issue of the batch
+fn asks_to_close_issues(text: &str, context: &Value) -> bool {
+    let Ok(rendered) = template::render(text, context) else {
+        return false;
+    };
+    let issues: Vec<u32> = match context.get("issues") {
+        Some(Value::Array(issues)) => issues.iter().map(|i| &i["number"]).collect(),
+        _ => vec![&context["issue"]],
+    }
+    .into_iter()
+    .filter_map(|n| n.as_u64().and_then(|n| u32::try_from(n).ok()))
+    .collect();
+
+    let description =
+        Regex::new(r"(?i)\b(?:pr|pull request)(?:'s)?\s+(?:description|body)\b").unwrap();
+    let linker = Linker::new("sample/repo", &LinksConfig::default()).unwrap();
+    rendered
+        .lines()
+        .filter(|line| description.is_match(line))
+        .any(|line| {
+            let pr = PullRequest {
+                body: line.to_string(),
+                ..PullRequest::default()
+            };
+            let linked = linker.closing_issues(&pr);
+            issues.iter().all(|issue| linked.contains(issue))
+        })
+}
+
+/// Context for a representative batch of the given kind
+fn sample_context(batching: Batching, verify_command: &str) -> Value {
+    let issue = |number| Issue {
+        number,
+        title: format!("Sample issue {number}"),
+        body: "Sample issue body.".to_string(),
+        labels: vec!["sample".to_string()],
+        ..Issue::default()
+    };
+    let batch = match batching {
+        Batching::Single => vec![issue(1)],
+        Batching::Module | Batching::Fixed => vec![issue(1), issue(2)],
+    };
+    subagent::batch_context(batching, "sample-module", &batch, verify_command)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prompts::PromptResolver;
+    use tempfile::TempDir;
+
+    fn findings(checks: &[TemplateCheck], name: &str) -> Vec<(Severity, String)> {
+        checks
+            .iter()
+            .find(|c| c.name == name)
+            .unwrap()
+            .findings
+            .clone()
+    }
+
+    #[test]
+    fn test_builtin_prompts_are_clean() {
+        let checks = check_templates(&Config::default(), 4000);
+        for check in &checks {
+            assert!(
+                check.findings.is_empty(),
+                "{}: {:?}",
+                check.name,
+                check.findings
+            );
+        }
+        assert!(checks.iter().any(|c| c.name == "command refactor-plan"));
+    }
+
+    #[test]
+    fn test_reports_missing_and_unused_variables() {
+        let repo = TempDir::new().unwrap();
+        let prompts = repo.path().join(".chore-bot/prompts");
+        std::fs::create_dir_all(&prompts).unwrap();
+        std::fs::write(
+            prompts.join("bug.md"),
+            "Fix #{{issue}}: {{title}} ({{closes_str}})",
+        )
+        .unwrap();
+        std::fs::write(
+            prompts.join("test.md"),
+            "{{module}} {{count}} {{verify_command}}\n{{#each issues}}{{numbr}}{{/each}}",
+        )
+        .unwrap();
+
+        let config = Config {
+            prompts: PromptResolver::new(None, repo.path()),
+            ..Config::default()
+        };
+        let checks = check_templates(&config, 4000);
+
+        let bug = findings(&checks, "workflow bug");
+        assert!(bug.contains(&(
+            Severity::Error,
+            "`{{closes_str}}` is never provided".to_string()
+        )));
+        assert!(bug.contains(&(
+            Severity::Warning,
+            "`body` is provided but never used".to_string()
+        )));
+
+        let test = findings(&checks, "workflow test");
+        assert!(test[0].1.contains("undefined variable `numbr`"));
+    }
+
+    #[test]
+    fn test_requires_closing_keywords_in_the_pr_description() {
+        let repo = TempDir::new().unwrap();
+        let prompts = repo.path().join(".chore-bot/prompts");
+        std::fs::create_dir_all(&prompts).unwrap();
+        let single = "Fix #{{issue}}: {{title}}\n{{body}}\nRun {{verify_command}}.\n";
+        std::fs::write(
+            prompts.join("bug.md"),
+            format!("{single}Commit as `fix: {{{{title}}}} (closes #{{{{issue}}}})`."),
+        )
+        .unwrap();
+        std::fs::write(
+            prompts.join("chore.md"),
+            format!("{single}The PR description must contain `Closes #{{{{issue}}}}`."),
+        )
+        .unwrap();
+        std::fs::write(
+            prompts.join("feature.md"),
+            format!("{single}The pull request body must contain `Closes #{{{{issue}}}}`."),
+        )
+        .unwrap();
+        std::fs::write(
+            prompts.join("test.md"),
+            "{{module}} {{count}} {{verify_command}}\n\
+             PR description: {{#each issues}}{{#if @first}}Closes #{{number}}{{/if}}{{/each}}",
+        )
+        .unwrap();
+
+        let config = Config {
+            prompts: PromptResolver::new(None, repo.path()),
+            ..Config::default()
+        };
+        let checks = check_templates(&config, 4000);
+
+        // A closing keyword in the commit message doesn't reach the PR
+        let missing = vec![(
+            Severity::Error,
+            "never asks for `Closes #N` in the PR description".to_string(),
+        )];
+        assert_eq!(findings(&checks, "workflow bug"), missing);
+        assert_eq!(findings(&checks, "workflow chore"), vec![]);
+        assert_eq!(findings(&checks, "workflow feature"), vec![]);
+        // Only the first issue of the batch would be closed
+        assert_eq!(findings(&checks, "workflow test"), missing);
+    }
+
+    #[test]
+    fn test_token_budget() {
+        let checks = check_templates(&Config::default(), 50);
+        let test = checks.iter().find(|c| c.name == "workflow test").unwrap();
+        assert!(test.tokens > 50);
+        assert!(test.errors() == 1 && test.findings[0].1.contains("budget of 50"));
+    }
+}
@@ -5,40 +5,143 @@
 //! - feature: Implement features from issues
 //! - bug: Fix bugs from issues
 //! - chore: Complete chores/tech debt
+//! - run: Any workflow defined in chore-bot.toml
 //! - approve: Rerun pending workflow runs
-
+//! - merge: Merge green agent PRs
+//! - refactor-plan: Plan a refactoring of a module
+//! - status: Reconcile spawned agent tasks with their issues and PRs
+
+mod analysis;
+mod approval;
+mod budget;
+mod ci_log;
+mod config;
+mod cron;
+mod daemon;
+mod forge;
+mod links;
+mod lint;
+mod merge;
+mod nudge;
+mod priority;
+mod prompts;
+mod rebase;
+mod schedule;
+mod sources;
+mod state;
+mod status;
 mod subagent;
-
-use anyhow::Result;
-use clap::{Parser, Subcommand};
+mod sweep;
+mod template;
+mod webhook;
+
+use anyhow::{Context, Result};
+use approval::Approval;
+use budget::Budget;
+use clap::{Parser, Subcommand, ValueEnum};
+use config::{Batching, Config, Escalation};
+use daemon::{Scheduler, SystemClock};
+use forge::{AgentTaskResult, Forge, GhCliForge, GitHubForge};
+use merge::Merge;
+use prompts::PromptResolver;
+use serde_json::json;
+use sources::SourceTree;
+use state::StateStore;
+use std::collections::{BTreeMap, HashMap};
 use std::path::{Path, PathBuf};
-
-/// Load a prompt template from the agent's directory
-fn load_prompt(agent: &str) -> Result<String> {
-    let prompt_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
-        .join("agents")
-        .join(agent)
-        .join("prompt.md");
-    Ok(std::fs::read_to_string(prompt_path)?)
+use sweep::{Manifest, RepoEntry, Step, Summary};
+use webhook::{Delivery, Event, Listener};
+
+/// Drop issues that already have an open PR or a recent spawn in the state store
+fn pending_issues(
+    forge: &dyn Forge,
+    state: &StateStore,
+    config: &Config,
+    issues: Vec<forge::Issue>,
+) -> Result<Vec<forge::Issue>> {
+    let repo = forge.repo_name()?;
+    let issues_with_prs = subagent::list_issues_with_open_prs(forge, &config.links)?;
+    let now = state::unix_now();
+
+    Ok(issues
+        .into_iter()
+        .filter(|issue| {
+            !issues_with_prs.contains(&issue.number)
+                && !state.recently_spawned(&repo, issue.number, now)
+        })
+        .collect())
 }
 
-/// Render a template by replacing {{key}} placeholders
-fn render_template(template: &str, vars: &[(&str, &str)]) -> String {
-    let mut result = template.to_string();
-    for (key, value) in vars {
-        result = result.replace(&format!("{{{{{key}}}}}"), value);
-    }
-    result
+/// Spawn an agent for `issues` and record the attempt in the state store
+fn spawn_tracked(
+    forge: &dyn Forge,
+    state: &mut StateStore,
+    workflow: &str,
+    issues: &[u32],
+    task: &str,
+) -> Result<AgentTaskResult> {
+    let result = forge.spawn_agent(task)?;
+    state.record(
+        &forge.repo_name()?,
+        issues,
+        workflow,
+        &result,
+        state::unix_now(),
+    )?;
+    Ok(result)
 }
 
 #[derive(Parser, Debug)]
 #[command(name = "chore-bot")]
 #[command(about = "Spawns GitHub Copilot agents for automated workflows")]
 struct Args {
+    /// Forge backend used to talk to GitHub
+    #[arg(long, global = true, value_enum, default_value = "gh")]
+    forge: ForgeKind,
+
+    /// Config file (default: chore-bot.toml or .chore-bot/config.toml in the repo)
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
+    /// State file recording spawned agents (default: ~/.local/state/chore-bot/state.json)
+    #[arg(long, global = true)]
+    state_file: Option<PathBuf>,
+
+    /// Directory searched first for prompt templates (`<name>.md`)
+    #[arg(long, global = true)]
+    prompts_dir: Option<PathBuf>,
+
     #[command(subcommand)]
     command: Commands,
 }
 
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum ForgeKind {
+    /// Shell out to the `gh` CLI
+    Gh,
+    /// Call the GitHub REST/GraphQL APIs directly (needs GITHUB_TOKEN)
+    Github,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum StatusOutput {
+    Console,
+    Json,
+    Markdown,
+}
+
+/// Opens the forge for a checkout and the `owner/name` it is a checkout of
+type OpenForge<'a> = dyn Fn(&Path, &str) -> Result<Box<dyn Forge>> + 'a;
+
+/// Open the forge for the repository at `repo_path`, which is `repo`
+/// (`owner/name`) when known rather than worked out from the checkout
+fn open_forge(kind: ForgeKind, repo_path: &Path, repo: Option<&str>) -> Result<Box<dyn Forge>> {
+    Ok(match kind {
+        ForgeKind::Gh => Box::new(GhCliForge::new(repo_path)),
+        ForgeKind::Github => Box::new(GitHubForge::from_env(repo_path, repo)?),
+    })
+}
+
 #[derive(Subcommand, Debug)]
 enum Commands {
     /// Spawn agents to add tests
@@ -47,9 +150,9 @@ enum Commands {
         #[arg(short, long)]
         repo_path: PathBuf,
 
-        /// Max agents to spawn
-        #[arg(short, long, default_value = "5")]
-        max_prs: u8,
+        /// Max agents to spawn (default from config)
+        #[arg(short, long)]
+        max_prs: Option<u8>,
 
         /// Batch by fixed size instead of module (issues per agent)
         #[arg(long)]
@@ -62,9 +165,9 @@ enum Commands {
         #[arg(short, long)]
         repo_path: PathBuf,
 
-        /// Max agents to spawn
-        #[arg(short, long, default_value = "3")]
-        max_prs: u8,
+        /// Max agents to spawn (default from config)
+        #[arg(short, long)]
+        max_prs: Option<u8>,
     },
 
     /// Spawn agents to fix bugs
@@ -73,9 +176,9 @@ enum Commands {
         #[arg(short, long)]
         repo_path: PathBuf,
 
-        /// Max bugs to fix
-        #[arg(short, long, default_value = "3")]
-        max_bugs: u8,
+        /// Max bugs to fix (default from config)
+        #[arg(short, long)]
+        max_bugs: Option<u8>,
     },
 
     /// Spawn agents for chores/tech debt
@@ -84,9 +187,23 @@ enum Commands {
         #[arg(short, long)]
         repo_path: PathBuf,
 
-        /// Max chores to resolve
-        #[arg(short, long, default_value = "5")]
-        max_chores: u8,
+        /// Max chores to resolve (default from config)
+        #[arg(short, long)]
+        max_chores: Option<u8>,
+    },
+
+    /// Run a workflow defined in config (built-in or user-defined)
+    Run {
+        /// Repository path
+        #[arg(short, long)]
+        repo_path: PathBuf,
+
+        /// Workflow name, a `[workflows.<name>]` table in config
+        workflow: String,
+
+        /// Max agents to spawn (default from config)
+        #[arg(short, long)]
+        max_agents: Option<u8>,
     },
 
     /// Spawn agent with custom task description
@@ -100,6 +217,29 @@ enum Commands {
         task: String,
     },
 
+    /// Spawn an agent to write a refactoring plan for a module (uses agents/refactor-plan)
+    RefactorPlan {
+        /// Repository path
+        #[arg(short, long)]
+        repo_path: PathBuf,
+
+        /// File or directory to plan for, relative to the repository
+        #[arg(long)]
+        path: String,
+
+        /// Attach file sizes and function counts measured locally
+        #[arg(long)]
+        context: bool,
+
+        /// Cobertura XML report to take line coverage from (implies --context)
+        #[arg(long)]
+        coverage: Option<PathBuf>,
+
+        /// Print the prompt instead of spawning an agent
+        #[arg(long)]
+        dry_run: bool,
+    },
+
     /// Approve all pending workflow runs
     Approve {
         /// Repository path
@@ -164,30 +304,259 @@ enum Commands {
         /// Close conflicting PRs instead of commenting
         #[arg(long)]
         close: bool,
+
+        /// Try rebasing each PR onto its base branch first, and only comment
+        /// on or close those that really conflict
+        #[arg(long)]
+        rebase: bool,
+    },
+
+    /// Merge green agent PRs that close only issues chore-bot dispatched
+    Merge {
+        /// Repository path
+        #[arg(short, long)]
+        repo_path: PathBuf,
+
+        /// Show what would be merged without merging
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Max PRs to merge (default from config)
+        #[arg(short, long)]
+        max_merges: Option<u32>,
     },
+
+    /// Reconcile spawned agent tasks with their issues and PRs
+    #[command(alias = "reconcile")]
+    Status {
+        /// Repository path
+        #[arg(short, long)]
+        repo_path: PathBuf,
+
+        /// Output format
+        #[arg(short, long, value_enum, default_value = "console")]
+        output: StatusOutput,
+
+        /// Hours after which a task without a PR counts as stale when the
+        /// forge no longer lists it
+        #[arg(long, default_value = "6")]
+        stale_hours: u64,
+    },
+
+    /// Run commands across every repository in a manifest
+    Sweep {
+        /// Manifest listing the repositories (see repos.toml)
+        #[arg(short, long)]
+        manifest: PathBuf,
+
+        /// Only sweep this repository (owner/name, repeatable)
+        #[arg(long = "repo")]
+        repos: Vec<String>,
+
+        /// Run these commands instead of the manifest's (comma-separated)
+        #[arg(long, value_delimiter = ',')]
+        only: Vec<Step>,
+    },
+
+    /// Keep running, sweeping each manifest command on its `[schedules]` cron
+    Daemon {
+        /// Manifest listing the repositories and schedules (see repos.toml)
+        #[arg(short, long)]
+        manifest: PathBuf,
+
+        /// Only sweep this repository (owner/name, repeatable)
+        #[arg(long = "repo")]
+        repos: Vec<String>,
+    },
+
+    /// Serve GitHub webhooks, reacting to each event instead of polling
+    /// (secret from CHORE_BOT_WEBHOOK_SECRET)
+    Webhook {
+        /// Repository path
+        #[arg(short, long, default_value = ".")]
+        repo_path: PathBuf,
+
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8787")]
+        addr: String,
+    },
+
+    /// Inspect the prompt templates workflows resolve to
+    Prompts {
+        /// Repository path
+        #[arg(short, long, default_value = ".")]
+        repo_path: PathBuf,
+
+        #[command(subcommand)]
+        action: PromptsAction,
+    },
+}
+
+/// Token budget `prompts check` enforces unless told otherwise
+const DEFAULT_MAX_TOKENS: usize = 4000;
+
+#[derive(Subcommand, Debug)]
+enum PromptsAction {
+    /// List every template and where it resolves from
+    List,
+
+    /// Print a template and its source
+    Show {
+        /// Template name or workflow name
+        name: String,
+    },
+
+    /// Check every template against the variables its workflow provides
+    Check {
+        /// Fail when a rendered prompt exceeds this many (estimated) tokens
+        #[arg(long, default_value_t = DEFAULT_MAX_TOKENS)]
+        max_tokens: usize,
+    },
+}
+
+impl Commands {
+    /// Repository every command operates on
+    fn repo_path(&self) -> &Path {
+        match self {
+            Commands::Test { repo_path, .. }
+            | Commands::Feature { repo_path, .. }
+            | Commands::Bug { repo_path, .. }
+            | Commands::Chore { repo_path, .. }
+            | Commands::Run { repo_path, .. }
+            | Commands::Custom { repo_path, .. }
+            | Commands::RefactorPlan { repo_path, .. }
+            | Commands::Approve { repo_path }
+            | Commands::Coverage { repo_path, .. }
+            | Commands::Scan { repo_path, .. }
+            | Commands::CreateIssues { repo_path, .. }
+            | Commands::Nudge { repo_path }
+            | Commands::Conflicts { repo_path, .. }
+            | Commands::Merge { repo_path, .. }
+            | Commands::Status { repo_path, .. }
+            | Commands::Webhook { repo_path, .. }
+            | Commands::Prompts { repo_path, .. } => repo_path,
+            // Each manifest entry is configured from its own checkout
+            Commands::Sweep { .. } | Commands::Daemon { .. } => Path::new("."),
+        }
+    }
+}
+
+/// Apply a `--max-*` CLI override to a workflow's config
+fn override_max(config: &mut Config, workflow: &str, max: Option<u8>) -> Result<()> {
+    if let Some(max) = max {
+        config.workflow_mut(workflow)?.max_agents = max;
+    }
+    Ok(())
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
+    let state_file = args.state_file.unwrap_or_else(StateStore::default_path);
+    let mut state = StateStore::load(&state_file)?;
+    let mut config = Config::discover(args.config.as_deref(), args.command.repo_path())?;
+    config.prompts = PromptResolver::new(args.prompts_dir.as_deref(), args.command.repo_path());
 
     match args.command {
         Commands::Test {
             repo_path,
             max_prs,
             batch_size,
-        } => run_test(&repo_path, max_prs, batch_size)?,
-        Commands::Feature { repo_path, max_prs } => run_feature(&repo_path, max_prs)?,
+        } => {
+            override_max(&mut config, "test", max_prs)?;
+            if let Some(size) = batch_size {
+                let test = config.workflow_mut("test")?;
+                test.batching = Batching::Fixed;
+                test.batch_size = size;
+            }
+            run_workflow(
+                &*open_forge(args.forge, &repo_path, None)?,
+                &mut state,
+                &config,
+                &repo_path,
+                "test",
+            )?;
+        }
+        Commands::Feature { repo_path, max_prs } => {
+            override_max(&mut config, "feature", max_prs)?;
+            run_workflow(
+                &*open_forge(args.forge, &repo_path, None)?,
+                &mut state,
+                &config,
+                &repo_path,
+                "feature",
+            )?;
+        }
         Commands::Bug {
             repo_path,
             max_bugs,
-        } => run_bug(&repo_path, max_bugs)?,
+        } => {
+            override_max(&mut config, "bug", max_bugs)?;
+            run_workflow(
+                &*open_forge(args.forge, &repo_path, None)?,
+                &mut state,
+                &config,
+                &repo_path,
+                "bug",
+            )?;
+        }
         Commands::Chore {
             repo_path,
             max_chores,
-        } => run_chore(&repo_path, max_chores)?,
-        Commands::Custom { repo_path, task } => run_custom(&repo_path, &task)?,
-        Commands::Approve { repo_path } => run_approve(&repo_path)?,
+        } => {
+            override_max(&mut config, "chore", max_chores)?;
+            run_workflow(
+                &*open_forge(args.forge, &repo_path, None)?,
+                &mut state,
+                &config,
+                &repo_path,
+                "chore",
+            )?;
+        }
+        Commands::Run {
+            repo_path,
+            workflow,
+            max_agents,
+        } => {
+            override_max(&mut config, &workflow, max_agents)?;
+            run_workflow(
+                &*open_forge(args.forge, &repo_path, None)?,
+                &mut state,
+                &config,
+                &repo_path,
+                &workflow,
+            )?;
+        }
+        Commands::Custom { repo_path, task } => {
+            run_custom(&*open_forge(args.forge, &repo_path, None)?, &config, &task)?
+        }
+        Commands::RefactorPlan {
+            repo_path,
+            path,
+            context,
+            coverage,
+            dry_run,
+        } => {
+            let files = if context || coverage.is_some() {
+                analysis::collect(&repo_path, &path, coverage.as_deref())?
+            } else {
+                Vec::new()
+            };
+            let task = render_refactor_plan(&config, &path, &files)?;
+            if dry_run {
+                println!("{task}");
+            } else {
+                run_refactor_plan(
+                    &*open_forge(args.forge, &repo_path, None)?,
+                    &config,
+                    &path,
+                    &task,
+                )?
+            }
+        }
+        Commands::Approve { repo_path } => {
+            run_approve(&*open_forge(args.forge, &repo_path, None)?, &config)?
+        }
         Commands::Coverage {
             repo_path,
             threshold,
@@ -199,259 +568,388 @@ async fn main() -> Result<()> {
             dry_run,
         } => run_scan(&repo_path, create_issues, dry_run)?,
         Commands::CreateIssues { repo_path, batch } => run_create_issues(&repo_path, &batch)?,
-        Commands::Nudge { repo_path } => run_nudge(&repo_path)?,
-        Commands::Conflicts { repo_path, close } => run_conflicts(&repo_path, close)?,
+        Commands::Nudge { repo_path } => run_nudge(
+            &*open_forge(args.forge, &repo_path, None)?,
+            &mut state,
+            &config,
+        )?,
+        Commands::Conflicts {
+            repo_path,
+            close,
+            rebase,
+        } => {
+            run_conflicts(
+                &*open_forge(args.forge, &repo_path, None)?,
+                &mut state,
+                &config,
+                &repo_path,
+                close,
+                rebase,
+            )?;
+        }
+        Commands::Merge {
+            repo_path,
+            dry_run,
+            max_merges,
+        } => {
+            if let Some(max) = max_merges {
+                config.merge.max_per_run = max;
+            }
+            run_merge(
+                &*open_forge(args.forge, &repo_path, None)?,
+                &state,
+                &config,
+                dry_run,
+            )?;
+        }
+        Commands::Status {
+            repo_path,
+            output,
+            stale_hours,
+        } => run_status(
+            &*open_forge(args.forge, &repo_path, None)?,
+            &mut state,
+            &config,
+            output,
+            stale_hours,
+        )?,
+        Commands::Sweep {
+            manifest,
+            repos,
+            only,
+        } => {
+            let manifest = Manifest::load(&manifest)?;
+            let summary = run_sweep(
+                &manifest,
+                manifest.plan(&repos, &only)?,
+                &mut state,
+                args.config.as_deref(),
+                args.prompts_dir.as_deref(),
+                &|path, name| open_forge(args.forge, path, Some(name)),
+            )?;
+            if summary.failures() > 0 {
+                anyhow::bail!("{} sweep steps failed", summary.failures());
+            }
+        }
+        Commands::Daemon { manifest, repos } => {
+            let manifest = Manifest::load(&manifest)?;
+            manifest.plan(&repos, &[])?;
+            let now = state::unix_now();
+            let mut scheduler = Scheduler::new(
+                manifest.schedules()?,
+                manifest.jitter_secs,
+                now ^ u64::from(std::process::id()),
+                now,
+            )?;
+
+            println!(
+                "🕰️  Daemon started for {} repositories",
+                manifest.repos.len()
+            );
+            for (step, cron) in manifest.schedules()? {
+                println!("   {step}: {cron}");
+            }
+
+            daemon::run(
+                &SystemClock,
+                &mut scheduler,
+                daemon::shutdown_signal(),
+                |steps| {
+                    run_sweep(
+                        &manifest,
+                        manifest.plan(&repos, steps)?,
+                        &mut state,
+                        args.config.as_deref(),
+                        args.prompts_dir.as_deref(),
+                        &|path, name| open_forge(args.forge, path, Some(name)),
+                    )?;
+                    state.save()
+                },
+            )
+            .await;
+        }
+        Commands::Webhook { repo_path, addr } => {
+            let secret = std::env::var("CHORE_BOT_WEBHOOK_SECRET")
+                .context("CHORE_BOT_WEBHOOK_SECRET must be set to the webhook's secret")?;
+            let forge = open_forge(args.forge, &repo_path, None)?;
+            let listener = Listener::bind(&addr, &secret)?;
+            let bound = listener.local_addr().map_or(addr, |a| a.to_string());
+            println!("📡 Listening for webhooks on {bound}\n");
+            listener.serve(&mut |delivery| {
+                handle_delivery(&*forge, &mut state, &config, &repo_path, delivery)
+            })?;
+        }
+        Commands::Prompts { action, .. } => run_prompts(&config, &action)?,
     }
 
     Ok(())
 }
 
-/// Spawn agents to handle testing issues
-fn run_test(repo_path: &Path, max_prs: u8, batch_size: Option<u8>) -> Result<()> {
-    println!("🧪 Test Workflow\n");
+/// Banner printed when a workflow starts
+fn workflow_banner(name: &str) -> String {
+    let icon = match name {
+        "test" => "🧪",
+        "feature" => "🚀",
+        "bug" => "🐛",
+        "chore" => "🧹",
+        _ => "🤖",
+    };
+    let mut chars = name.chars();
+    let title: String = chars
+        .next()
+        .map(|c| c.to_uppercase().chain(chars).collect())
+        .unwrap_or_default();
+    format!("{icon} {title} Workflow")
+}
 
-    let all_issues = subagent::list_issues_by_label(repo_path, "testing")?;
-    let issues_with_prs = subagent::list_issues_with_open_prs(repo_path)?;
-    let issues: Vec<_> = all_issues
+/// Spawn agents for the open issues matching a configured workflow, returning
+/// how many spawned
+fn run_workflow(
+    forge: &dyn Forge,
+    state: &mut StateStore,
+    config: &Config,
+    repo_path: &Path,
+    name: &str,
+) -> Result<usize> {
+    println!("{}\n", workflow_banner(name));
+
+    let workflow = config.workflow(name)?;
+    let all_issues: Vec<_> = forge
+        .list_issues_by_label(&workflow.label)?
         .into_iter()
-        .filter(|n| !issues_with_prs.contains(n))
+        .filter(|issue| !workflow.excludes(&issue.labels))
         .collect();
+    let mut issues = pending_issues(forge, state, config, all_issues)?;
 
     if issues.is_empty() {
-        println!("No testing issues without open PRs found.");
-        return Ok(());
+        println!("No {} issues without open PRs found.", workflow.label);
+        return Ok(0);
     }
 
     println!("Found {} issues without PRs", issues.len());
 
-    // Build batches based on mode
-    let batches: Vec<(String, Vec<(u32, String)>)> = if let Some(size) = batch_size {
-        // Batch by fixed size
-        let mut issues_with_titles: Vec<(u32, String)> = Vec::new();
-        for &issue_num in &issues {
-            if let Some(title) = subagent::fetch_issue_title(repo_path, issue_num)? {
-                issues_with_titles.push((issue_num, title));
-            }
-        }
-        issues_with_titles
-            .chunks(size as usize)
-            .enumerate()
-            .map(|(i, chunk)| (format!("batch-{}", i + 1), chunk.to_vec()))
-            .collect()
-    } else {
-        // Batch by module (default)
-        subagent::group_by_module(repo_path, &issues)?
-    };
+    let now = state::unix_now();
+    priority::rank(&mut issues, &config.priority, now);
+    let tree = SourceTree::scan(repo_path);
+    let mut batches = subagent::batch(workflow, issues, &tree);
+    priority::rank_batches(&mut batches, &config.priority, now);
 
-    println!("Grouped into {} batches\n", batches.len());
-    for (name, issues) in &batches {
-        println!("  {}: {} issues", name, issues.len());
+    if workflow.batching != Batching::Single {
+        println!("Grouped into {} batches\n", batches.len());
+        for (name, issues) in &batches {
+            println!("  {}: {} issues", name, issues.len());
+        }
     }
     println!();
 
-    let mut spawned = 0;
-    for (batch_name, batch) in batches.into_iter().take(max_prs as usize) {
-        println!(
-            "🤖 Spawning agent for {} ({} issues)...",
-            batch_name,
-            batch.len()
-        );
-
-        let issue_list: String = batch
-            .iter()
-            .map(|(num, title)| format!("- #{num}: {title}\n"))
-            .collect();
-
-        let closes: Vec<_> = batch.iter().map(|(n, _)| format!("closes #{n}")).collect();
-        let closes_str = closes.join(", ");
-        let module_snake = batch_name.replace('-', "_");
-        let count = batch.len().to_string();
-
-        let template = load_prompt("test")?;
-        let task = render_template(
-            &template,
-            &[
-                ("module", &batch_name),
-                ("issue_list", &issue_list),
-                ("module_snake", &module_snake),
-                ("closes_str", &closes_str),
-                ("count", &count),
-            ],
-        );
-
-        let result = subagent::spawn_agent(repo_path, &task)?;
-        if result.success {
-            println!("   ✅ Spawned");
-            spawned += 1;
-        } else {
-            println!("   ❌ Failed: {}", result.message);
-        }
-    }
+    let batches = schedule_batches(forge, &tree, batches, workflow.max_agents as usize)?;
+    let spawned = spawn_batches(forge, state, config, name, batches)?;
 
     println!("\n✅ Spawned {spawned} agents");
-    println!("Monitor: gh agent-task list");
+    println!("Monitor: chore-bot status");
 
-    Ok(())
+    Ok(spawned)
 }
 
-fn run_feature(repo_path: &Path, max_prs: u8) -> Result<()> {
-    println!("🚀 Feature Workflow\n");
-
-    let all_issues = subagent::list_issues_by_label(repo_path, "enhancement")?;
-    let issues_with_prs = subagent::list_issues_with_open_prs(repo_path)?;
-    let issues: Vec<_> = all_issues
-        .into_iter()
-        .filter(|n| !issues_with_prs.contains(n))
-        .collect();
-
-    if issues.is_empty() {
-        println!("No enhancement issues without open PRs found.");
-        return Ok(());
+/// Pick up to `limit` batches that won't touch the same files as each other
+/// or an open PR, reporting the ones deferred to a later run
+fn schedule_batches(
+    forge: &dyn Forge,
+    tree: &SourceTree,
+    batches: Vec<subagent::IssueBatch>,
+    limit: usize,
+) -> Result<Vec<subagent::IssueBatch>> {
+    let schedule = schedule::plan(batches, &forge.list_open_prs()?, tree, limit);
+
+    for deferred in &schedule.deferred {
+        println!(
+            "⏸️  Deferring {}: {} is already touched by {}",
+            deferred.name, deferred.file, deferred.claim
+        );
+    }
+    if !schedule.deferred.is_empty() {
+        println!();
     }
 
-    println!("Found {} issues without PRs\n", issues.len());
+    Ok(schedule.ready)
+}
 
-    let mut spawned = 0;
-    for issue in issues.into_iter().take(max_prs as usize) {
-        let (title, body) = match subagent::fetch_issue(repo_path, issue)? {
-            Some(details) => details,
-            None => continue,
-        };
+/// Render the workflow's prompt for each batch and spawn an agent per batch,
+/// returning how many spawned
+fn spawn_batches(
+    forge: &dyn Forge,
+    state: &mut StateStore,
+    config: &Config,
+    name: &str,
+    batches: impl IntoIterator<Item = subagent::IssueBatch>,
+) -> Result<usize> {
+    let workflow = config.workflow(name)?;
+    let template = config.prompts.resolve(&workflow.prompt)?.text;
+    let mut budget = Budget::measure(forge, &config.budget)?;
 
-        let issue_str = issue.to_string();
-        let template = load_prompt("feature")?;
-        let task = render_template(
-            &template,
-            &[("issue", &issue_str), ("title", &title), ("body", &body)],
-        );
+    let mut spawned = 0;
+    for (batch_name, batch) in batches {
+        if budget.remaining() == 0 {
+            println!("⏸️  Concurrency budget reached ({budget}); leaving the rest for a later run");
+            break;
+        }
 
-        println!("🤖 Spawning agent for #{issue}: {title}...");
-        let result = subagent::spawn_agent(repo_path, &task)?;
+        match batch.as_slice() {
+            [issue] => println!(
+                "🤖 Spawning agent for #{}: {}...",
+                issue.number, issue.title
+            ),
+            _ => println!(
+                "🤖 Spawning agent for {} ({} issues)...",
+                batch_name,
+                batch.len()
+            ),
+        }
 
+        let context = subagent::batch_context(
+            workflow.batching,
+            &batch_name,
+            &batch,
+            &config.verify_command,
+        );
+        let task = template::render(&template, &context)
+            .with_context(|| format!("Failed to render prompt {:?}", workflow.prompt))?;
+        let numbers: Vec<u32> = batch.iter().map(|issue| issue.number).collect();
+        let result = spawn_tracked(forge, state, name, &numbers, &task)?;
         if result.success {
             println!("   ✅ Spawned");
             spawned += 1;
+            budget.spend();
         } else {
             println!("   ❌ Failed: {}", result.message);
         }
     }
 
-    println!("\n✅ Spawned {spawned} agents");
-    println!("Monitor: gh agent-task list");
-
-    Ok(())
+    Ok(spawned)
 }
 
-fn run_bug(repo_path: &Path, max_bugs: u8) -> Result<()> {
-    println!("🐛 Bug Workflow\n");
+fn run_custom(forge: &dyn Forge, config: &Config, task: &str) -> Result<()> {
+    println!("🎯 Custom Workflow\n");
 
-    let issues = subagent::list_issues_by_label(repo_path, "bug")?;
+    Budget::measure(forge, &config.budget)?.check()?;
+    let result = forge.spawn_agent(task)?;
 
-    if issues.is_empty() {
-        println!("No bug issues found.");
-        return Ok(());
+    if result.success {
+        println!("✅ Agent spawned");
+    } else {
+        println!("❌ Failed: {}", result.message);
     }
 
-    println!("Found {} bugs\n", issues.len());
+    Ok(())
+}
 
-    for issue in issues.into_iter().take(max_bugs as usize) {
-        let (title, body) = match subagent::fetch_issue(repo_path, issue)? {
-            Some(details) => details,
-            None => continue,
-        };
+/// Where the refactor-plan agent is asked to write its issue-creator batch
+fn plan_file(path: &str) -> String {
+    let name: String = path
+        .trim_matches('/')
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '-' })
+        .collect();
+    format!(".chore-bot/plans/{name}.json")
+}
 
-        let issue_str = issue.to_string();
-        let template = load_prompt("bug")?;
-        let task = render_template(
-            &template,
-            &[("issue", &issue_str), ("title", &title), ("body", &body)],
-        );
+/// Render the refactor-plan prompt for `path`, with local metrics if measured
+fn render_refactor_plan(
+    config: &Config,
+    path: &str,
+    files: &[analysis::FileStats],
+) -> Result<String> {
+    let template = config.prompts.resolve("refactor-plan")?.text;
+    let context = analysis::refactor_context(path, &plan_file(path), files);
+    template::render(&template, &context).context("Failed to render refactor-plan prompt")
+}
 
-        println!("Spawning agent for bug #{issue}...");
-        let result = subagent::spawn_agent(repo_path, &task)?;
+fn run_refactor_plan(forge: &dyn Forge, config: &Config, path: &str, task: &str) -> Result<()> {
+    println!("🗺️ Refactor Plan Workflow\n");
+    Budget::measure(forge, &config.budget)?.check()?;
+    println!("🤖 Spawning planning agent for {path}...");
 
-        if result.success {
-            println!("✅ Spawned");
-        } else {
-            println!("❌ Failed: {}", result.message);
-        }
+    let result = forge.spawn_agent(task)?;
+
+    if result.success {
+        println!("   ✅ Spawned");
+        println!(
+            "\nOnce merged, file the plan with: chore-bot create-issues --batch {}",
+            plan_file(path)
+        );
+    } else {
+        println!("   ❌ Failed: {}", result.message);
     }
 
     Ok(())
 }
 
-fn run_chore(repo_path: &Path, max_chores: u8) -> Result<()> {
-    println!("🧹 Chore Workflow\n");
+fn run_approve(forge: &dyn Forge, config: &Config) -> Result<()> {
+    println!("✅ Approving Pending Workflows\n");
 
-    let issues = subagent::list_issues_by_label(repo_path, "chore")?;
+    let results = subagent::approve_pending_workflows(forge, &config.approve)?;
 
-    if issues.is_empty() {
-        println!("No chore issues found.");
+    if results.is_empty() {
+        println!("No pending workflows to approve.");
         return Ok(());
     }
 
-    println!("Found {} chores\n", issues.len());
-
-    for issue in issues.into_iter().take(max_chores as usize) {
-        let (title, body) = match subagent::fetch_issue(repo_path, issue)? {
-            Some(details) => details,
-            None => continue,
-        };
-
-        let issue_str = issue.to_string();
-        let template = load_prompt("chore")?;
-        let task = render_template(
-            &template,
-            &[("issue", &issue_str), ("title", &title), ("body", &body)],
-        );
-
-        println!("Spawning agent for chore #{issue}...");
-        let result = subagent::spawn_agent(repo_path, &task)?;
-
-        if result.success {
-            println!("✅ Spawned");
-        } else {
-            println!("❌ Failed: {}", result.message);
+    for (run, approval) in &results {
+        let run_name = format!("run {} ({} by @{})", run.id, run.workflow_path, run.actor);
+        match approval {
+            Approval::Approved => println!("✅ Approved {run_name}"),
+            Approval::Failed => println!("❌ Failed to approve {run_name}"),
+            Approval::Skipped(reason) => println!("⏭️  Skipped {run_name}: {reason}"),
         }
     }
 
+    let approved = results
+        .iter()
+        .filter(|(_, approval)| *approval == Approval::Approved)
+        .count();
+    println!("\n✅ Approved {}/{} workflows", approved, results.len());
+
     Ok(())
 }
 
-fn run_custom(repo_path: &Path, task: &str) -> Result<()> {
-    println!("🎯 Custom Workflow\n");
-
-    let result = subagent::spawn_agent(repo_path, task)?;
-
-    if result.success {
-        println!("✅ Agent spawned");
+fn run_merge(forge: &dyn Forge, state: &StateStore, config: &Config, dry_run: bool) -> Result<()> {
+    if dry_run {
+        println!("🔀 Merging Green Agent PRs (dry run)\n");
     } else {
-        println!("❌ Failed: {}", result.message);
+        println!("🔀 Merging Green Agent PRs\n");
     }
 
-    Ok(())
-}
-
-fn run_approve(repo_path: &Path) -> Result<()> {
-    println!("✅ Approving Pending Workflows\n");
-
-    let results = subagent::approve_pending_workflows(repo_path)?;
+    let results = subagent::merge_green_prs(forge, state, config, dry_run)?;
 
     if results.is_empty() {
-        println!("No pending workflows to approve.");
+        println!("No open agent PRs found.");
         return Ok(());
     }
 
-    for (run_id, success) in &results {
-        if *success {
-            println!("✅ Approved run {run_id}");
-        } else {
-            println!("❌ Failed to approve run {run_id}");
+    for (pr, merge) in &results {
+        let pr_name = format!("#{}: {}", pr.number, pr.title);
+        match merge {
+            Merge::Merged => println!("✅ Merged {pr_name}"),
+            Merge::Ready => println!("🔍 Would merge {pr_name}"),
+            Merge::Failed => println!("❌ Failed to merge {pr_name}"),
+            Merge::Skipped(reason) => println!("⏭️  Skipped {pr_name}: {reason}"),
         }
     }
 
-    let approved = results.iter().filter(|(_, s)| *s).count();
-    println!("\n✅ Approved {}/{} workflows", approved, results.len());
+    let merged = results
+        .iter()
+        .filter(|(_, merge)| matches!(merge, Merge::Merged | Merge::Ready))
+        .count();
+    if dry_run {
+        println!("\n🔍 Would merge {}/{} agent PRs", merged, results.len());
+    } else {
+        println!(
+            "\n✅ Merged {}/{} agent PRs ({})",
+            merged,
+            results.len(),
+            config.merge.strategy.as_str()
+        );
+    }
 
     Ok(())
 }
@@ -548,10 +1046,10 @@ fn run_create_issues(repo_path: &Path, batch: &Path) -> Result<()> {
     Ok(())
 }
 
-fn run_nudge(repo_path: &Path) -> Result<()> {
+fn run_nudge(forge: &dyn Forge, state: &mut StateStore, config: &Config) -> Result<()> {
     println!("💬 Nudge PRs with Failing Pipelines\n");
 
-    let failing_prs = subagent::list_failing_prs(repo_path)?;
+    let failing_prs = subagent::list_failing_prs(forge, &config.links)?;
 
     if failing_prs.is_empty() {
         println!("✅ No PRs with failing pipelines found!");
@@ -560,24 +1058,12 @@ fn run_nudge(repo_path: &Path) -> Result<()> {
 
     println!("Found {} PRs with failing checks:\n", failing_prs.len());
 
-    let comment = r#"@copilot This PR has failing CI checks.
-
-Please take a look at the build failures and push a fix. Common issues:
-- Compilation errors
-- Test failures  
-- Linting/formatting issues
-
-Run `make run-guidelines` locally to verify before pushing."#;
-
     let mut commented = 0;
     for pr in &failing_prs {
         println!("  #{}: {} (by @{})", pr.number, pr.title, pr.author);
 
-        if subagent::comment_on_pr(repo_path, pr.number, comment)? {
-            println!("     ✅ Commented");
+        if nudge_pr(forge, state, config, pr)? {
             commented += 1;
-        } else {
-            println!("     ❌ Failed to comment");
         }
     }
 
@@ -586,14 +1072,136 @@ Run `make run-guidelines` locally to verify before pushing."#;
     Ok(())
 }
 
-fn run_conflicts(repo_path: &Path, close: bool) -> Result<()> {
+/// Nudge a failing PR unless it was already nudged for its head commit, or
+/// escalate it once nudging stops helping. Returns whether a comment was
+/// posted.
+fn nudge_pr(
+    forge: &dyn Forge,
+    state: &mut StateStore,
+    config: &Config,
+    pr: &subagent::FailingPr,
+) -> Result<bool> {
+    let comments = forge.list_pr_comments(pr.number)?;
+    let nudges = match nudge::decide(&comments, &pr.head_sha, &config.nudge) {
+        nudge::Action::AlreadyNudged => {
+            println!("     ⏭️  Already nudged for this commit");
+            return Ok(false);
+        }
+        nudge::Action::AlreadyEscalated => {
+            println!("     ⏭️  Escalated earlier, left to a human");
+            return Ok(false);
+        }
+        nudge::Action::Nudge { previous } => {
+            let comment = nudge::mark_nudge(&nudge_comment(forge, config, pr)?, &pr.head_sha);
+            let posted = forge.comment_on_pr(pr.number, &comment)?;
+            if posted {
+                println!("     ✅ Commented (nudge {})", previous + 1);
+            } else {
+                println!("     ❌ Failed to comment");
+            }
+            return Ok(posted);
+        }
+        nudge::Action::Escalate { nudges } => nudges,
+    };
+
+    let policy = &config.nudge;
+    let mentions = match policy.escalation {
+        Escalation::Ping => policy
+            .ping
+            .iter()
+            .map(|who| format!("@{}", who.trim_start_matches('@')))
+            .collect::<Vec<_>>()
+            .join(" "),
+        _ => String::new(),
+    };
+    let comment = template::render(
+        &policy.escalation_comment,
+        &json!({ "nudges": nudges, "mentions": mentions }),
+    )
+    .context("Failed to render escalation comment")?;
+    let posted = forge.comment_on_pr(pr.number, &nudge::mark_escalation(&comment))?;
+    println!("     ⚠️  Still failing after {nudges} nudges, escalating");
+
+    match policy.escalation {
+        Escalation::Label => {
+            if forge.add_label(pr.number, &policy.escalation_label)? {
+                println!("     🏷️  Labelled {}", policy.escalation_label);
+            } else {
+                println!("     ❌ Failed to add label {}", policy.escalation_label);
+            }
+        }
+        Escalation::Ping => println!("     📣 Pinged {mentions}"),
+        Escalation::Close => {
+            if forge.close_pr(pr.number)? {
+                // The next workflow run picks the issues up again
+                state.forget(&forge.repo_name()?, &pr.linked_issues)?;
+                println!(
+                    "     🔒 Closed, issues {:?} are free to respawn",
+                    pr.linked_issues
+                );
+            } else {
+                println!("     ❌ Failed to close");
+            }
+        }
+    }
+
+    Ok(posted)
+}
+
+/// Comment asking an agent to fix its failing checks, quoting what each
+/// check's job log says went wrong
+fn nudge_comment(forge: &dyn Forge, config: &Config, pr: &subagent::FailingPr) -> Result<String> {
+    let mut checks = Vec::new();
+    for check in &pr.failed_checks {
+        let log = match check.job_id.map(|id| forge.fetch_job_log(id)) {
+            Some(Ok(log)) => log,
+            Some(Err(e)) => {
+                println!("     ⚠️  Could not fetch the {} log: {e:#}", check.name);
+                None
+            }
+            None => None,
+        };
+        let mut context =
+            serde_json::to_value(log.as_deref().map(ci_log::extract).unwrap_or_default())?;
+        context["name"] = json!(check.name);
+        checks.push(context);
+    }
+
+    template::render(
+        &config.nudge.comment,
+        &json!({ "verify_command": config.verify_command, "checks": checks }),
+    )
+    .context("Failed to render nudge comment")
+}
+
+/// Comment asking an agent to rebase its conflicting PR, naming the files a
+/// rebase attempt conflicted in if there was one
+fn conflicts_comment(config: &Config, conflicted_files: &[String]) -> Result<String> {
+    template::render(
+        &config.conflicts.comment,
+        &json!({
+            "verify_command": config.verify_command,
+            "conflicted_files": conflicted_files,
+        }),
+    )
+    .context("Failed to render conflicts comment")
+}
+
+fn run_conflicts(
+    forge: &dyn Forge,
+    state: &mut StateStore,
+    config: &Config,
+    repo_path: &Path,
+    close: bool,
+    rebase: bool,
+) -> Result<usize> {
     println!("🔀 Handle PRs with Merge Conflicts\n");
 
-    let conflicting_prs = subagent::list_conflicting_prs(repo_path)?;
+    let mut conflicting_prs = subagent::list_conflicting_prs(forge, &config.links)?;
 
     if conflicting_prs.is_empty() {
         println!("✅ No PRs with merge conflicts found!");
-        return Ok(());
+        return Ok(0);
     }
 
     println!(
@@ -601,84 +1209,121 @@ fn run_conflicts(repo_path: &Path, close: bool) -> Result<()> {
         conflicting_prs.len()
     );
 
+    // Files each PR conflicted in when rebased
+    let mut conflicted_files: HashMap<u32, Vec<String>> = HashMap::new();
+    if rebase {
+        conflicting_prs.retain(|pr| {
+            println!("  #{}: {} (by @{})", pr.number, pr.title, pr.author);
+            match rebase::rebase_branch(repo_path, &pr.head_ref, &pr.base_ref) {
+                Ok(rebase::Outcome::Rebased { head }) => {
+                    println!(
+                        "     ♻️  Rebased onto {} and pushed {:.7}",
+                        pr.base_ref, head
+                    );
+                    false
+                }
+                Ok(rebase::Outcome::UpToDate) => {
+                    println!("     ✅ Already up to date with {}", pr.base_ref);
+                    false
+                }
+                Ok(rebase::Outcome::Conflicted { files }) => {
+                    println!("     💥 Conflicts in {}", files.join(", "));
+                    conflicted_files.insert(pr.number, files);
+                    true
+                }
+                // Still conflicting as far as anyone knows, so it's handled
+                // like one that conflicted
+                Err(e) => {
+                    println!("     ⚠️  Could not rebase: {e:#}");
+                    true
+                }
+            }
+        });
+
+        if conflicting_prs.is_empty() {
+            println!("\n✅ No PRs left with real conflicts");
+            return Ok(0);
+        }
+        println!(
+            "\n{} PRs still conflict after rebasing:\n",
+            conflicting_prs.len()
+        );
+    }
+
     let mut handled = 0;
-    let mut issues_to_respawn: Vec<u32> = Vec::new();
+    let mut spawned = 0;
+    // Linked issues of closed PRs, with the workflow each was last spawned
+    // through and the title of the PR that covered it
+    let mut issues_to_respawn: Vec<(u32, Option<String>, &str)> = Vec::new();
 
     if close {
         // Close conflicting PRs and collect linked issues
+        let repo = forge.repo_name()?;
         for pr in &conflicting_prs {
             println!("  #{}: {} (by @{})", pr.number, pr.title, pr.author);
             if !pr.linked_issues.is_empty() {
                 println!("     📎 Linked issues: {:?}", pr.linked_issues);
             }
 
-            if subagent::close_pr(repo_path, pr.number)? {
+            if forge.close_pr(pr.number)? {
                 println!("     ✅ Closed");
                 handled += 1;
-                // Collect linked issues for respawning
-                issues_to_respawn.extend(&pr.linked_issues);
+                // Collect linked issues for respawning; the closed PR no longer
+                // covers them, so earlier spawns shouldn't block the respawn
+                for &issue in &pr.linked_issues {
+                    if issues_to_respawn.iter().all(|(n, ..)| *n != issue) {
+                        let recorded = state.latest(&repo, issue).map(|r| r.workflow.clone());
+                        issues_to_respawn.push((issue, recorded, &pr.title));
+                    }
+                }
+                state.forget(&repo, &pr.linked_issues)?;
             } else {
                 println!("     ❌ Failed to close");
             }
         }
         println!("\n✅ Closed {}/{} PRs", handled, conflicting_prs.len());
 
-        // Respawn agents for linked issues
+        // Respawn agents for linked issues, each through the workflow it came from
         if !issues_to_respawn.is_empty() {
             println!(
                 "\n🔄 Respawning agents for {} linked issues...\n",
                 issues_to_respawn.len()
             );
 
-            let batches = subagent::group_by_module(repo_path, &issues_to_respawn)?;
-            let mut spawned = 0;
-
-            for (batch_name, batch) in batches {
-                println!("  📦 Module: {} ({} issues)", batch_name, batch.len());
-
-                let issue_list: String = batch
-                    .iter()
-                    .map(|(num, title)| format!("- #{num}: {title}\n"))
-                    .collect();
-
-                let closes: Vec<_> = batch.iter().map(|(n, _)| format!("closes #{n}")).collect();
-                let closes_str = closes.join(", ");
-                let module_snake = batch_name.replace('-', "_");
-                let count = batch.len().to_string();
-
-                let template = load_prompt("test")?;
-                let task = render_template(
-                    &template,
-                    &[
-                        ("module", &batch_name),
-                        ("issue_list", &issue_list),
-                        ("module_snake", &module_snake),
-                        ("closes_str", &closes_str),
-                        ("count", &count),
-                    ],
-                );
-
-                let result = subagent::spawn_agent(repo_path, &task)?;
-                if result.success {
-                    println!("     ✅ Spawned agent");
-                    spawned += 1;
-                } else {
-                    println!("     ❌ Failed: {}", result.message);
+            let mut by_workflow: BTreeMap<&str, Vec<forge::Issue>> = BTreeMap::new();
+            for (number, recorded, pr_title) in &issues_to_respawn {
+                let Some(issue) = forge.fetch_issue(*number)? else {
+                    continue;
+                };
+                match subagent::respawn_workflow(config, &issue, recorded.as_deref(), pr_title) {
+                    Some(name) => by_workflow.entry(name).or_default().push(issue),
+                    None => println!(
+                        "⚠️  #{number}: no workflow matches its labels, spawn history or PR title; not respawned"
+                    ),
                 }
             }
 
+            let now = state::unix_now();
+            let tree = SourceTree::scan(repo_path);
+            for (name, mut issues) in by_workflow {
+                priority::rank(&mut issues, &config.priority, now);
+                let mut batches = subagent::batch(config.workflow(name)?, issues, &tree);
+                priority::rank_batches(&mut batches, &config.priority, now);
+                let limit = config.workflow(name)?.max_agents as usize;
+                let batches = schedule_batches(forge, &tree, batches, limit)?;
+                spawned += spawn_batches(forge, state, config, name, batches)?;
+            }
+
             println!("\n✅ Respawned {} agents", spawned);
         }
     } else {
         // Comment asking to rebase
-        let comment = r#"@copilot This PR has merge conflicts.
-
-Please rebase on main and resolve the conflicts, then push again."#;
-
         for pr in &conflicting_prs {
             println!("  #{}: {} (by @{})", pr.number, pr.title, pr.author);
 
-            if subagent::comment_on_pr(repo_path, pr.number, comment)? {
+            let files = conflicted_files.remove(&pr.number).unwrap_or_default();
+            let comment = nudge::mark_conflicts(&conflicts_comment(config, &files)?, &pr.head_sha);
+            if forge.comment_on_pr(pr.number, &comment)? {
                 println!("     ✅ Commented");
                 handled += 1;
             } else {
@@ -692,5 +1337,1294 @@ Please rebase on main and resolve the conflicts, then push again."#;
         );
     }
 
+    Ok(spawned)
+}
+
+fn run_status(
+    forge: &dyn Forge,
+    state: &mut StateStore,
+    config: &Config,
+    output: StatusOutput,
+    stale_hours: u64,
+) -> Result<()> {
+    let reports = status::reconcile(
+        forge,
+        state,
+        &config.links,
+        state::unix_now(),
+        stale_hours * 3600,
+    )?;
+
+    match output {
+        StatusOutput::Json => println!("{}", status::generate_json_report(&reports)?),
+        StatusOutput::Markdown => println!("{}", status::generate_markdown_report(&reports)),
+        StatusOutput::Console => {
+            println!("📡 Agent Task Status\n");
+            if reports.is_empty() {
+                println!("No agent tasks recorded for this repository.");
+            } else {
+                status::print_console_report(&reports);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn run_prompts(config: &Config, action: &PromptsAction) -> Result<()> {
+    match action {
+        PromptsAction::List => {
+            println!("📝 Prompt Templates\n");
+            for name in config.prompts.names() {
+                let prompt = config.prompts.resolve(&name)?;
+                println!("  {:<16} {}", name, prompt.source());
+            }
+
+            println!("\nWorkflows:");
+            for (name, workflow) in &config.workflows {
+                let source = match config.prompts.resolve(&workflow.prompt) {
+                    Ok(prompt) => prompt.source(),
+                    Err(e) => format!("❌ {e}"),
+                };
+                println!("  {:<16} {} → {}", name, workflow.prompt, source);
+            }
+        }
+        PromptsAction::Show { name } => {
+            // Accept workflow names as well as template names
+            let prompt = config
+                .workflows
+                .get(name)
+                .map_or(name.as_str(), |workflow| workflow.prompt.as_str());
+            let prompt = config.prompts.resolve(prompt)?;
+            eprintln!("# {}: {}", prompt.name, prompt.source());
+            print!("{}", prompt.text);
+        }
+        PromptsAction::Check { max_tokens } => {
+            println!("🔍 Checking Prompt Templates\n");
+
+            let checks = lint::check_templates(config, *max_tokens);
+            for check in &checks {
+                let icon = if check.errors() > 0 { "❌" } else { "✅" };
+                println!(
+                    "  {} {} — {} (~{} tokens)",
+                    icon, check.name, check.source, check.tokens
+                );
+                for (severity, message) in &check.findings {
+                    let marker = match severity {
+                        lint::Severity::Error => "error",
+                        lint::Severity::Warning => "warning",
+                    };
+                    println!("     {marker}: {message}");
+                }
+            }
+
+            let errors: usize = checks.iter().map(|c| c.errors()).sum();
+            if errors > 0 {
+                anyhow::bail!("{errors} template errors found");
+            }
+            println!("\n✅ {} templates OK", checks.len());
+        }
+    }
+
     Ok(())
 }
+
+/// Run each repository's commands, carrying on past failures, and print an
+/// aggregated summary
+fn run_sweep(
+    manifest: &Manifest,
+    plan: Vec<(&RepoEntry, Vec<Step>)>,
+    state: &mut StateStore,
+    config_file: Option<&Path>,
+    prompts_dir: Option<&Path>,
+    open: &OpenForge<'_>,
+) -> Result<Summary> {
+    println!("🧹 Sweep of {} repositories\n", plan.len());
+
+    // Open every repository up front, so each one's `[budget]` total can
+    // count the agent PRs open in the others
+    let mut agent_prs = BTreeMap::new();
+    let opened: Vec<_> = plan
+        .into_iter()
+        .map(|(repo, steps)| {
+            let opened = open_repo(manifest, repo, config_file, prompts_dir, open).and_then(
+                |(repo_path, config, forge)| {
+                    let prs = budget::agent_prs(&*forge, &config.budget)?;
+                    agent_prs.insert(forge.repo_name()?, prs);
+                    Ok((repo_path, config, forge))
+                },
+            );
+            (repo, steps, opened)
+        })
+        .collect();
+
+    let mut summary = Summary::default();
+    for (repo, steps, opened) in opened {
+        println!("━━━ {} ━━━\n", repo.name);
+
+        let (repo_path, mut config, forge) = match opened {
+            Ok(opened) => opened,
+            Err(e) => {
+                println!("❌ {e:#}\n");
+                for step in &steps {
+                    summary.record(&repo.name, step, Err(anyhow::anyhow!("{e:#}")));
+                }
+                continue;
+            }
+        };
+        config.budget.other_agent_prs = agent_prs.clone();
+
+        for step in &steps {
+            let outcome = run_step(&*forge, state, &config, &repo_path, step);
+            if let Err(e) = &outcome {
+                println!("❌ {step} failed: {e:#}");
+            }
+            println!();
+            summary.record(&repo.name, step, outcome);
+        }
+    }
+
+    print!("{}", summary.render());
+    Ok(summary)
+}
+
+/// Check out a manifest repository and load its config and forge
+fn open_repo(
+    manifest: &Manifest,
+    repo: &RepoEntry,
+    config_file: Option<&Path>,
+    prompts_dir: Option<&Path>,
+    open: &OpenForge<'_>,
+) -> Result<(PathBuf, Config, Box<dyn Forge>)> {
+    let repo_path = manifest.checkout(repo)?;
+    let mut config = Config::discover(config_file, &repo_path)?
+        .with_overrides(toml::Value::Table(repo.config.clone()))
+        .with_context(|| format!("Invalid config overrides for {}", repo.name))?;
+    config.prompts = PromptResolver::new(prompts_dir, &repo_path);
+    // The manifest names the repository; with `--forge github` the
+    // environment would otherwise point every entry at the same one
+    let forge = open(&repo_path, &repo.name)?;
+    Ok((repo_path, config, forge))
+}
+
+/// Run one sweep command, returning how many agents it spawned
+fn run_step(
+    forge: &dyn Forge,
+    state: &mut StateStore,
+    config: &Config,
+    repo_path: &Path,
+    step: &Step,
+) -> Result<usize> {
+    match step {
+        Step::Approve => run_approve(forge, config).map(|()| 0),
+        Step::Nudge => run_nudge(forge, state, config).map(|()| 0),
+        Step::Conflicts { close, rebase } => {
+            run_conflicts(forge, state, config, repo_path, *close, *rebase)
+        }
+        Step::Merge => run_merge(forge, state, config, false).map(|()| 0),
+        Step::PromptsCheck => run_prompts(
+            config,
+            &PromptsAction::Check {
+                max_tokens: DEFAULT_MAX_TOKENS,
+            },
+        )
+        .map(|()| 0),
+        Step::Workflow(name) => run_workflow(forge, state, config, repo_path, name),
+    }
+}
+
+/// React to a webhook delivery, touching only the PR, issue or run it names
+fn handle_delivery(
+    forge: &dyn Forge,
+    state: &mut StateStore,
+    config: &Config,
+    repo_path: &Path,
+    delivery: &Delivery,
+) -> Result<String> {
+    let repo = forge.repo_name()?;
+    if let Some(other) = delivery
+        .repository()
+        .filter(|r| !r.eq_ignore_ascii_case(&repo))
+    {
+        return Ok(format!("ignored: {other} is not {repo}"));
+    }
+
+    match Event::parse(&delivery.event, &delivery.payload)? {
+        Event::ChecksFailed { prs } => {
+            let failing: Vec<_> = subagent::list_failing_prs(forge, &config.links)?
+                .into_iter()
+                .filter(|pr| prs.contains(&pr.number))
+                .collect();
+            let mut commented = 0;
+            for pr in &failing {
+                println!("  #{}: {} (by @{})", pr.number, pr.title, pr.author);
+                if nudge_pr(forge, state, config, pr)? {
+                    commented += 1;
+                }
+            }
+            Ok(format!("commented on {commented}/{} PRs", failing.len()))
+        }
+        Event::PrSynchronized { pr } => {
+            let Some(pr) = forge
+                .list_open_prs()?
+                .into_iter()
+                .find(|open| open.number == pr)
+            else {
+                return Ok(format!("PR #{pr} is no longer open"));
+            };
+            match pr.mergeable {
+                forge::MergeState::Mergeable => {
+                    return Ok(format!("PR #{} has no conflicts", pr.number))
+                }
+                // Usually the case right after a push; the conflicts sweep
+                // picks it up once GitHub knows
+                forge::MergeState::Unknown => {
+                    return Ok(format!(
+                        "GitHub hasn't worked out yet whether PR #{} conflicts",
+                        pr.number
+                    ))
+                }
+                forge::MergeState::Conflicting => {}
+            }
+            if nudge::asked_to_rebase(&forge.list_pr_comments(pr.number)?, &pr.head_sha) {
+                return Ok(format!(
+                    "PR #{} was already asked to rebase {:.7}",
+                    pr.number, pr.head_sha
+                ));
+            }
+            let comment = nudge::mark_conflicts(&conflicts_comment(config, &[])?, &pr.head_sha);
+            forge.comment_on_pr(pr.number, &comment)?;
+            Ok(format!("asked PR #{} to rebase", pr.number))
+        }
+        Event::IssueLabeled { issue, label } => {
+            let Some(name) = config
+                .workflows
+                .iter()
+                .find(|(_, workflow)| workflow.label == label)
+                .map(|(name, _)| name.clone())
+            else {
+                return Ok(format!("no workflow uses label {label:?}"));
+            };
+            let spawned = spawn_issue(forge, state, config, repo_path, &name, issue)?;
+            Ok(format!("spawned {spawned} {name} agents for #{issue}"))
+        }
+        Event::RunAwaitingApproval { run_id } => {
+            let Some(run) = forge
+                .list_workflow_runs("action_required")?
+                .into_iter()
+                .find(|run| run.id == run_id)
+            else {
+                return Ok(format!("run {run_id} is no longer waiting for approval"));
+            };
+            let prs = forge.list_open_prs()?;
+            match subagent::approve_run(forge, &config.approve, &run, &prs)? {
+                Approval::Approved => Ok(format!("approved run {run_id}")),
+                Approval::Failed => anyhow::bail!("Failed to approve run {run_id}"),
+                Approval::Skipped(reason) => Ok(format!("skipped run {run_id}: {reason}")),
+            }
+        }
+        Event::Ignored(reason) => Ok(reason),
+    }
+}
+
+/// Run workflow `name` for a single issue, returning how many agents spawned
+fn spawn_issue(
+    forge: &dyn Forge,
+    state: &mut StateStore,
+    config: &Config,
+    repo_path: &Path,
+    name: &str,
+    number: u32,
+) -> Result<usize> {
+    let workflow = config.workflow(name)?;
+    let Some(issue) = forge.fetch_issue(number)? else {
+        return Ok(0);
+    };
+    if workflow.excludes(&issue.labels) {
+        return Ok(0);
+    }
+
+    let issues = pending_issues(forge, state, config, vec![issue])?;
+    let tree = SourceTree::scan(repo_path);
+    let batches = subagent::batch(workflow, issues, &tree);
+    let batches = schedule_batches(forge, &tree, batches, 1)?;
+    spawn_batches(forge, state, config, name, batches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use forge::fake::{FakeForge, Mutation};
+    use state::StateStore;
+
+    fn fake() -> FakeForge {
+        FakeForge::from_json(include_str!("../tests/fixtures/forge.json")).unwrap()
+    }
+
+    /// Checked-out source tree matching the fixture issues
+    fn repo() -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/repo")
+    }
+
+    #[test]
+    fn test_run_test_skips_issues_with_open_prs() {
+        let forge = fake();
+        let mut config = Config::default();
+        config.workflow_mut("test").unwrap().max_agents = 1;
+        run_workflow(&forge, &mut StateStore::default(), &config, &repo(), "test").unwrap();
+
+        let tasks = forge.spawned_tasks();
+        assert_eq!(tasks.len(), 1);
+        assert!(tasks[0].contains("#40") && tasks[0].contains("#41"));
+        assert!(!tasks[0].contains("#4:") && !tasks[0].contains("#9"));
+        assert!(tasks[0].contains("- #40: test: Add tests for function `config::loader`\n- #41"));
+        assert!(tasks[0].contains("`config/tests/") && !tasks[0].contains("{{"));
+    }
+
+    #[test]
+    fn test_only_linked_issues_count_as_covered() {
+        let forge = FakeForge::from_json(
+            r##"{
+                "repo": "acme/widgets",
+                "issues": [
+                    { "number": 30, "title": "Crash on empty input", "labels": ["bug"] },
+                    { "number": 44, "title": "Hover panics", "labels": ["bug"] },
+                    { "number": 45, "title": "Wrong colour", "labels": ["bug"] }
+                ],
+                "prs": [
+                    { "number": 60, "title": "Guard input", "body": "See #30; colour #45 is fine",
+                      "closing_issues": [44] }
+                ]
+            }"##,
+        )
+        .unwrap();
+        run_workflow(
+            &forge,
+            &mut StateStore::default(),
+            &Config::default(),
+            &repo(),
+            "bug",
+        )
+        .unwrap();
+        let tasks = forge.spawned_tasks();
+        assert_eq!(tasks.len(), 2);
+        assert!(tasks[0].starts_with("Fix bug #30.") && tasks[1].starts_with("Fix bug #45."));
+
+        // With the old behaviour configured, any `#N` links the issue
+        let forge = FakeForge::from_json(
+            r##"{
+                "repo": "acme/widgets",
+                "issues": [{ "number": 30, "title": "Crash on empty input", "labels": ["bug"] }],
+                "prs": [{ "number": 60, "title": "Guard input", "body": "See #30" }]
+            }"##,
+        )
+        .unwrap();
+        let config = Config::parse("[links]\nfallback_pattern = '#(\\d+)'\n").unwrap();
+        run_workflow(&forge, &mut StateStore::default(), &config, &repo(), "bug").unwrap();
+        assert!(forge.spawned_tasks().is_empty());
+    }
+
+    #[test]
+    fn test_run_test_batches_by_source_module() {
+        let forge = fake();
+        run_workflow(
+            &forge,
+            &mut StateStore::default(),
+            &Config::default(),
+            &repo(),
+            "test",
+        )
+        .unwrap();
+
+        // #43 only names its file, which lives in the parser module
+        let tasks = forge.spawned_tasks();
+        assert_eq!(tasks.len(), 3);
+        let parser = tasks.iter().find(|t| t.contains("#43")).unwrap();
+        assert!(parser.contains("the **parser** module"));
+        assert!(!parser.contains("#40"));
+    }
+
+    #[test]
+    fn test_run_test_fixed_batch_size() {
+        let forge = fake();
+        let mut config = Config::default();
+        let test = config.workflow_mut("test").unwrap();
+        test.batching = Batching::Fixed;
+        test.batch_size = 2;
+        run_workflow(&forge, &mut StateStore::default(), &config, &repo(), "test").unwrap();
+
+        assert_eq!(forge.spawned_tasks().len(), 2);
+    }
+
+    #[test]
+    fn test_run_feature_spawns_for_enhancements() {
+        let forge = fake();
+        run_workflow(
+            &forge,
+            &mut StateStore::default(),
+            &Config::default(),
+            &repo(),
+            "feature",
+        )
+        .unwrap();
+
+        let tasks = forge.spawned_tasks();
+        assert_eq!(tasks.len(), 1);
+        assert!(tasks[0].contains("#21") && tasks[0].contains("dark colour scheme"));
+    }
+
+    #[test]
+    fn test_run_bug_spawns_for_bugs() {
+        let forge = fake();
+        run_workflow(
+            &forge,
+            &mut StateStore::default(),
+            &Config::default(),
+            &repo(),
+            "bug",
+        )
+        .unwrap();
+
+        let tasks = forge.spawned_tasks();
+        assert_eq!(tasks.len(), 1);
+        assert!(tasks[0].contains("Fix bug #30"));
+    }
+
+    #[test]
+    fn test_run_picks_highest_priority_issues_first() {
+        let forge = FakeForge::from_json(
+            r#"{
+                "issues": [
+                    { "number": 1, "title": "Old cleanup", "labels": ["chore", "priority-low"], "created_at": "2020-01-01T00:00:00Z" },
+                    { "number": 2, "title": "Popular cleanup", "labels": ["chore"], "reactions": 3 },
+                    { "number": 3, "title": "Release blocker", "labels": ["chore", "priority-critical"] }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let mut config = Config::default();
+        config.workflow_mut("chore").unwrap().max_agents = 2;
+        run_workflow(
+            &forge,
+            &mut StateStore::default(),
+            &config,
+            &repo(),
+            "chore",
+        )
+        .unwrap();
+
+        let tasks = forge.spawned_tasks();
+        assert_eq!(tasks.len(), 2);
+        assert!(tasks[0].contains("#3") && tasks[1].contains("#1"));
+    }
+
+    #[test]
+    fn test_spawn_sites_respect_concurrency_budget() {
+        // The fixture's three open PRs are all agent-authored
+        let forge = fake();
+        let mut config = Config::default();
+        config.budget.per_repo = 4;
+        run_workflow(&forge, &mut StateStore::default(), &config, &repo(), "test").unwrap();
+        assert_eq!(forge.spawned_tasks().len(), 1);
+
+        let err = run_custom(&forge, &config, "anything").unwrap_err();
+        assert!(err.to_string().contains("budget exhausted"));
+        assert_eq!(forge.spawned_tasks().len(), 1);
+
+        config.budget.per_repo = 0;
+        run_custom(&forge, &config, "anything").unwrap();
+        assert_eq!(forge.spawned_tasks().len(), 2);
+    }
+
+    #[test]
+    fn test_sweep_runs_each_repo_and_summarises() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("repos.toml");
+        std::fs::write(
+            &path,
+            format!(
+                r#"
+                commands = ["nudge", "bug"]
+
+                [[repos]]
+                name = "acme/widgets"
+                path = {repo:?}
+
+                [repos.config.workflows.bug]
+                max_agents = 0
+
+                [[repos]]
+                name = "acme/gadgets"
+                path = {repo:?}
+                commands = ["chore", "docs"]
+
+                [[repos]]
+                name = "acme/missing"
+                path = "missing"
+                "#,
+                repo = repo()
+            ),
+        )
+        .unwrap();
+        let manifest = Manifest::load(&path).unwrap();
+        let opened = std::cell::RefCell::new(Vec::new());
+
+        let summary = run_sweep(
+            &manifest,
+            manifest.plan(&[], &[]).unwrap(),
+            &mut StateStore::default(),
+            None,
+            None,
+            &|_, name| {
+                opened.borrow_mut().push(name.to_string());
+                Ok(Box::new(fake()))
+            },
+        )
+        .unwrap();
+
+        // Each entry's forge is opened for the repository the manifest names
+        assert_eq!(*opened.borrow(), ["acme/widgets", "acme/gadgets"]);
+
+        let outcomes: Vec<(String, String, Result<usize, String>)> = summary
+            .results
+            .iter()
+            .map(|r| (r.repo.clone(), r.step.to_string(), r.outcome.clone()))
+            .collect();
+        assert_eq!(outcomes[0], ("acme/widgets".into(), "nudge".into(), Ok(0)));
+        // The per-repo override stops the bug workflow spawning
+        assert_eq!(outcomes[1], ("acme/widgets".into(), "bug".into(), Ok(0)));
+        assert_eq!(outcomes[2], ("acme/gadgets".into(), "chore".into(), Ok(1)));
+        assert!(outcomes[3]
+            .2
+            .as_ref()
+            .unwrap_err()
+            .contains("No workflow named"));
+        assert!(outcomes[4]
+            .2
+            .as_ref()
+            .unwrap_err()
+            .contains("does not exist"));
+        assert_eq!(summary.failures(), 3);
+        assert_eq!(summary.spawned(), 1);
+    }
+
+    #[test]
+    fn test_sweep_budget_total_counts_every_repositorys_agent_prs() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("repos.toml");
+        let entry = |name: &str| {
+            format!(
+                "[[repos]]\nname = {name:?}\npath = {:?}\n\
+                 [repos.config.budget]\nper_repo = 0\ntotal = 5\n",
+                repo()
+            )
+        };
+        std::fs::write(
+            &path,
+            format!(
+                "commands = [\"bug\"]\n{}{}",
+                entry("acme/widgets"),
+                entry("acme/gadgets")
+            ),
+        )
+        .unwrap();
+        let manifest = Manifest::load(&path).unwrap();
+
+        // Two agent PRs and three unclaimed bugs in each repository
+        let summary = run_sweep(
+            &manifest,
+            manifest.plan(&[], &[]).unwrap(),
+            &mut StateStore::default(),
+            None,
+            None,
+            &|_, name| {
+                Ok(Box::new(
+                    FakeForge::from_json(&format!(
+                        r#"{{
+                            "repo": "{name}",
+                            "issues": [
+                                {{ "number": 1, "title": "One", "labels": ["bug"] }},
+                                {{ "number": 2, "title": "Two", "labels": ["bug"] }},
+                                {{ "number": 3, "title": "Three", "labels": ["bug"] }}
+                            ],
+                            "prs": [
+                                {{ "number": 10, "title": "a", "author": "Copilot" }},
+                                {{ "number": 11, "title": "b", "author": "Copilot" }}
+                            ]
+                        }}"#
+                    ))
+                    .unwrap(),
+                ))
+            },
+        )
+        .unwrap();
+
+        // Four agent PRs across both leave room for one more in each
+        assert_eq!(summary.spawned(), 2);
+        assert_eq!(summary.failures(), 0);
+    }
+
+    #[test]
+    fn test_run_defers_issues_touching_open_pr_files() {
+        let forge = fake();
+        let mut state = StateStore::default();
+        run_workflow(&forge, &mut state, &Config::default(), &repo(), "bug").unwrap();
+
+        // #44 is in src/server/mod.rs, which PR #20 already changes
+        let tasks = forge.spawned_tasks();
+        assert_eq!(tasks.len(), 1);
+        assert!(!tasks[0].contains("#44"));
+        assert!(state.latest("acme/widgets", 44).is_none());
+    }
+
+    #[test]
+    fn test_state_store_prevents_duplicate_spawns() {
+        let forge = fake();
+        let mut state = StateStore::default();
+        let config = Config::default();
+
+        run_workflow(&forge, &mut state, &config, &repo(), "bug").unwrap();
+        run_workflow(&forge, &mut state, &config, &repo(), "bug").unwrap();
+        run_workflow(&forge, &mut state, &config, &repo(), "chore").unwrap();
+        run_workflow(&forge, &mut state, &config, &repo(), "chore").unwrap();
+
+        assert_eq!(forge.spawned_tasks().len(), 2);
+    }
+
+    #[test]
+    fn test_run_user_defined_workflow() {
+        let forge = fake();
+        let config = Config::parse(
+            r#"
+            [workflows.lexer]
+            label = "testing"
+            exclude_labels = ["blocked"]
+            prompt = "test"
+            batching = "fixed"
+            batch_size = 10
+            "#,
+        )
+        .unwrap();
+
+        let mut state = StateStore::default();
+        run_workflow(&forge, &mut state, &config, &repo(), "lexer").unwrap();
+
+        let tasks = forge.spawned_tasks();
+        assert_eq!(tasks.len(), 1);
+        assert!(tasks[0].contains("#40") && !tasks[0].contains("#42"));
+        assert_eq!(state.latest("acme/widgets", 40).unwrap().workflow, "lexer");
+        assert!(run_workflow(&forge, &mut state, &config, &repo(), "docs").is_err());
+    }
+
+    #[test]
+    fn test_config_changes_labels_and_comments() {
+        let forge = fake();
+        let config = Config::parse(
+            r#"
+            verify_command = "just ci"
+
+            [workflows.bug]
+            label = "chore"
+
+            [nudge]
+            comment = "Run {{verify_command}} please"
+            "#,
+        )
+        .unwrap();
+
+        run_workflow(&forge, &mut StateStore::default(), &config, &repo(), "bug").unwrap();
+        run_nudge(&forge, &mut StateStore::default(), &config).unwrap();
+
+        let tasks = forge.spawned_tasks();
+        assert_eq!(tasks.len(), 1);
+        assert!(tasks[0].contains("Fix bug #31") && tasks[0].contains("`just ci` must pass"));
+        assert!(forge.mutations().iter().any(|m| matches!(
+            m,
+            Mutation::Comment { pr: 15, body } if body.starts_with("Run just ci please\n\n<!--")
+        )));
+    }
+
+    #[test]
+    fn test_run_nudge_comments_on_failing_prs_only() {
+        let forge = fake();
+        run_nudge(&forge, &mut StateStore::default(), &Config::default()).unwrap();
+
+        let mutations = forge.mutations();
+        assert_eq!(mutations.len(), 1);
+        assert!(matches!(&mutations[0], Mutation::Comment { pr: 15, .. }));
+    }
+
+    #[test]
+    fn test_nudge_quotes_failing_checks_from_job_logs() {
+        let forge = FakeForge::from_json(
+            r#"{
+                "prs": [{
+                    "number": 15, "title": "fix", "head_sha": "aaa",
+                    "checks": [
+                        { "name": "build", "conclusion": "SUCCESS", "job_id": 1 },
+                        { "name": "test", "conclusion": "FAILURE", "job_id": 2 },
+                        { "name": "ci/lint", "conclusion": "FAILURE" }
+                    ]
+                }],
+                "job_logs": {
+                    "2": "2024-03-01T10:00:03Z test parser::tests::unicode ... FAILED\n2024-03-01T10:00:03Z error[E0425]: cannot find value `x` in this scope\n2024-03-01T10:00:03Z  --> src/parser.rs:9:5\n"
+                }
+            }"#,
+        )
+        .unwrap();
+        run_nudge(&forge, &mut StateStore::default(), &Config::default()).unwrap();
+
+        let Mutation::Comment { body, .. } = &forge.mutations()[0] else {
+            panic!("expected a comment");
+        };
+        assert!(body.contains(
+            "### ❌ test\n\nFailing tests:\n- `parser::tests::unicode`\n\n```text\nerror[E0425]: cannot find value `x` in this scope\n --> src/parser.rs:9:5\n```\n"
+        ));
+        assert!(body.contains("### ❌ ci/lint\n\nPlease fix"));
+        assert!(!body.contains("build"));
+    }
+
+    #[test]
+    fn test_run_nudge_skips_nudged_commits_and_escalates() {
+        // Red through nudges on two earlier commits, now at ccc
+        let red_pr = || {
+            FakeForge::from_json(
+                r#"{
+                    "prs": [{
+                        "number": 15, "title": "fix", "body": "closes #30", "head_sha": "ccc",
+                        "checks": [{ "conclusion": "FAILURE" }],
+                        "comments": [
+                            "@copilot fix it\n\n<!-- chore-bot:nudge aaa -->",
+                            "@copilot fix it\n\n<!-- chore-bot:nudge bbb -->"
+                        ]
+                    }]
+                }"#,
+            )
+            .unwrap()
+        };
+        let mut state = StateStore::default();
+
+        let forge = red_pr();
+        run_nudge(&forge, &mut state, &Config::default()).unwrap();
+        run_nudge(&forge, &mut state, &Config::default()).unwrap();
+        // One nudge for ccc; the second run sees its marker and stays quiet
+        assert!(matches!(
+            &forge.mutations()[..],
+            [Mutation::Comment { pr: 15, body }] if body.ends_with("<!-- chore-bot:nudge ccc -->")
+        ));
+
+        let forge = red_pr();
+        let config = Config::parse("[nudge]\nescalate_after = 2\n").unwrap();
+        run_nudge(&forge, &mut state, &config).unwrap();
+        run_nudge(&forge, &mut state, &config).unwrap();
+        let mutations = forge.mutations();
+        assert_eq!(mutations.len(), 2);
+        assert!(matches!(
+            &mutations[0],
+            Mutation::Comment { pr: 15, body } if body.contains("after 2 nudges")
+        ));
+        assert_eq!(
+            mutations[1],
+            Mutation::AddLabel {
+                pr: 15,
+                label: "needs-human".to_string()
+            }
+        );
+
+        let forge = red_pr();
+        let config = Config::parse(
+            "[nudge]\nescalate_after = 2\nescalation = \"ping\"\nping = [\"jade\"]\n",
+        )
+        .unwrap();
+        run_nudge(&forge, &mut state, &config).unwrap();
+        assert!(matches!(
+            &forge.mutations()[..],
+            [Mutation::Comment { body, .. }] if body.contains("@jade")
+        ));
+
+        let forge = red_pr();
+        let config =
+            Config::parse("[nudge]\nescalate_after = 2\nescalation = \"close\"\n").unwrap();
+        run_nudge(&forge, &mut state, &config).unwrap();
+        assert_eq!(forge.mutations()[1], Mutation::ClosePr { pr: 15 });
+
+        assert!(Config::parse("[nudge]\nescalation = \"ping\"\n").is_err());
+    }
+
+    #[test]
+    fn test_run_conflicts_close_respawns_linked_issues() {
+        let forge = fake();
+        run_conflicts(
+            &forge,
+            &mut StateStore::default(),
+            &Config::default(),
+            &repo(),
+            true,
+            false,
+        )
+        .unwrap();
+
+        let mutations = forge.mutations();
+        assert_eq!(mutations[0], Mutation::ClosePr { pr: 12 });
+
+        let tasks = forge.spawned_tasks();
+        assert_eq!(tasks.len(), 1);
+        assert!(tasks[0].contains("closes #4, closes #7"));
+    }
+
+    #[test]
+    fn test_run_conflicts_respawns_through_the_original_workflow() {
+        let forge = FakeForge::from_json(
+            r#"{
+                "repo": "acme/widgets",
+                "issues": [
+                    { "number": 30, "title": "Crash on empty input", "labels": ["bug"] },
+                    { "number": 50, "title": "Remove unused helpers", "body": "Dead code in utils." },
+                    { "number": 51, "title": "Add dark mode" },
+                    { "number": 52, "title": "Something" }
+                ],
+                "prs": [
+                    { "number": 60, "title": "Guard empty input", "body": "closes #30",
+                      "mergeable": "CONFLICTING" },
+                    { "number": 61, "title": "refactor: Drop helpers", "body": "closes #50",
+                      "mergeable": "CONFLICTING" },
+                    { "number": 62, "title": "feat(ui): Dark mode", "body": "closes #51",
+                      "mergeable": "CONFLICTING" },
+                    { "number": 63, "title": "Misc", "body": "closes #52",
+                      "mergeable": "CONFLICTING" }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let mut state = StateStore::default();
+        let spawned = AgentTaskResult {
+            success: true,
+            message: String::new(),
+            task_id: None,
+        };
+        state
+            .record("acme/widgets", &[50], "chore", &spawned, 0)
+            .unwrap();
+
+        let count =
+            run_conflicts(&forge, &mut state, &Config::default(), &repo(), true, false).unwrap();
+
+        // Label, spawn history and PR title prefix, in that order; #52 has none
+        assert_eq!(count, 3);
+        let tasks = forge.spawned_tasks();
+        assert!(tasks[0].starts_with("Fix bug #30."));
+        assert!(tasks[1].starts_with("Complete chore #50."));
+        assert!(tasks[1].contains("Dead code in utils."));
+        assert!(tasks[2].starts_with("Implement the feature from issue #51."));
+        assert!(state.latest("acme/widgets", 52).is_none());
+    }
+
+    #[test]
+    fn test_run_conflicts_respawns_at_most_max_agents() {
+        let forge = FakeForge::from_json(
+            r#"{
+                "issues": [
+                    { "number": 30, "title": "Crash on empty input", "labels": ["bug"] },
+                    { "number": 31, "title": "Crash on long input", "labels": ["bug"] }
+                ],
+                "prs": [
+                    { "number": 60, "title": "Guard empty input", "body": "closes #30",
+                      "mergeable": "CONFLICTING" },
+                    { "number": 61, "title": "Guard long input", "body": "closes #31",
+                      "mergeable": "CONFLICTING" }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let mut config = Config::default();
+        config.workflow_mut("bug").unwrap().max_agents = 1;
+
+        let count = run_conflicts(
+            &forge,
+            &mut StateStore::default(),
+            &config,
+            &repo(),
+            true,
+            false,
+        )
+        .unwrap();
+        assert_eq!(count, 1);
+        assert_eq!(forge.spawned_tasks().len(), 1);
+    }
+
+    #[test]
+    fn test_run_conflicts_comment_mode() {
+        let forge = fake();
+        run_conflicts(
+            &forge,
+            &mut StateStore::default(),
+            &Config::default(),
+            &repo(),
+            false,
+            false,
+        )
+        .unwrap();
+
+        let mutations = forge.mutations();
+        assert_eq!(mutations.len(), 1);
+        assert!(matches!(&mutations[0], Mutation::Comment { pr: 12, .. }));
+    }
+
+    #[test]
+    fn test_run_conflicts_rebases_before_giving_up() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let clone = rebase::scratch_remote(dir.path());
+        let forge = FakeForge::from_json(
+            r#"{
+                "prs": [
+                    { "number": 21, "title": "clean", "head_ref": "clean", "base_ref": "main",
+                      "mergeable": "CONFLICTING" },
+                    { "number": 22, "title": "clash", "head_ref": "clash", "base_ref": "main",
+                      "body": "closes #4", "mergeable": "CONFLICTING" }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let mut state = StateStore::default();
+        let config = Config::default();
+
+        // #21 rebases cleanly; only #22 is asked to resolve its conflicts
+        run_conflicts(&forge, &mut state, &config, &clone, false, true).unwrap();
+        let mutations = forge.mutations();
+        assert_eq!(mutations.len(), 1);
+        let Mutation::Comment { pr: 22, body } = &mutations[0] else {
+            panic!("expected a comment on #22");
+        };
+        assert!(body.contains("Rebasing it conflicts in:\n- `a.txt`\n"));
+
+        // With --close only the PR that really conflicts is closed
+        run_conflicts(&forge, &mut state, &config, &clone, true, true).unwrap();
+        assert_eq!(forge.mutations()[1..], [Mutation::ClosePr { pr: 22 }]);
+    }
+
+    #[test]
+    fn test_run_conflicts_keeps_prs_it_could_not_rebase() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let clone = rebase::scratch_remote(dir.path());
+        let missing = dir.path().join("missing.git");
+        let status = std::process::Command::new("git")
+            .args(["remote", "set-url", "origin"])
+            .arg(&missing)
+            .current_dir(&clone)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let forge = FakeForge::from_json(
+            r#"{
+                "prs": [
+                    { "number": 21, "title": "clean", "head_ref": "clean", "base_ref": "main",
+                      "mergeable": "CONFLICTING" }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let mut state = StateStore::default();
+        let config = Config::default();
+
+        // The fetch fails, so #21 is still asked to resolve its conflicts
+        run_conflicts(&forge, &mut state, &config, &clone, false, true).unwrap();
+        assert!(matches!(
+            forge.mutations()[..],
+            [Mutation::Comment { pr: 21, .. }]
+        ));
+
+        run_conflicts(&forge, &mut state, &config, &clone, true, true).unwrap();
+        assert_eq!(forge.mutations()[1..], [Mutation::ClosePr { pr: 21 }]);
+    }
+
+    #[test]
+    fn test_refactor_plan_renders_context_and_spawns() {
+        let files = vec![analysis::FileStats {
+            path: "src/parser/lexer.rs".to_string(),
+            lines: 420,
+            functions: 18,
+            coverage: None,
+        }];
+        let task = render_refactor_plan(&Config::default(), "src/parser", &files).unwrap();
+        assert!(task.starts_with("Create a refactoring plan for: src/parser\n"));
+        assert!(task.contains("| `src/parser/lexer.rs` | 420 | 18 | - |"));
+        assert!(task.contains("--batch .chore-bot/plans/src-parser.json"));
+
+        let bare = render_refactor_plan(&Config::default(), "src/parser", &[]).unwrap();
+        assert!(!bare.contains("Local Context"));
+
+        let forge = fake();
+        run_refactor_plan(&forge, &Config::default(), "src/parser", &task).unwrap();
+        assert_eq!(forge.spawned_tasks(), vec![task]);
+    }
+
+    #[test]
+    fn test_run_approve_reruns_action_required_only() {
+        let forge = fake();
+        run_approve(&forge, &Config::default()).unwrap();
+
+        assert_eq!(forge.mutations(), vec![Mutation::Rerun { run_id: 100 }]);
+    }
+
+    #[test]
+    fn test_run_approve_follows_the_approval_policy() {
+        let forge = FakeForge::from_json(
+            r#"{
+                "prs": [
+                    { "number": 15, "title": "fix", "head_sha": "aaa", "files": ["src/lib.rs"] },
+                    { "number": 16, "title": "ci", "head_sha": "bbb",
+                      "files": ["src/lib.rs", ".github/workflows/ci.yml"] }
+                ],
+                "workflow_runs": [
+                    { "id": 1, "conclusion": "action_required", "actor": "Copilot", "pr": 15 },
+                    { "id": 2, "conclusion": "action_required", "actor": "mallory", "pr": 15 },
+                    { "id": 3, "conclusion": "action_required", "actor": "Copilot", "head_sha": "bbb" },
+                    { "id": 4, "conclusion": "action_required", "actor": "Copilot", "head_sha": "ccc" },
+                    { "id": 5, "conclusion": "action_required", "actor": "Copilot", "pr": 15,
+                      "head_sha": "old" }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let results = subagent::approve_pending_workflows(&forge, &Config::default().approve)
+            .unwrap()
+            .into_iter()
+            .map(|(run, approval)| (run.id, approval))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            results,
+            [
+                (1, Approval::Approved),
+                (
+                    2,
+                    Approval::Skipped("actor @mallory is not allowed".to_string())
+                ),
+                (
+                    3,
+                    Approval::Skipped(
+                        "PR #16 changes protected path .github/workflows/ci.yml".to_string()
+                    )
+                ),
+                (
+                    4,
+                    Approval::Skipped("no open PR found to check its changed paths".to_string())
+                ),
+                (
+                    5,
+                    Approval::Skipped("run is for an older commit than PR #15".to_string())
+                ),
+            ]
+        );
+        assert_eq!(forge.mutations(), [Mutation::Rerun { run_id: 1 }]);
+    }
+
+    #[test]
+    fn test_run_merge_ignores_fallback_pattern_links() {
+        let green = r#""author": "Copilot", "mergeable": "MERGEABLE",
+                      "checks": [{ "name": "ci", "conclusion": "SUCCESS" }], "approvals": 1"#;
+        let forge = FakeForge::from_json(&format!(
+            r#"{{
+                "prs": [
+                    {{ "number": 70, "title": "fix", "body": "closes #80, see #81", {green} }},
+                    {{ "number": 71, "title": "part", "body": "Part of #80", {green} }}
+                ]
+            }}"#
+        ))
+        .unwrap();
+        let mut state = StateStore::default();
+        let spawned = AgentTaskResult {
+            success: true,
+            message: String::new(),
+            task_id: None,
+        };
+        state
+            .record("acme/widgets", &[80], "bug", &spawned, 0)
+            .unwrap();
+        let mut config = Config::default();
+        config.links.fallback_pattern = Some(r"#(\d+)".to_string());
+
+        let outcomes: Vec<_> = subagent::merge_green_prs(&forge, &state, &config, true)
+            .unwrap()
+            .into_iter()
+            .map(|(pr, merge)| (pr.number, merge))
+            .collect();
+        // #81 is only mentioned, and merging #71 wouldn't close #80
+        assert_eq!(
+            outcomes,
+            [
+                (70, Merge::Ready),
+                (71, Merge::Skipped("it doesn't close any issue".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_run_merge_merges_green_dispatched_prs_up_to_the_cap() {
+        let green = r#""mergeable": "MERGEABLE", "checks": [{ "name": "ci", "conclusion": "SUCCESS" }],
+                      "approvals": 1, "review_decision": "APPROVED""#;
+        let forge = FakeForge::from_json(&format!(
+            r#"{{
+                "prs": [
+                    {{ "number": 73, "title": "later", "author": "Copilot", "head_sha": "c73",
+                      "body": "closes #82", {green} }},
+                    {{ "number": 70, "title": "fix", "author": "Copilot", "head_sha": "c70",
+                      "body": "Fixes #80", {green} }},
+                    {{ "number": 71, "title": "stray", "author": "Copilot", "head_sha": "c71",
+                      "body": "closes #80, closes #81", {green} }},
+                    {{ "number": 72, "title": "human", "author": "alice", "head_sha": "c72",
+                      "body": "closes #80", {green} }},
+                    {{ "number": 74, "title": "red", "author": "app/copilot-swe-agent",
+                      "head_sha": "c74", "body": "closes #80", "mergeable": "MERGEABLE",
+                      "checks": [{{ "name": "ci", "conclusion": "FAILURE" }}], "approvals": 1 }}
+                ]
+            }}"#
+        ))
+        .unwrap();
+        let mut state = StateStore::default();
+        let result = |success| AgentTaskResult {
+            success,
+            message: String::new(),
+            task_id: None,
+        };
+        state
+            .record("acme/widgets", &[80, 82], "bug", &result(true), 0)
+            .unwrap();
+        state
+            .record("acme/widgets", &[81], "bug", &result(false), 0)
+            .unwrap();
+        // Spawned long past the spawn TTL, and its agent went quiet before
+        // the PR turned up; #82 still counts as dispatched
+        state.set_outcome("acme/widgets", 82, state::Outcome::Stale, None);
+        let mut config = Config::default();
+        config.merge.max_per_run = 1;
+        config.merge.strategy = forge::MergeMethod::Rebase;
+
+        let outcomes = |dry_run| {
+            subagent::merge_green_prs(&forge, &state, &config, dry_run)
+                .unwrap()
+                .into_iter()
+                .map(|(pr, merge)| (pr.number, merge))
+                .collect::<Vec<_>>()
+        };
+        let skipped = |reason: &str| Merge::Skipped(reason.to_string());
+
+        // Oldest first; humans' PRs are left alone
+        assert_eq!(
+            outcomes(true),
+            [
+                (70, Merge::Ready),
+                (
+                    71,
+                    skipped("it closes #81, which chore-bot didn't dispatch")
+                ),
+                (73, skipped("the per-run merge cap (1) was reached")),
+                (74, skipped("check ci is failure")),
+            ]
+        );
+        assert!(forge.mutations().is_empty());
+
+        assert_eq!(outcomes(false)[0], (70, Merge::Merged));
+        assert_eq!(
+            forge.mutations(),
+            [Mutation::Merge {
+                pr: 70,
+                method: forge::MergeMethod::Rebase
+            }]
+        );
+        assert_eq!(outcomes(false)[1], (73, Merge::Merged));
+    }
+
+    #[test]
+    fn test_webhook_deliveries_act_on_the_affected_item() {
+        let forge = fake();
+        let config = Config::default();
+        let listener = Listener::bind("127.0.0.1:0", "s3cret").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+
+        let deliveries = [
+            ("check_suite", "check_suite_completed", "s3cret"),
+            ("pull_request", "pull_request_synchronize", "s3cret"),
+            ("issues", "issues_labeled", "s3cret"),
+            ("workflow_run", "workflow_run_requested", "s3cret"),
+            ("workflow_run", "workflow_run_requested", "forged"),
+        ];
+        let client = std::thread::spawn(move || {
+            deliveries.map(|(event, fixture, secret)| {
+                let path = repo().join(format!("../webhooks/{fixture}.json"));
+                let body = std::fs::read(path).unwrap();
+                let response = ureq::post(&url)
+                    .set("X-GitHub-Event", event)
+                    .set("X-GitHub-Delivery", fixture)
+                    .set(
+                        "X-Hub-Signature-256",
+                        &webhook::sign(secret.as_bytes(), &body),
+                    )
+                    .send_bytes(&body);
+                match response {
+                    Ok(response) => response.status(),
+                    Err(ureq::Error::Status(status, _)) => status,
+                    Err(e) => panic!("{e}"),
+                }
+            })
+        });
+
+        let mut state = StateStore::default();
+        for _ in 0..deliveries.len() {
+            listener
+                .handle_next(&mut |delivery| {
+                    handle_delivery(&forge, &mut state, &config, &repo(), delivery)
+                })
+                .unwrap();
+        }
+
+        assert_eq!(client.join().unwrap(), [200, 200, 200, 200, 401]);
+        let mutations = forge.mutations();
+        assert_eq!(mutations.len(), 4);
+        assert!(matches!(&mutations[0], Mutation::Comment { pr: 15, .. }));
+        assert!(matches!(&mutations[1], Mutation::Comment { pr: 12, .. }));
+        assert!(matches!(&mutations[2], Mutation::SpawnAgent { task } if task.contains("#30")));
+        assert_eq!(mutations[3], Mutation::Rerun { run_id: 100 });
+    }
+
+    #[test]
+    fn test_webhook_counts_only_the_failing_prs() {
+        let forge = fake();
+        let config = Config::default();
+        let mut state = StateStore::default();
+        let path = repo().join("../webhooks/check_suite_completed.json");
+        let mut payload: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+        // #12 is green, so only #15 is up for a nudge
+        payload["check_suite"]["pull_requests"]
+            .as_array_mut()
+            .unwrap()
+            .push(json!({ "number": 12 }));
+        let delivery = Delivery {
+            event: "check_suite".to_string(),
+            id: "1".to_string(),
+            payload,
+        };
+
+        let summary = handle_delivery(&forge, &mut state, &config, &repo(), &delivery).unwrap();
+        assert_eq!(summary, "commented on 1/1 PRs");
+    }
+
+    #[test]
+    fn test_webhook_asks_to_rebase_once_per_head() {
+        let forge = fake();
+        let config = Config::default();
+        let mut state = StateStore::default();
+        let path = repo().join("../webhooks/pull_request_synchronize.json");
+        let delivery = Delivery {
+            event: "pull_request".to_string(),
+            id: "1".to_string(),
+            payload: serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap(),
+        };
+        let mut handle = || handle_delivery(&forge, &mut state, &config, &repo(), &delivery);
+
+        assert_eq!(handle().unwrap(), "asked PR #12 to rebase");
+        assert!(handle().unwrap().contains("already asked to rebase"));
+        assert_eq!(forge.mutations().len(), 1);
+    }
+}
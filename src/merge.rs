@@ -0,0 +1,177 @@
+//! Policy for merging green agent PRs (`merge`)
+//!
+//! Merging is the one step chore-bot takes that closing a PR can't undo, so
+//! an agent's PR is only merged once there is nothing left for a human to
+//! decide: it merges cleanly, every check passed, reviewers approved it, and
+//! every issue it closes is one chore-bot dispatched an agent for. The merge
+//! is pinned to the head commit that was checked, so a push in between makes
+//! it fail rather than merge code nobody looked at.
+
+use crate::config::MergeConfig;
+use crate::forge::{MergeState, PullRequest, ReviewDecision};
+
+/// What became of an agent PR
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Merge {
+    Merged,
+    /// Allowed, but left open by a dry run
+    Ready,
+    /// The forge refused the merge, e.g. because the head moved on
+    Failed,
+    /// The policy doesn't allow it, and why
+    Skipped(String),
+}
+
+/// Why `pr` must not be merged, or `None` if the policy allows it; `linked`
+/// are the issues it closes and `dispatched` tells whether chore-bot spawned
+/// an agent for an issue
+pub fn skip_reason(
+    config: &MergeConfig,
+    pr: &PullRequest,
+    linked: &[u32],
+    dispatched: impl Fn(u32) -> bool,
+) -> Option<String> {
+    match pr.mergeable {
+        MergeState::Mergeable => {}
+        MergeState::Conflicting => return Some("it has merge conflicts".to_string()),
+        MergeState::Unknown => {
+            return Some("GitHub hasn't worked out whether it merges cleanly".to_string())
+        }
+    }
+
+    if pr.truncated {
+        return Some("it has more checks, reviews or closing issues than were listed".to_string());
+    }
+    if pr.checks.is_empty() {
+        return Some("it has no checks".to_string());
+    }
+    if let Some(check) = pr.checks.iter().find(|check| !check.is_success()) {
+        let name = if check.name.is_empty() {
+            "a check".to_string()
+        } else {
+            format!("check {}", check.name)
+        };
+        let state = check
+            .conclusion
+            .as_deref()
+            .map_or("pending".to_string(), str::to_lowercase);
+        return Some(format!("{name} is {state}"));
+    }
+
+    if pr.review_decision == ReviewDecision::ChangesRequested {
+        return Some("changes were requested".to_string());
+    }
+    if pr.approvals < config.required_approvals {
+        return Some(format!(
+            "it has {}/{} approvals",
+            pr.approvals, config.required_approvals
+        ));
+    }
+    if pr.review_decision == ReviewDecision::ReviewRequired {
+        return Some("GitHub still requires a review".to_string());
+    }
+
+    if linked.is_empty() {
+        return Some("it doesn't close any issue".to_string());
+    }
+    linked
+        .iter()
+        .find(|&&issue| !dispatched(issue))
+        .map(|issue| format!("it closes #{issue}, which chore-bot didn't dispatch"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::forge::CheckRun;
+
+    fn check(name: &str, conclusion: Option<&str>) -> CheckRun {
+        CheckRun {
+            name: name.to_string(),
+            conclusion: conclusion.map(str::to_string),
+            job_id: None,
+        }
+    }
+
+    #[test]
+    fn test_skip_reasons() {
+        let config = MergeConfig::default();
+        let pr = PullRequest {
+            number: 20,
+            mergeable: MergeState::Mergeable,
+            checks: vec![
+                check("build", Some("SUCCESS")),
+                check("docs", Some("SKIPPED")),
+            ],
+            review_decision: ReviewDecision::Approved,
+            approvals: 1,
+            ..PullRequest::default()
+        };
+        let reason =
+            |pr: &PullRequest, linked: &[u32]| skip_reason(&config, pr, linked, |issue| issue == 9);
+
+        assert_eq!(reason(&pr, &[9]), None);
+        assert_eq!(
+            reason(&pr, &[9, 11]).unwrap(),
+            "it closes #11, which chore-bot didn't dispatch"
+        );
+        assert_eq!(reason(&pr, &[]).unwrap(), "it doesn't close any issue");
+
+        let conflicting = PullRequest {
+            mergeable: MergeState::Conflicting,
+            ..pr.clone()
+        };
+        assert_eq!(
+            reason(&conflicting, &[9]).unwrap(),
+            "it has merge conflicts"
+        );
+        let unchecked = PullRequest {
+            mergeable: MergeState::Unknown,
+            ..pr.clone()
+        };
+        assert!(reason(&unchecked, &[9]).unwrap().contains("merges cleanly"));
+
+        let pending = PullRequest {
+            checks: vec![check("build", Some("SUCCESS")), check("test", None)],
+            ..pr.clone()
+        };
+        assert_eq!(reason(&pending, &[9]).unwrap(), "check test is pending");
+        let failing = PullRequest {
+            checks: vec![check("", Some("FAILURE"))],
+            ..pr.clone()
+        };
+        assert_eq!(reason(&failing, &[9]).unwrap(), "a check is failure");
+        let no_checks = PullRequest {
+            checks: Vec::new(),
+            ..pr.clone()
+        };
+        assert_eq!(reason(&no_checks, &[9]).unwrap(), "it has no checks");
+        let truncated = PullRequest {
+            truncated: true,
+            ..pr.clone()
+        };
+        assert!(reason(&truncated, &[9])
+            .unwrap()
+            .contains("than were listed"));
+
+        let unapproved = PullRequest {
+            approvals: 0,
+            review_decision: ReviewDecision::Unknown,
+            ..pr.clone()
+        };
+        assert_eq!(reason(&unapproved, &[9]).unwrap(), "it has 0/1 approvals");
+        let changes = PullRequest {
+            review_decision: ReviewDecision::ChangesRequested,
+            ..pr.clone()
+        };
+        assert_eq!(reason(&changes, &[9]).unwrap(), "changes were requested");
+        let required = PullRequest {
+            review_decision: ReviewDecision::ReviewRequired,
+            ..pr.clone()
+        };
+        assert_eq!(
+            reason(&required, &[9]).unwrap(),
+            "GitHub still requires a review"
+        );
+    }
+}
@@ -0,0 +1,136 @@
+//! Deduplicated nudges and escalation for PRs with failing checks
+//!
+//! Every nudge carries a hidden marker naming the commit it was posted for,
+//! so a PR's own comments record its nudge history and nothing needs to be
+//! stored locally. A PR is nudged at most once per head commit; once
+//! `escalate_after` nudges haven't turned it green it is escalated, once,
+//! and then left to humans. Requests to resolve merge conflicts are marked
+//! the same way, so a PR is asked to rebase at most once per head commit too.
+
+use crate::config::NudgeConfig;
+use std::collections::HashSet;
+
+const NUDGE_MARKER: &str = "<!-- chore-bot:nudge ";
+const ESCALATED_MARKER: &str = "<!-- chore-bot:escalated -->";
+const CONFLICTS_MARKER: &str = "<!-- chore-bot:conflicts ";
+
+/// What to do about a failing PR
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// A nudge was already posted for the current head commit
+    AlreadyNudged,
+    /// The PR was escalated on an earlier run
+    AlreadyEscalated,
+    /// Nudge again; `previous` nudges were posted for earlier commits
+    Nudge { previous: usize },
+    /// `nudges` nudges didn't help
+    Escalate { nudges: usize },
+}
+
+/// Decide from the PR's comments whether to nudge or escalate
+pub fn decide(comments: &[String], head_sha: &str, config: &NudgeConfig) -> Action {
+    if comments.iter().any(|c| c.contains(ESCALATED_MARKER)) {
+        return Action::AlreadyEscalated;
+    }
+
+    let nudged: HashSet<&str> = comments
+        .iter()
+        .filter_map(|c| marked_sha(c, NUDGE_MARKER))
+        .collect();
+    if nudged.contains(head_sha) {
+        Action::AlreadyNudged
+    } else if config.escalate_after > 0 && nudged.len() >= config.escalate_after as usize {
+        Action::Escalate {
+            nudges: nudged.len(),
+        }
+    } else {
+        Action::Nudge {
+            previous: nudged.len(),
+        }
+    }
+}
+
+/// Whether the PR was already asked to resolve its conflicts at `head_sha`
+pub fn asked_to_rebase(comments: &[String], head_sha: &str) -> bool {
+    comments
+        .iter()
+        .any(|c| marked_sha(c, CONFLICTS_MARKER) == Some(head_sha))
+}
+
+/// Commit a comment carrying `marker` was posted for
+fn marked_sha<'a>(comment: &'a str, marker: &str) -> Option<&'a str> {
+    let rest = &comment[comment.find(marker)? + marker.len()..];
+    rest.split_once(" -->").map(|(sha, _)| sha)
+}
+
+/// Append the hidden marker recording a nudge for `head_sha`
+pub fn mark_nudge(comment: &str, head_sha: &str) -> String {
+    format!("{comment}\n\n{NUDGE_MARKER}{head_sha} -->")
+}
+
+/// Append the hidden marker recording a request to rebase `head_sha`
+pub fn mark_conflicts(comment: &str, head_sha: &str) -> String {
+    format!("{comment}\n\n{CONFLICTS_MARKER}{head_sha} -->")
+}
+
+/// Append the hidden marker recording an escalation
+pub fn mark_escalation(comment: &str) -> String {
+    format!("{comment}\n\n{ESCALATED_MARKER}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nudges_once_per_commit_then_escalates() {
+        let config = NudgeConfig {
+            escalate_after: 2,
+            ..NudgeConfig::default()
+        };
+        let mut comments = vec!["LGTM".to_string()];
+
+        assert_eq!(
+            decide(&comments, "aaa", &config),
+            Action::Nudge { previous: 0 }
+        );
+        comments.push(mark_nudge("please fix", "aaa"));
+        assert_eq!(decide(&comments, "aaa", &config), Action::AlreadyNudged);
+
+        assert_eq!(
+            decide(&comments, "bbb", &config),
+            Action::Nudge { previous: 1 }
+        );
+        comments.push(mark_nudge("please fix", "bbb"));
+        assert_eq!(
+            decide(&comments, "ccc", &config),
+            Action::Escalate { nudges: 2 }
+        );
+
+        comments.push(mark_escalation("over to you"));
+        assert_eq!(decide(&comments, "ddd", &config), Action::AlreadyEscalated);
+
+        let never = NudgeConfig {
+            escalate_after: 0,
+            ..NudgeConfig::default()
+        };
+        assert_eq!(
+            decide(&comments[..3], "ccc", &never),
+            Action::Nudge { previous: 2 }
+        );
+    }
+
+    #[test]
+    fn test_asks_to_rebase_once_per_commit() {
+        let mut comments = vec![mark_nudge("please fix", "aaa")];
+        assert!(!asked_to_rebase(&comments, "aaa"));
+
+        comments.push(mark_conflicts("please rebase", "aaa"));
+        assert!(asked_to_rebase(&comments, "aaa"));
+        assert!(!asked_to_rebase(&comments, "bbb"));
+        assert_eq!(
+            decide(&comments, "aaa", &NudgeConfig::default()),
+            Action::AlreadyNudged
+        );
+    }
+}
@@ -0,0 +1,129 @@
+//! Ranking open issues before they are batched
+//!
+//! Forges list issues in whatever order they like, and a workflow only spawns
+//! `max_agents` batches, so the order decides what gets worked on. Issues are
+//! scored from their labels, age, reactions and milestone using the weights
+//! in `[priority]`, and picked highest first.
+
+use crate::config::PriorityConfig;
+use crate::forge::Issue;
+use crate::subagent::IssueBatch;
+use std::cmp::Reverse;
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Score an issue at `now` (Unix seconds)
+pub fn score(issue: &Issue, config: &PriorityConfig, now: u64) -> i64 {
+    let labels: i64 = issue
+        .labels
+        .iter()
+        .filter_map(|label| config.labels.get(label))
+        .sum();
+    let age_days = issue
+        .created_at
+        .as_deref()
+        .and_then(parse_timestamp)
+        .map(|opened| now.saturating_sub(opened) / SECS_PER_DAY)
+        .unwrap_or(0)
+        .min(config.max_age_days);
+    let milestone = if issue.milestone.is_some() {
+        config.milestone
+    } else {
+        0
+    };
+
+    labels
+        + age_days as i64 * config.per_day
+        + i64::from(issue.reactions) * config.per_reaction
+        + milestone
+}
+
+/// Sort issues highest score first, lowest number first on ties
+pub fn rank(issues: &mut [Issue], config: &PriorityConfig, now: u64) {
+    issues.sort_by_cached_key(|issue| (Reverse(score(issue, config, now)), issue.number));
+}
+
+/// Order batches by their best issue's score, keeping the batching order on
+/// ties
+pub fn rank_batches(batches: &mut [IssueBatch], config: &PriorityConfig, now: u64) {
+    batches.sort_by_cached_key(|(_, batch)| {
+        Reverse(batch.iter().map(|issue| score(issue, config, now)).max())
+    });
+}
+
+/// Unix seconds for an RFC 3339 timestamp such as `2024-01-15T10:00:00Z`.
+/// The UTC offset is ignored; ages are only counted in whole days.
+fn parse_timestamp(text: &str) -> Option<u64> {
+    let re = regex::Regex::new(r"^(\d{4})-(\d{2})-(\d{2})T(\d{2}):(\d{2}):(\d{2})").unwrap();
+    let caps = re.captures(text)?;
+    let field = |i: usize| caps[i].parse::<i64>().ok();
+
+    let days = days_from_civil(field(1)?, field(2)?, field(3)?);
+    let secs = days * SECS_PER_DAY as i64 + field(4)? * 3600 + field(5)? * 60 + field(6)?;
+    u64::try_from(secs).ok()
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn issue(number: u32, labels: &[&str], created_at: &str) -> Issue {
+        Issue {
+            number,
+            labels: labels.iter().map(|l| l.to_string()).collect(),
+            created_at: Some(created_at.to_string()),
+            ..Issue::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(parse_timestamp("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(parse_timestamp("2024-03-01T12:30:15Z"), Some(1_709_296_215));
+        assert_eq!(parse_timestamp("2024-03-01"), None);
+    }
+
+    #[test]
+    fn test_critical_bugs_outrank_stale_low_priority_chores() {
+        let config = PriorityConfig::default();
+        let now = parse_timestamp("2024-06-01T00:00:00Z").unwrap();
+
+        let mut issues = vec![
+            issue(1, &["chore", "priority-low"], "2021-01-01T00:00:00Z"),
+            issue(2, &["bug", "priority-critical"], "2024-05-31T00:00:00Z"),
+            issue(3, &["bug"], "2024-05-21T00:00:00Z"),
+            Issue {
+                reactions: 10,
+                milestone: Some("v1.0".to_string()),
+                ..issue(4, &["priority-medium"], "2024-06-01T00:00:00Z")
+            },
+        ];
+        // Age is capped at 30 days
+        assert_eq!(score(&issues[0], &config, now), 30);
+        assert_eq!(score(&issues[3], &config, now), 20 + 20 + 10);
+
+        rank(&mut issues, &config, now);
+        let order: Vec<u32> = issues.iter().map(|i| i.number).collect();
+        assert_eq!(order, [2, 4, 1, 3]);
+
+        let mut batches = vec![
+            ("low".to_string(), vec![issues[3].clone()]),
+            (
+                "mixed".to_string(),
+                vec![issues[2].clone(), issues[0].clone()],
+            ),
+        ];
+        rank_batches(&mut batches, &config, now);
+        assert_eq!(batches[0].0, "mixed");
+    }
+}
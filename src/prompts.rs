@@ -0,0 +1,216 @@
+//! Prompt template discovery
+//!
+//! The stock prompts under `agents/` are compiled into the binary, so a
+//! release build works wherever it is copied. Each can be overridden by a
+//! `<name>.md` (or `<name>/prompt.md`) file found in, in order:
+//!
+//! 1. the directory passed with `--prompts-dir`
+//! 2. `<repo>/.chore-bot/prompts/`
+//! 3. `$XDG_CONFIG_HOME/chore-bot/prompts/` (or `~/.config/chore-bot/prompts/`)
+//!
+//! A workflow prompt ending in `.md` is read from that path instead.
+
+use crate::config;
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Prompts shipped with chore-bot
+const BUILTIN: &[(&str, &str)] = &[
+    ("test", include_str!("../agents/test/prompt.md")),
+    ("feature", include_str!("../agents/feature/prompt.md")),
+    ("bug", include_str!("../agents/bug/prompt.md")),
+    ("chore", include_str!("../agents/chore/prompt.md")),
+    (
+        "refactor-plan",
+        include_str!("../agents/refactor-plan/prompt.md"),
+    ),
+];
+
+/// Where a prompt template was found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Origin {
+    /// `--prompts-dir`
+    Flag,
+    /// The target repository's `.chore-bot/prompts/`
+    Repo,
+    /// The user's config directory
+    User,
+    /// A `.md` path set as a workflow's prompt
+    File,
+    /// Compiled into the binary
+    Builtin,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Flag => "--prompts-dir",
+            Self::Repo => "repo",
+            Self::User => "user",
+            Self::File => "config path",
+            Self::Builtin => "built-in",
+        })
+    }
+}
+
+/// A resolved prompt template
+#[derive(Debug, Clone)]
+pub struct Prompt {
+    pub name: String,
+    pub origin: Origin,
+    /// File the template was read from (`None` for built-ins)
+    pub path: Option<PathBuf>,
+    pub text: String,
+}
+
+impl Prompt {
+    /// Human-readable description of where the template came from
+    pub fn source(&self) -> String {
+        match &self.path {
+            Some(path) => format!("{} ({})", self.origin, path.display()),
+            None => self.origin.to_string(),
+        }
+    }
+}
+
+/// Prompt search path, highest priority first
+#[derive(Debug, Clone, Default)]
+pub struct PromptResolver {
+    dirs: Vec<(Origin, PathBuf)>,
+}
+
+impl PromptResolver {
+    /// Search `flag_dir`, the repo's `.chore-bot/prompts/` and the user
+    /// config dir before falling back to the built-ins
+    pub fn new(flag_dir: Option<&Path>, repo_path: &Path) -> Self {
+        let mut dirs = Vec::new();
+        if let Some(dir) = flag_dir {
+            dirs.push((Origin::Flag, dir.to_path_buf()));
+        }
+        dirs.push((Origin::Repo, repo_path.join(".chore-bot").join("prompts")));
+        if let Some(dir) = user_prompts_dir() {
+            dirs.push((Origin::User, dir));
+        }
+        Self { dirs }
+    }
+
+    /// Resolve a workflow prompt: a `.md` path or a template name
+    pub fn resolve(&self, prompt: &str) -> Result<Prompt> {
+        if let Some(path) = config::prompt_path(prompt) {
+            return read(prompt, Origin::File, path);
+        }
+
+        for (origin, dir) in &self.dirs {
+            let candidates = [
+                dir.join(format!("{prompt}.md")),
+                dir.join(prompt).join("prompt.md"),
+            ];
+            if let Some(path) = candidates.into_iter().find(|p| p.is_file()) {
+                return read(prompt, *origin, path);
+            }
+        }
+
+        match BUILTIN.iter().find(|(name, _)| *name == prompt) {
+            Some((name, text)) => Ok(Prompt {
+                name: name.to_string(),
+                origin: Origin::Builtin,
+                path: None,
+                text: text.to_string(),
+            }),
+            None => bail!("No prompt named {prompt:?} in the search path or built-ins"),
+        }
+    }
+
+    /// Names of every template reachable through the search path
+    pub fn names(&self) -> BTreeSet<String> {
+        let mut names: BTreeSet<String> =
+            BUILTIN.iter().map(|(name, _)| name.to_string()).collect();
+
+        for (_, dir) in &self.dirs {
+            let Ok(entries) = std::fs::read_dir(dir) else {
+                continue;
+            };
+            for path in entries.flatten().map(|e| e.path()) {
+                if path.extension().is_some_and(|ext| ext == "md") {
+                    names.extend(path.file_stem().map(|s| s.to_string_lossy().into_owned()));
+                } else if path.join("prompt.md").is_file() {
+                    names.extend(path.file_name().map(|s| s.to_string_lossy().into_owned()));
+                }
+            }
+        }
+
+        names
+    }
+}
+
+fn read(name: &str, origin: Origin, path: PathBuf) -> Result<Prompt> {
+    let text = std::fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read prompt: {}", path.display()))?;
+    Ok(Prompt {
+        name: name.to_string(),
+        origin,
+        path: Some(path),
+        text,
+    })
+}
+
+/// `$XDG_CONFIG_HOME/chore-bot/prompts`, falling back to `~/.config`
+fn user_prompts_dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .map(|base| base.join("chore-bot").join("prompts"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_builtins_resolve_without_files() {
+        let prompt = PromptResolver::default().resolve("bug").unwrap();
+        assert_eq!(prompt.origin, Origin::Builtin);
+        assert!(prompt.text.contains("{{issue}}"));
+        assert!(PromptResolver::default().resolve("missing").is_err());
+    }
+
+    #[test]
+    fn test_search_path_order() {
+        let flag = TempDir::new().unwrap();
+        let repo = TempDir::new().unwrap();
+        let repo_prompts = repo.path().join(".chore-bot/prompts");
+        std::fs::create_dir_all(repo_prompts.join("feature")).unwrap();
+        std::fs::write(repo_prompts.join("bug.md"), "repo bug").unwrap();
+        std::fs::write(repo_prompts.join("feature/prompt.md"), "repo feature").unwrap();
+        std::fs::write(repo_prompts.join("docs.md"), "repo docs").unwrap();
+        std::fs::write(flag.path().join("bug.md"), "flag bug").unwrap();
+
+        let resolver = PromptResolver::new(Some(flag.path()), repo.path());
+
+        let bug = resolver.resolve("bug").unwrap();
+        assert_eq!((bug.origin, bug.text.as_str()), (Origin::Flag, "flag bug"));
+        let feature = resolver.resolve("feature").unwrap();
+        assert_eq!(feature.origin, Origin::Repo);
+        assert_eq!(feature.text, "repo feature");
+        assert_eq!(resolver.resolve("chore").unwrap().origin, Origin::Builtin);
+
+        let names = resolver.names();
+        assert!(names.contains("docs") && names.contains("refactor-plan"));
+    }
+
+    #[test]
+    fn test_md_paths_are_read_directly() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("custom.md");
+        std::fs::write(&path, "custom").unwrap();
+
+        let prompt = PromptResolver::default()
+            .resolve(&path.to_string_lossy())
+            .unwrap();
+        assert_eq!(prompt.origin, Origin::File);
+        assert_eq!(prompt.text, "custom");
+    }
+}
@@ -0,0 +1,244 @@
+//! Rebase conflicting PRs in a scratch worktree (`conflicts --rebase`)
+//!
+//! Closing a conflicting PR throws away everything the agent did, so before
+//! giving up on one chore-bot tries rebasing its branch onto the base branch
+//! itself. The rebase happens in a throwaway worktree of the local checkout,
+//! leaving the checkout untouched, and a clean result is pushed with
+//! `--force-with-lease` so a push the agent made in the meantime is never
+//! overwritten.
+
+use anyhow::{bail, Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// Remote PR branches are fetched from and pushed to
+const REMOTE: &str = "origin";
+
+/// Committer used when the checkout has no identity configured
+const FALLBACK_IDENTITY: [&str; 4] = [
+    "-c",
+    "user.name=chore-bot",
+    "-c",
+    "user.email=chore-bot@users.noreply.github.com",
+];
+
+/// Result of trying to rebase a PR branch
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// Rebased cleanly and pushed; the branch now points at `head`
+    Rebased { head: String },
+    /// The branch already contains the base branch, so nothing was pushed
+    UpToDate,
+    /// The rebase stopped on conflicts in these files
+    Conflicted { files: Vec<String> },
+}
+
+/// Rebase `head_ref` onto `base_ref` and push it if that applies cleanly
+pub fn rebase_branch(repo_path: &Path, head_ref: &str, base_ref: &str) -> Result<Outcome> {
+    if head_ref.is_empty() || base_ref.is_empty() {
+        bail!("The PR's head or base branch is unknown");
+    }
+
+    let head = format!("refs/remotes/{REMOTE}/{head_ref}");
+    let base = format!("refs/remotes/{REMOTE}/{base_ref}");
+    git(
+        repo_path,
+        &[
+            "fetch",
+            "--quiet",
+            REMOTE,
+            &format!("+refs/heads/{head_ref}:{head}"),
+            &format!("+refs/heads/{base_ref}:{base}"),
+        ],
+    )?;
+    let leased = git(repo_path, &["rev-parse", &head])?;
+
+    let is_ancestor = Command::new("git")
+        .args(["merge-base", "--is-ancestor", &base, &head])
+        .current_dir(repo_path)
+        .status()
+        .context("Failed to run git")?;
+    if is_ancestor.success() {
+        return Ok(Outcome::UpToDate);
+    }
+
+    let worktree = Worktree::add(repo_path, &leased)?;
+    let mut rebase = Command::new("git");
+    if git(repo_path, &["config", "user.email"]).is_err() {
+        rebase.args(FALLBACK_IDENTITY);
+    }
+    let rebased = rebase
+        .args(["rebase", "--quiet", &base])
+        .current_dir(&worktree.path)
+        .output()
+        .context("Failed to run git")?;
+
+    if !rebased.status.success() {
+        let files = git(&worktree.path, &["diff", "--name-only", "--diff-filter=U"])?;
+        let _ = git(&worktree.path, &["rebase", "--abort"]);
+        if files.is_empty() {
+            bail!("git rebase failed: {}", stderr(&rebased));
+        }
+        return Ok(Outcome::Conflicted {
+            files: files.lines().map(str::to_string).collect(),
+        });
+    }
+
+    let new_head = git(&worktree.path, &["rev-parse", "HEAD"])?;
+    git(
+        &worktree.path,
+        &[
+            "push",
+            "--quiet",
+            &format!("--force-with-lease=refs/heads/{head_ref}:{leased}"),
+            REMOTE,
+            &format!("HEAD:refs/heads/{head_ref}"),
+        ],
+    )?;
+
+    Ok(Outcome::Rebased { head: new_head })
+}
+
+/// Detached worktree in the temp directory, removed again on drop
+struct Worktree {
+    repo_path: PathBuf,
+    path: PathBuf,
+}
+
+impl Worktree {
+    fn add(repo_path: &Path, commit: &str) -> Result<Self> {
+        let path = std::env::temp_dir().join(format!(
+            "chore-bot-rebase-{}-{}",
+            std::process::id(),
+            &commit[..commit.len().min(12)]
+        ));
+        let path_arg = path.to_string_lossy();
+        git(
+            repo_path,
+            &["worktree", "add", "--quiet", "--detach", &path_arg, commit],
+        )?;
+        Ok(Self {
+            repo_path: repo_path.to_path_buf(),
+            path,
+        })
+    }
+}
+
+impl Drop for Worktree {
+    fn drop(&mut self) {
+        let path = self.path.to_string_lossy();
+        if git(&self.repo_path, &["worktree", "remove", "--force", &path]).is_err() {
+            let _ = std::fs::remove_dir_all(&self.path);
+            let _ = git(&self.repo_path, &["worktree", "prune"]);
+        }
+    }
+}
+
+/// Run git in `dir`, returning its trimmed stdout
+fn git(dir: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .context("Failed to run git")?;
+    if !output.status.success() {
+        bail!("git {} failed: {}", args[0], stderr(&output));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).trim().to_string()
+}
+
+/// Bare `origin` and a clone of it in `dir`, with `main` holding `a.txt` and
+/// `b.txt` and these branches pushed:
+///
+/// - `clean` edits `b.txt`, and rebases cleanly once `main` moves on
+/// - `clash` edits the line of `a.txt` that `main` then changes too, so it
+///   conflicts
+///
+/// Returns the clone; `main` has then moved on by editing `a.txt`.
+#[cfg(test)]
+pub fn scratch_remote(dir: &Path) -> PathBuf {
+    let origin = dir.join("origin.git");
+    let clone = dir.join("clone");
+    let run = |cwd: &Path, args: &[&str]| {
+        git(cwd, args).unwrap();
+    };
+    let commit = |file: &str, text: &str| {
+        std::fs::write(clone.join(file), text).unwrap();
+        run(
+            &clone,
+            &["commit", "--quiet", "-am", &format!("Edit {file}")],
+        );
+    };
+
+    run(
+        dir,
+        &["init", "--quiet", "--bare", "-b", "main", "origin.git"],
+    );
+    run(
+        dir,
+        &["clone", "--quiet", &origin.to_string_lossy(), "clone"],
+    );
+    run(&clone, &["config", "user.name", "Agent"]);
+    run(&clone, &["config", "user.email", "agent@example.com"]);
+    run(&clone, &["checkout", "--quiet", "-b", "main"]);
+    std::fs::write(clone.join("a.txt"), "one\n").unwrap();
+    std::fs::write(clone.join("b.txt"), "two\n").unwrap();
+    run(&clone, &["add", "."]);
+    run(&clone, &["commit", "--quiet", "-m", "Initial"]);
+
+    for (branch, file, text) in [("clean", "b.txt", "TWO\n"), ("clash", "a.txt", "uno\n")] {
+        run(&clone, &["checkout", "--quiet", "-b", branch, "main"]);
+        commit(file, text);
+    }
+    run(&clone, &["checkout", "--quiet", "main"]);
+    commit("a.txt", "ONE\n");
+    run(
+        &clone,
+        &["push", "--quiet", "origin", "main", "clean", "clash"],
+    );
+
+    clone
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_rebases_clean_branches_and_reports_conflicts() {
+        let dir = TempDir::new().unwrap();
+        let clone = scratch_remote(dir.path());
+        let origin = dir.path().join("origin.git");
+        let remote_head = |branch: &str| git(&origin, &["rev-parse", branch]).unwrap();
+        let clash = remote_head("clash");
+
+        let Outcome::Rebased { head } = rebase_branch(&clone, "clean", "main").unwrap() else {
+            panic!("expected a clean rebase");
+        };
+        assert_eq!(remote_head("clean"), head);
+        assert!(git(&origin, &["merge-base", "--is-ancestor", "main", "clean"]).is_ok());
+        assert_eq!(
+            rebase_branch(&clone, "clean", "main").unwrap(),
+            Outcome::UpToDate
+        );
+
+        assert_eq!(
+            rebase_branch(&clone, "clash", "main").unwrap(),
+            Outcome::Conflicted {
+                files: vec!["a.txt".to_string()]
+            }
+        );
+        assert_eq!(remote_head("clash"), clash);
+
+        // Scratch worktrees are cleaned up and the checkout is untouched
+        let worktrees = git(&clone, &["worktree", "list"]).unwrap();
+        assert_eq!(worktrees.lines().count(), 1);
+        assert_eq!(git(&clone, &["status", "--porcelain"]).unwrap(), "");
+        assert!(rebase_branch(&clone, "missing", "main").is_err());
+    }
+}
@@ -0,0 +1,191 @@
+//! Conflict-avoidance scheduling
+//!
+//! Two agents editing the same file produce PRs that conflict, which
+//! `conflicts --close` then has to clean up. Before spawning, each batch's
+//! files are predicted from its issues' file references and checked against
+//! the files open PRs already change and the batches picked earlier in the
+//! run. Overlapping batches are deferred; a later run picks them up once the
+//! other work has merged.
+
+use crate::forge::{Issue, PullRequest};
+use crate::sources::SourceTree;
+use crate::subagent::IssueBatch;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+/// Work already claiming a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Claim {
+    /// An open pull request changes it
+    Pr(u32),
+    /// A batch scheduled earlier in this run will touch it
+    Batch(String),
+}
+
+impl fmt::Display for Claim {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pr(number) => write!(f, "PR #{number}"),
+            Self::Batch(name) => write!(f, "batch {name}"),
+        }
+    }
+}
+
+/// A batch held back because another piece of work touches one of its files
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Deferred {
+    pub name: String,
+    pub file: String,
+    pub claim: Claim,
+}
+
+/// Batches to spawn now and those deferred to a later run
+#[derive(Debug, Default)]
+pub struct Schedule {
+    pub ready: Vec<IssueBatch>,
+    pub deferred: Vec<Deferred>,
+}
+
+/// Files a batch is expected to touch: those its issues resolve to.
+/// Issues that don't resolve to a file add nothing, so never block a batch.
+pub fn predict_files(batch: &[Issue], tree: &SourceTree) -> BTreeSet<String> {
+    batch
+        .iter()
+        .filter_map(|issue| tree.resolve(issue))
+        .collect()
+}
+
+/// Pick up to `limit` batches, in order, whose predicted files overlap
+/// neither an open PR's diff nor a batch picked before them. A PR whose files
+/// weren't all listed might touch any file, so it holds back every batch
+/// with a predicted file.
+pub fn plan(
+    batches: Vec<IssueBatch>,
+    open_prs: &[PullRequest],
+    tree: &SourceTree,
+    limit: usize,
+) -> Schedule {
+    let unlisted = open_prs.iter().find(|pr| pr.truncated).map(|pr| pr.number);
+    let mut claimed: HashMap<String, Claim> = HashMap::new();
+    for pr in open_prs {
+        for file in &pr.files {
+            claimed.entry(file.clone()).or_insert(Claim::Pr(pr.number));
+        }
+    }
+
+    let mut schedule = Schedule::default();
+    for (name, batch) in batches {
+        if schedule.ready.len() >= limit {
+            break;
+        }
+
+        let files = predict_files(&batch, tree);
+        if let Some((file, claim)) = files.iter().find_map(|file| {
+            claimed
+                .get(file)
+                .cloned()
+                .or(unlisted.map(Claim::Pr))
+                .map(|claim| (file, claim))
+        }) {
+            schedule.deferred.push(Deferred {
+                name,
+                file: file.clone(),
+                claim,
+            });
+            continue;
+        }
+
+        for file in files {
+            claimed.insert(file, Claim::Batch(name.clone()));
+        }
+        schedule.ready.push((name, batch));
+    }
+
+    schedule
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn issue(number: u32, file: &str) -> Issue {
+        Issue {
+            number,
+            title: format!("Issue {number}"),
+            body: format!("**File:** `{file}:1`"),
+            ..Issue::default()
+        }
+    }
+
+    #[test]
+    fn test_plan_defers_overlapping_batches() {
+        let root = TempDir::new().unwrap();
+        for file in ["src/a.rs", "src/b.rs", "src/c.rs", "src/d.rs"] {
+            let path = root.path().join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        }
+        let tree = SourceTree::scan(root.path());
+
+        let batches = vec![
+            (
+                "one".to_string(),
+                vec![issue(1, "src/a.rs"), issue(2, "src/b.rs")],
+            ),
+            ("two".to_string(), vec![issue(3, "src/b.rs")]),
+            ("three".to_string(), vec![issue(4, "src/c.rs")]),
+            ("four".to_string(), vec![Issue::default()]),
+            ("five".to_string(), vec![issue(5, "src/d.rs")]),
+        ];
+        let prs = vec![PullRequest {
+            number: 12,
+            files: vec!["src/c.rs".to_string()],
+            ..PullRequest::default()
+        }];
+
+        let schedule = plan(batches, &prs, &tree, 2);
+
+        let ready: Vec<&str> = schedule.ready.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(ready, ["one", "four"]);
+        assert_eq!(
+            schedule.deferred,
+            vec![
+                Deferred {
+                    name: "two".to_string(),
+                    file: "src/b.rs".to_string(),
+                    claim: Claim::Batch("one".to_string()),
+                },
+                Deferred {
+                    name: "three".to_string(),
+                    file: "src/c.rs".to_string(),
+                    claim: Claim::Pr(12),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plan_holds_back_batches_behind_a_truncated_pr() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("src")).unwrap();
+        std::fs::write(root.path().join("src/a.rs"), "").unwrap();
+        let tree = SourceTree::scan(root.path());
+
+        let batches = vec![
+            ("one".to_string(), vec![issue(1, "src/a.rs")]),
+            ("two".to_string(), vec![Issue::default()]),
+        ];
+        let prs = vec![PullRequest {
+            number: 12,
+            truncated: true,
+            ..PullRequest::default()
+        }];
+
+        let schedule = plan(batches, &prs, &tree, 5);
+
+        let ready: Vec<&str> = schedule.ready.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(ready, ["two"]);
+        assert_eq!(schedule.deferred[0].claim, Claim::Pr(12));
+    }
+}
@@ -0,0 +1,205 @@
+//! Resolving issues against the target repository's source tree
+//!
+//! Issues produced by the coverage and todo-scanner agents carry a
+//! `**File:** `path:line`` line; others only name a Rust path such as
+//! `parser::lexer::tokenize` in their title. Both are matched against the
+//! `.rs` files actually checked out, so batches follow the real module layout.
+
+use crate::analysis;
+use crate::forge::Issue;
+use std::path::{Path, PathBuf};
+
+/// Index of the `.rs` files in a checked-out repository
+#[derive(Debug, Default)]
+pub struct SourceTree {
+    root: PathBuf,
+    /// Paths relative to `root`, `/`-separated, sorted shortest first
+    files: Vec<String>,
+}
+
+impl SourceTree {
+    /// Index every Rust file under `root` (empty if it doesn't exist)
+    pub fn scan(root: &Path) -> Self {
+        let mut files: Vec<String> = analysis::rust_files(root)
+            .iter()
+            .filter_map(|file| file.strip_prefix(root).ok())
+            .map(|file| file.to_string_lossy().replace('\\', "/"))
+            .collect();
+        files.sort_by_key(|f| (f.len(), f.clone()));
+
+        Self {
+            root: root.to_path_buf(),
+            files,
+        }
+    }
+
+    /// The file an issue is about: an explicit `**File:**` line or `.rs` path
+    /// in the title or body, else the module named by a Rust path in the title
+    pub fn resolve(&self, issue: &Issue) -> Option<String> {
+        file_refs(&issue.body)
+            .into_iter()
+            .chain(file_refs(&issue.title))
+            .find_map(|path| self.find(&path))
+            .or_else(|| rust_path(&issue.title).and_then(|path| self.find_module(&path)))
+    }
+
+    /// Module (and crate, in a workspace) containing a repo-relative file,
+    /// e.g. `src/parser/lexer.rs` → `parser`, `crates/base/src/lib.rs` → `base`
+    pub fn module_of(&self, file: &str) -> String {
+        let path = Path::new(file);
+        let crate_dir = path
+            .ancestors()
+            .skip(1)
+            .find(|dir| self.root.join(dir).join("Cargo.toml").is_file())
+            .unwrap_or(Path::new(""));
+        let crate_name = crate_dir
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned());
+
+        let within = path.strip_prefix(crate_dir).unwrap_or(path);
+        let within = within.strip_prefix("src").unwrap_or(within);
+        let mut segments: Vec<String> = within
+            .parent()
+            .into_iter()
+            .flat_map(|dir| dir.components())
+            .map(|c| c.as_os_str().to_string_lossy().into_owned())
+            .collect();
+
+        // Top-level files are their own module, except the crate roots
+        if segments.is_empty() {
+            let stem = within.file_stem().unwrap_or_default().to_string_lossy();
+            if !matches!(stem.as_ref(), "lib" | "main" | "mod") {
+                segments.push(stem.into_owned());
+            }
+        }
+
+        let module = segments.join("-").replace('_', "-").to_lowercase();
+        match (crate_name, module.is_empty()) {
+            (Some(krate), true) => krate,
+            (Some(krate), false) => format!("{krate}-{module}"),
+            (None, true) => "root".to_string(),
+            (None, false) => module,
+        }
+    }
+
+    /// Find an indexed file by path, allowing crate- or workspace-relative paths
+    fn find(&self, path: &str) -> Option<String> {
+        let path = path.trim_start_matches("./");
+        let suffix = format!("/{path}");
+        self.files
+            .iter()
+            .find(|file| *file == path || file.ends_with(&suffix))
+            .cloned()
+    }
+
+    /// Find the file defining the longest prefix of a Rust path
+    fn find_module(&self, segments: &[&str]) -> Option<String> {
+        // The first segment may be the crate name rather than a module
+        (0..segments.len().min(2)).find_map(|start| {
+            (start + 1..=segments.len()).rev().find_map(|end| {
+                let module = segments[start..end].join("/");
+                self.find(&format!("{module}.rs"))
+                    .or_else(|| self.find(&format!("{module}/mod.rs")))
+            })
+        })
+    }
+}
+
+/// Paths from `**File:** `path:line`` lines and backticked `.rs` paths
+fn file_refs(text: &str) -> Vec<String> {
+    let re = regex::Regex::new(r"`([\w./-]+\.rs)(?::\d+)?`").unwrap();
+    re.captures_iter(text)
+        .filter_map(|cap| cap.get(1).map(|m| m.as_str().to_string()))
+        .collect()
+}
+
+/// Segments of the first `a::b::c` path in `text`, without `crate`/`self`
+fn rust_path(text: &str) -> Option<Vec<&str>> {
+    let re = regex::Regex::new(r"(\w+::)+\w+").unwrap();
+    let segments: Vec<&str> = re
+        .find(text)?
+        .as_str()
+        .split("::")
+        .filter(|s| !matches!(*s, "crate" | "self" | "super"))
+        .collect();
+    (!segments.is_empty()).then_some(segments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn issue(title: &str, body: &str) -> Issue {
+        Issue {
+            title: title.to_string(),
+            body: body.to_string(),
+            ..Issue::default()
+        }
+    }
+
+    fn tree(files: &[&str]) -> (TempDir, SourceTree) {
+        let root = TempDir::new().unwrap();
+        for file in files {
+            let path = root.path().join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        }
+        let tree = SourceTree::scan(root.path());
+        (root, tree)
+    }
+
+    #[test]
+    fn test_resolve_prefers_file_lines_then_rust_paths() {
+        let (_root, tree) = tree(&[
+            "Cargo.toml",
+            "src/lib.rs",
+            "src/parser/lexer.rs",
+            "src/server/mod.rs",
+        ]);
+
+        let from_body = issue("Untested", "**File:** `src/parser/lexer.rs:42`");
+        assert_eq!(tree.resolve(&from_body).unwrap(), "src/parser/lexer.rs");
+
+        let from_title = issue("test: Add tests for `widgets::parser::lexer::next`", "");
+        assert_eq!(tree.resolve(&from_title).unwrap(), "src/parser/lexer.rs");
+
+        let module_dir = issue("test: Add tests for `server::hover`", "");
+        assert_eq!(tree.resolve(&module_dir).unwrap(), "src/server/mod.rs");
+
+        assert!(tree.resolve(&issue("Add dark mode", "")).is_none());
+        assert!(tree
+            .resolve(&issue("x", "**File:** `src/gone.rs:1`"))
+            .is_none());
+    }
+
+    #[test]
+    fn test_module_of_follows_crates_and_directories() {
+        let (_root, tree) = tree(&[
+            "Cargo.toml",
+            "src/config.rs",
+            "crates/syster_base/Cargo.toml",
+            "crates/syster_base/src/parser/lexer.rs",
+            "crates/syster_base/src/lib.rs",
+        ]);
+
+        assert_eq!(tree.module_of("src/config.rs"), "config");
+        assert_eq!(tree.module_of("src/config/loader.rs"), "config");
+        assert_eq!(tree.module_of("src/main.rs"), "root");
+        assert_eq!(
+            tree.module_of("crates/syster_base/src/parser/lexer.rs"),
+            "syster_base-parser"
+        );
+        assert_eq!(
+            tree.module_of("crates/syster_base/src/lib.rs"),
+            "syster_base"
+        );
+
+        // Crate-relative references resolve inside the workspace
+        let issue = issue("", "**File:** `src/parser/lexer.rs:3`");
+        assert_eq!(
+            tree.resolve(&issue).unwrap(),
+            "crates/syster_base/src/parser/lexer.rs"
+        );
+    }
+}
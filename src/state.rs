@@ -0,0 +1,296 @@
+//! Persistent record of agent spawn attempts
+//!
+//! Stored as JSON, keyed by repository and issue number, so repeated cron runs
+//! don't spawn duplicate agents for issues that are already being worked on.
+
+use crate::forge::AgentTaskResult;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How long a successful spawn blocks another spawn for the same issue
+pub const SPAWN_TTL_SECS: u64 = 24 * 60 * 60;
+
+/// Outcome of a spawn attempt
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Spawned,
+    Failed,
+    /// The agent opened a PR for the issue
+    PrOpened,
+    /// The agent went quiet without opening a PR
+    Stale,
+}
+
+/// A single spawn attempt for an issue
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpawnRecord {
+    pub workflow: String,
+    pub task_id: Option<String>,
+    /// Unix timestamp (seconds) of the attempt
+    pub spawned_at: u64,
+    pub outcome: Outcome,
+    /// PR opened for the issue, once known
+    #[serde(default)]
+    pub pr: Option<u32>,
+}
+
+/// Spawn attempts keyed by repository (`owner/name`) and issue number
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct StateStore {
+    #[serde(skip)]
+    path: Option<PathBuf>,
+    repos: BTreeMap<String, BTreeMap<u32, SpawnRecord>>,
+}
+
+impl StateStore {
+    /// Load the store from `path`, starting empty if the file doesn't exist
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut store: Self = if path.exists() {
+            let content = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read state file: {}", path.display()))?;
+            serde_json::from_str(&content)
+                .with_context(|| format!("Failed to parse state file: {}", path.display()))?
+        } else {
+            Self::default()
+        };
+        store.path = Some(path.to_path_buf());
+        Ok(store)
+    }
+
+    /// Default location: `$XDG_STATE_HOME/chore-bot/state.json`, falling back
+    /// to `~/.local/state/chore-bot/state.json`
+    pub fn default_path() -> PathBuf {
+        let base = std::env::var_os("XDG_STATE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| {
+                std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state"))
+            })
+            .unwrap_or_else(|| PathBuf::from("."));
+        base.join("chore-bot").join("state.json")
+    }
+
+    /// Write the store back to disk (no-op for stores without a path). The
+    /// file is replaced in one rename, so a crash mid-write leaves the old one
+    pub fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut temp = path.clone().into_os_string();
+        temp.push(".tmp");
+        let temp = PathBuf::from(temp);
+
+        let write = || -> std::io::Result<()> {
+            let mut file = std::fs::File::create(&temp)?;
+            file.write_all(serde_json::to_string_pretty(self)?.as_bytes())?;
+            file.sync_all()?;
+            std::fs::rename(&temp, path)
+        };
+        write().with_context(|| format!("Failed to write state file: {}", path.display()))
+    }
+
+    /// Latest spawn attempt for an issue
+    pub fn latest(&self, repo: &str, issue: u32) -> Option<&SpawnRecord> {
+        self.repos.get(repo).and_then(|issues| issues.get(&issue))
+    }
+
+    /// All spawn records for a repository, by issue number
+    pub fn records(&self, repo: &str) -> impl Iterator<Item = (u32, &SpawnRecord)> {
+        self.repos
+            .get(repo)
+            .into_iter()
+            .flat_map(|issues| issues.iter().map(|(n, r)| (*n, r)))
+    }
+
+    /// Update the outcome (and PR) of an issue's latest spawn, without persisting
+    pub fn set_outcome(&mut self, repo: &str, issue: u32, outcome: Outcome, pr: Option<u32>) {
+        if let Some(record) = self
+            .repos
+            .get_mut(repo)
+            .and_then(|issues| issues.get_mut(&issue))
+        {
+            record.outcome = outcome;
+            record.pr = pr.or(record.pr);
+        }
+    }
+
+    /// Whether an agent was successfully spawned for the issue within the TTL
+    pub fn recently_spawned(&self, repo: &str, issue: u32, now: u64) -> bool {
+        self.latest(repo, issue).is_some_and(|record| {
+            record.outcome == Outcome::Spawned
+                && now.saturating_sub(record.spawned_at) < SPAWN_TTL_SECS
+        })
+    }
+
+    /// Whether chore-bot ever got an agent started on the issue. Unlike
+    /// `recently_spawned` this ignores the TTL, and agents that went quiet
+    /// still count: a PR they open days later closes a dispatched issue
+    pub fn dispatched(&self, repo: &str, issue: u32) -> bool {
+        self.latest(repo, issue)
+            .is_some_and(|record| record.outcome != Outcome::Failed)
+    }
+
+    /// Record a spawn attempt covering `issues` and persist it. A failed
+    /// attempt doesn't replace an earlier spawn, whose agent may still be
+    /// working on the issue
+    pub fn record(
+        &mut self,
+        repo: &str,
+        issues: &[u32],
+        workflow: &str,
+        result: &AgentTaskResult,
+        now: u64,
+    ) -> Result<()> {
+        let record = SpawnRecord {
+            workflow: workflow.to_string(),
+            task_id: result.task_id.clone(),
+            spawned_at: now,
+            outcome: if result.success {
+                Outcome::Spawned
+            } else {
+                Outcome::Failed
+            },
+            pr: None,
+        };
+
+        let entries = self.repos.entry(repo.to_string()).or_default();
+        for &issue in issues {
+            let spawned_before = entries
+                .get(&issue)
+                .is_some_and(|earlier| earlier.outcome != Outcome::Failed);
+            if record.outcome == Outcome::Failed && spawned_before {
+                continue;
+            }
+            entries.insert(issue, record.clone());
+        }
+
+        self.save()
+    }
+
+    /// Forget spawn attempts for `issues`, e.g. after closing their PR
+    pub fn forget(&mut self, repo: &str, issues: &[u32]) -> Result<()> {
+        if let Some(entries) = self.repos.get_mut(repo) {
+            for issue in issues {
+                entries.remove(issue);
+            }
+        }
+        self.save()
+    }
+}
+
+/// Current Unix timestamp in seconds
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn spawned(task_id: &str) -> AgentTaskResult {
+        AgentTaskResult {
+            success: true,
+            message: String::new(),
+            task_id: Some(task_id.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_record_round_trips_through_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("nested").join("state.json");
+
+        let mut store = StateStore::load(&path).unwrap();
+        store
+            .record("acme/widgets", &[4, 7], "test", &spawned("t1"), 1_000)
+            .unwrap();
+
+        let store = StateStore::load(&path).unwrap();
+        let record = store.latest("acme/widgets", 7).unwrap();
+        assert_eq!(record.task_id.as_deref(), Some("t1"));
+        assert_eq!(record.outcome, Outcome::Spawned);
+        assert!(store.latest("acme/other", 7).is_none());
+        // Written through a temp file that's renamed into place
+        assert!(!temp_dir.path().join("nested/state.json.tmp").exists());
+    }
+
+    #[test]
+    fn test_recently_spawned_respects_ttl_and_outcome() {
+        let mut store = StateStore::default();
+        store
+            .record("acme/widgets", &[1], "bug", &spawned("t1"), 1_000)
+            .unwrap();
+        let failed = AgentTaskResult {
+            success: false,
+            message: "quota".to_string(),
+            task_id: None,
+        };
+        store
+            .record("acme/widgets", &[2], "bug", &failed, 1_000)
+            .unwrap();
+
+        assert!(store.recently_spawned("acme/widgets", 1, 1_000 + 60));
+        assert!(!store.recently_spawned("acme/widgets", 1, 1_000 + SPAWN_TTL_SECS));
+        assert!(!store.recently_spawned("acme/widgets", 2, 1_000 + 60));
+
+        // Dispatched for good, even once the TTL is over or the agent went quiet
+        assert!(store.dispatched("acme/widgets", 1));
+        store.set_outcome("acme/widgets", 1, Outcome::Stale, None);
+        assert!(store.dispatched("acme/widgets", 1));
+        assert!(!store.dispatched("acme/widgets", 2));
+        assert!(!store.dispatched("acme/widgets", 3));
+    }
+
+    #[test]
+    fn test_failed_retry_keeps_the_earlier_spawn() {
+        let mut store = StateStore::default();
+        store
+            .record("acme/widgets", &[1], "bug", &spawned("t1"), 1_000)
+            .unwrap();
+        let failed = AgentTaskResult {
+            success: false,
+            message: "quota".to_string(),
+            task_id: None,
+        };
+        store
+            .record(
+                "acme/widgets",
+                &[1, 2],
+                "bug",
+                &failed,
+                1_000 + SPAWN_TTL_SECS,
+            )
+            .unwrap();
+
+        let record = store.latest("acme/widgets", 1).unwrap();
+        assert_eq!(record.task_id.as_deref(), Some("t1"));
+        assert!(store.dispatched("acme/widgets", 1));
+        assert_eq!(
+            store.latest("acme/widgets", 2).unwrap().outcome,
+            Outcome::Failed
+        );
+    }
+
+    #[test]
+    fn test_forget_clears_issues() {
+        let mut store = StateStore::default();
+        store
+            .record("acme/widgets", &[1, 2], "test", &spawned("t1"), 1_000)
+            .unwrap();
+        store.forget("acme/widgets", &[1]).unwrap();
+
+        assert!(store.latest("acme/widgets", 1).is_none());
+        assert!(store.latest("acme/widgets", 2).is_some());
+    }
+}
@@ -0,0 +1,297 @@
+//! Agent task lifecycle tracking
+//!
+//! Joins the spawn records in the [`StateStore`] with the forge's agent tasks
+//! and open PRs, classifies every task chore-bot created and writes the
+//! result back so stale or failed spawns stop blocking respawns.
+
+use crate::config::LinksConfig;
+use crate::forge::{AgentTaskState, Forge};
+use crate::state::{Outcome, SpawnRecord, StateStore};
+use crate::subagent;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+/// Where a spawned agent task stands
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskStatus {
+    Running,
+    PrOpened,
+    Failed,
+    Stale,
+}
+
+impl TaskStatus {
+    fn icon(self) -> &'static str {
+        match self {
+            Self::Running => "⏳",
+            Self::PrOpened => "✅",
+            Self::Failed => "❌",
+            Self::Stale => "💤",
+        }
+    }
+
+    fn outcome(self) -> Outcome {
+        match self {
+            Self::Running => Outcome::Spawned,
+            Self::PrOpened => Outcome::PrOpened,
+            Self::Failed => Outcome::Failed,
+            Self::Stale => Outcome::Stale,
+        }
+    }
+}
+
+/// One agent task chore-bot spawned, linked to its issues and PR
+#[derive(Debug, Clone, Serialize)]
+pub struct TaskReport {
+    pub task_id: Option<String>,
+    pub workflow: String,
+    pub issues: Vec<u32>,
+    pub pr: Option<u32>,
+    pub status: TaskStatus,
+    pub age_secs: u64,
+}
+
+/// Classify every recorded spawn for the forge's repository and persist the
+/// resulting outcomes
+pub fn reconcile(
+    forge: &dyn Forge,
+    state: &mut StateStore,
+    links: &LinksConfig,
+    now: u64,
+    stale_after_secs: u64,
+) -> Result<Vec<TaskReport>> {
+    let repo = forge.repo_name()?;
+    let tasks: HashMap<String, _> = forge
+        .list_agent_tasks()?
+        .into_iter()
+        .filter(|task| task.repo.as_deref().is_none_or(|r| r == repo))
+        .map(|task| (task.id.clone(), task))
+        .collect();
+    let prs_by_issue = subagent::open_prs_by_issue(forge, links)?;
+
+    // Batched spawns share a task id, so group issues by task
+    let mut groups: BTreeMap<String, (SpawnRecord, Vec<u32>)> = BTreeMap::new();
+    for (issue, record) in state.records(&repo) {
+        let key = record
+            .task_id
+            .clone()
+            .unwrap_or_else(|| format!("{}-{}", record.workflow, record.spawned_at));
+        groups
+            .entry(key)
+            .or_insert_with(|| (record.clone(), Vec::new()))
+            .1
+            .push(issue);
+    }
+
+    let mut reports = Vec::new();
+    for (record, issues) in groups.into_values() {
+        let task = record.task_id.as_ref().and_then(|id| tasks.get(id));
+        let age_secs = now.saturating_sub(record.spawned_at);
+
+        let pr = task
+            .and_then(|t| t.pr_number)
+            .or_else(|| issues.iter().find_map(|i| prs_by_issue.get(i).copied()))
+            .or(record.pr);
+
+        let status = if pr.is_some() {
+            TaskStatus::PrOpened
+        } else if record.outcome == Outcome::Failed
+            || task.is_some_and(|t| t.state == AgentTaskState::Failed)
+        {
+            TaskStatus::Failed
+        } else if task.map_or(
+            // Without the task only its age says whether it's still going
+            record.outcome == Outcome::Spawned && age_secs < stale_after_secs,
+            |t| t.state == AgentTaskState::Running,
+        ) {
+            TaskStatus::Running
+        } else {
+            TaskStatus::Stale
+        };
+
+        for &issue in &issues {
+            state.set_outcome(&repo, issue, status.outcome(), pr);
+        }
+
+        reports.push(TaskReport {
+            task_id: record.task_id,
+            workflow: record.workflow,
+            issues,
+            pr,
+            status,
+            age_secs,
+        });
+    }
+
+    state.save()?;
+    reports.sort_by_key(|r| r.age_secs);
+
+    Ok(reports)
+}
+
+fn format_age(secs: u64) -> String {
+    match secs {
+        s if s < 3600 => format!("{}m", s / 60),
+        s if s < 86400 => format!("{}h", s / 3600),
+        s => format!("{}d", s / 86400),
+    }
+}
+
+fn format_issues(issues: &[u32]) -> String {
+    issues
+        .iter()
+        .map(|n| format!("#{n}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+pub fn print_console_report(reports: &[TaskReport]) {
+    println!("📋 Agent Tasks ({}):", reports.len());
+    for report in reports {
+        let pr = report.pr.map(|n| format!(" → PR #{n}")).unwrap_or_default();
+        println!(
+            "  {} {:?} [{}] {} ({} ago){}",
+            report.status.icon(),
+            report.status,
+            report.workflow,
+            format_issues(&report.issues),
+            format_age(report.age_secs),
+            pr
+        );
+    }
+}
+
+pub fn generate_json_report(reports: &[TaskReport]) -> Result<String> {
+    serde_json::to_string_pretty(reports).context("Failed to serialize report")
+}
+
+pub fn generate_markdown_report(reports: &[TaskReport]) -> String {
+    let mut report = String::new();
+
+    report.push_str("# Agent Task Status\n\n");
+    report.push_str("| Task | Workflow | Issues | PR | Status | Age |\n");
+    report.push_str("|------|----------|--------|----|--------|-----|\n");
+
+    for task in reports {
+        report.push_str(&format!(
+            "| `{}` | {} | {} | {} | {} {:?} | {} |\n",
+            task.task_id.as_deref().unwrap_or("-"),
+            task.workflow,
+            format_issues(&task.issues),
+            task.pr
+                .map(|n| format!("#{n}"))
+                .unwrap_or_else(|| "-".into()),
+            task.status.icon(),
+            task.status,
+            format_age(task.age_secs)
+        ));
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::forge::fake::FakeForge;
+    use crate::forge::AgentTaskResult;
+
+    const HOUR: u64 = 3600;
+
+    fn forge() -> FakeForge {
+        FakeForge::from_json(
+            r#"{
+                "prs": [{ "number": 12, "title": "test: lexer", "body": "closes #4" }],
+                "agent_tasks": [
+                    { "id": "t-pr", "state": "completed" },
+                    { "id": "t-run", "state": "in_progress" },
+                    { "id": "t-fail", "state": "failed" },
+                    { "id": "t-old", "state": "in_progress" },
+                    { "id": "t-done", "state": "completed" }
+                ]
+            }"#,
+        )
+        .unwrap()
+    }
+
+    fn record(state: &mut StateStore, issues: &[u32], task: &str, at: u64) {
+        let result = AgentTaskResult {
+            success: true,
+            message: String::new(),
+            task_id: Some(task.to_string()),
+        };
+        state
+            .record("acme/widgets", issues, "test", &result, at)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_reconcile_classifies_tasks() {
+        let now = 100 * HOUR;
+        let mut state = StateStore::default();
+        record(&mut state, &[4, 7], "t-pr", now - HOUR);
+        record(&mut state, &[8], "t-run", now - HOUR);
+        record(&mut state, &[9], "t-fail", now - HOUR);
+        record(&mut state, &[10], "t-old", now - 10 * HOUR);
+        record(&mut state, &[11], "t-done", now - HOUR);
+        record(&mut state, &[13], "t-gone", now - 10 * HOUR);
+
+        let reports =
+            reconcile(&forge(), &mut state, &LinksConfig::default(), now, 6 * HOUR).unwrap();
+        let status = |id: &str| {
+            reports
+                .iter()
+                .find(|r| r.task_id.as_deref() == Some(id))
+                .unwrap()
+                .status
+        };
+
+        assert_eq!(status("t-pr"), TaskStatus::PrOpened);
+        assert_eq!(status("t-run"), TaskStatus::Running);
+        assert_eq!(status("t-fail"), TaskStatus::Failed);
+        // Still running however old it is; only finished or vanished tasks
+        // without a PR are stale
+        assert_eq!(status("t-old"), TaskStatus::Running);
+        assert_eq!(status("t-done"), TaskStatus::Stale);
+        assert_eq!(status("t-gone"), TaskStatus::Stale);
+
+        let batch = reports.iter().find(|r| r.status == TaskStatus::PrOpened);
+        assert_eq!(batch.unwrap().issues, vec![4, 7]);
+        assert_eq!(batch.unwrap().pr, Some(12));
+    }
+
+    #[test]
+    fn test_reconcile_frees_stale_issues_for_respawn() {
+        let now = 100 * HOUR;
+        let mut state = StateStore::default();
+        record(&mut state, &[10], "t-old", now - 10 * HOUR);
+        record(&mut state, &[13], "t-gone", now - 10 * HOUR);
+
+        reconcile(&forge(), &mut state, &LinksConfig::default(), now, 6 * HOUR).unwrap();
+
+        let latest = state.latest("acme/widgets", 13).unwrap();
+        assert_eq!(latest.outcome, Outcome::Stale);
+        assert!(!state.recently_spawned("acme/widgets", 13, now));
+        // A live agent keeps blocking duplicate spawns
+        assert!(state.recently_spawned("acme/widgets", 10, now));
+    }
+
+    #[test]
+    fn test_markdown_report_lists_tasks() {
+        let reports = vec![TaskReport {
+            task_id: Some("t1".to_string()),
+            workflow: "bug".to_string(),
+            issues: vec![30],
+            pr: Some(31),
+            status: TaskStatus::PrOpened,
+            age_secs: 2 * HOUR,
+        }];
+
+        let report = generate_markdown_report(&reports);
+        assert!(report.contains("`t1`"));
+        assert!(report.contains("#30"));
+        assert!(report.contains("2h"));
+    }
+}
@@ -1,203 +1,113 @@
-//! GitHub Copilot Agent spawning utilities
+//! Workflow helpers built on top of a [`Forge`]
 //!
-//! This module provides helpers for spawning GitHub Copilot agents via `gh agent-task create`.
-
+//! This module turns raw forge data into the shapes the workflows need:
+//! issue batches, failing PRs, conflicting PRs, pending workflow runs and
+//! mergeable agent PRs.
+
+use crate::approval::{self, Approval};
+use crate::budget;
+use crate::config::{ApproveConfig, Batching, Config, LinksConfig, WorkflowConfig};
+use crate::forge::{CheckRun, Forge, Issue, MergeState, PullRequest, WorkflowRun};
+use crate::links::Linker;
+use crate::merge::{self, Merge};
+use crate::sources::SourceTree;
+use crate::state::StateStore;
 use anyhow::Result;
-use std::path::Path;
-use std::process::Command;
-
-/// Issue with number and title
-pub type IssueWithTitle = (u32, String);
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
 
 /// Batch of issues grouped by module name
-pub type IssueBatch = (String, Vec<IssueWithTitle>);
+pub type IssueBatch = (String, Vec<Issue>);
 
-/// Response from spawning a GitHub agent task
-#[derive(Debug)]
-pub struct AgentTaskResult {
-    pub success: bool,
-    pub message: String,
+/// List issue numbers that are linked to open PRs (see [`Linker`])
+pub fn list_issues_with_open_prs(forge: &dyn Forge, links: &LinksConfig) -> Result<HashSet<u32>> {
+    Ok(open_prs_by_issue(forge, links)?.into_keys().collect())
 }
 
-/// Spawn a GitHub Copilot agent task
-pub fn spawn_agent(repo_path: &Path, task_description: &str) -> Result<AgentTaskResult> {
-    let output = Command::new("gh")
-        .args(["agent-task", "create", task_description])
-        .current_dir(repo_path)
-        .output()?;
-
-    Ok(AgentTaskResult {
-        success: output.status.success(),
-        message: if output.status.success() {
-            String::from_utf8_lossy(&output.stdout).to_string()
-        } else {
-            String::from_utf8_lossy(&output.stderr).to_string()
-        },
-    })
-}
+/// Map each issue linked to an open PR to that PR's number
+pub fn open_prs_by_issue(forge: &dyn Forge, links: &LinksConfig) -> Result<HashMap<u32, u32>> {
+    let linker = Linker::new(&forge.repo_name()?, links)?;
+    let mut issues = HashMap::new();
 
-/// Fetch issue details from GitHub
-pub fn fetch_issue(repo_path: &Path, issue_num: u32) -> Result<Option<(String, String)>> {
-    let output = Command::new("gh")
-        .args([
-            "issue",
-            "view",
-            &issue_num.to_string(),
-            "--json",
-            "title,body",
-        ])
-        .current_dir(repo_path)
-        .output()?;
-
-    if !output.status.success() {
-        return Ok(None);
+    for pr in forge.list_open_prs()? {
+        for issue in linker.linked_issues(&pr) {
+            issues.entry(issue).or_insert(pr.number);
+        }
     }
 
-    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap_or_default();
-    let title = json["title"].as_str().unwrap_or("").to_string();
-    let body = json["body"].as_str().unwrap_or("").to_string();
-
-    Ok(Some((title, body)))
+    Ok(issues)
 }
 
-/// Fetch issue title only
-pub fn fetch_issue_title(repo_path: &Path, issue_num: u32) -> Result<Option<String>> {
-    let output = Command::new("gh")
-        .args(["issue", "view", &issue_num.to_string(), "--json", "title"])
-        .current_dir(repo_path)
-        .output()?;
+/// Group issues by the module containing the file they refer to, falling back
+/// to the function path in the title when the file can't be found
+fn group_by_module(issues: Vec<Issue>, tree: &SourceTree) -> Vec<IssueBatch> {
+    let mut batches: HashMap<String, Vec<Issue>> = HashMap::new();
 
-    if !output.status.success() {
-        return Ok(None);
+    for issue in issues {
+        let module = match tree.resolve(&issue) {
+            Some(file) => tree.module_of(&file),
+            None => categorize_by_path(&issue.title),
+        };
+        batches.entry(module).or_default().push(issue);
     }
 
-    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap_or_default();
-    Ok(json["title"].as_str().map(|s| s.to_string()))
-}
-
-/// List issues by label
-pub fn list_issues_by_label(repo_path: &Path, label: &str) -> Result<Vec<u32>> {
-    let output = Command::new("gh")
-        .args([
-            "issue",
-            "list",
-            "--label",
-            label,
-            "--state",
-            "open",
-            "--limit",
-            "150",
-            "--json",
-            "number",
-            "--jq",
-            ".[].number",
-        ])
-        .current_dir(repo_path)
-        .output()?;
-
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let issues: Vec<u32> = stdout
-        .lines()
-        .filter_map(|line| line.trim().parse().ok())
-        .collect();
+    // Sort by batch size descending, then name, so runs are reproducible
+    let mut result: Vec<_> = batches.into_iter().collect();
+    result.sort_by(|a, b| b.1.len().cmp(&a.1.len()).then_with(|| a.0.cmp(&b.0)));
 
-    Ok(issues)
+    result
 }
 
-/// List open PR numbers
-pub fn list_open_prs(repo_path: &Path) -> Result<std::collections::HashSet<u32>> {
-    let output = Command::new("gh")
-        .args([
-            "pr",
-            "list",
-            "--state",
-            "open",
-            "--limit",
-            "150",
-            "--json",
-            "number",
-            "--jq",
-            ".[].number",
-        ])
-        .current_dir(repo_path)
-        .output()?;
-
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let prs: std::collections::HashSet<u32> = stdout
-        .lines()
-        .filter_map(|line| line.trim().parse().ok())
-        .collect();
+/// Split issues into chunks of `size`, named `batch-1`, `batch-2`, ...
+fn group_fixed(issues: Vec<Issue>, size: usize) -> Vec<IssueBatch> {
+    issues
+        .chunks(size.max(1))
+        .enumerate()
+        .map(|(i, chunk)| (format!("batch-{}", i + 1), chunk.to_vec()))
+        .collect()
+}
 
-    Ok(prs)
+/// One batch per issue, named `issue-<number>`
+fn group_single(issues: Vec<Issue>) -> Vec<IssueBatch> {
+    issues
+        .into_iter()
+        .map(|issue| (format!("issue-{}", issue.number), vec![issue]))
+        .collect()
 }
 
-/// List issue numbers that are linked to open PRs (via body text like "closes #123" or "#123")
-pub fn list_issues_with_open_prs(repo_path: &Path) -> Result<std::collections::HashSet<u32>> {
-    let output = Command::new("gh")
-        .args([
-            "pr",
-            "list",
-            "--state",
-            "open",
-            "--limit",
-            "150",
-            "--json",
-            "body,title",
-        ])
-        .current_dir(repo_path)
-        .output()?;
-
-    let mut issues = std::collections::HashSet::new();
-
-    if !output.status.success() {
-        return Ok(issues);
+/// Group issues into agent tasks according to the workflow's batching mode
+pub fn batch(workflow: &WorkflowConfig, issues: Vec<Issue>, tree: &SourceTree) -> Vec<IssueBatch> {
+    match workflow.batching {
+        Batching::Module => group_by_module(issues, tree),
+        Batching::Fixed => group_fixed(issues, workflow.batch_size as usize),
+        Batching::Single => group_single(issues),
     }
-
-    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap_or_default();
-
-    // Regex to find issue references like #123, closes #123, fixes #123
-    let re = regex::Regex::new(r"#(\d+)").unwrap();
-
-    if let Some(prs) = json.as_array() {
-        for pr in prs {
-            let body = pr.get("body").and_then(|b| b.as_str()).unwrap_or("");
-            let title = pr.get("title").and_then(|t| t.as_str()).unwrap_or("");
-
-            // Extract all issue numbers from body and title
-            for cap in re.captures_iter(body) {
-                if let Some(num) = cap.get(1).and_then(|m| m.as_str().parse().ok()) {
-                    issues.insert(num);
-                }
-            }
-            for cap in re.captures_iter(title) {
-                if let Some(num) = cap.get(1).and_then(|m| m.as_str().parse().ok()) {
-                    issues.insert(num);
-                }
-            }
-        }
-    }
-
-    Ok(issues)
 }
 
-/// Group issues by module based on function path in title
-pub fn group_by_module(repo_path: &Path, issues: &[u32]) -> Result<Vec<IssueBatch>> {
-    use std::collections::HashMap;
-
-    let mut batches: HashMap<String, Vec<IssueWithTitle>> = HashMap::new();
-
-    for &issue_num in issues {
-        if let Some(title) = fetch_issue_title(repo_path, issue_num)? {
-            let module = categorize_by_path(&title);
-            batches.entry(module).or_default().push((issue_num, title));
-        }
+/// Template context for one batch
+///
+/// Single-issue workflows get the issue's `issue`, `title` and `body`;
+/// batched workflows get the batch's `module`, `issues` and `count`.
+pub fn batch_context(
+    batching: Batching,
+    name: &str,
+    batch: &[Issue],
+    verify_command: &str,
+) -> Value {
+    match (batching, batch) {
+        (Batching::Single, [issue]) => json!({
+            "issue": issue.number,
+            "title": issue.title,
+            "body": issue.body,
+            "verify_command": verify_command,
+        }),
+        _ => json!({
+            "module": name,
+            "issues": batch,
+            "count": batch.len(),
+            "verify_command": verify_command,
+        }),
     }
-
-    // Sort by batch size descending
-    let mut result: Vec<_> = batches.into_iter().collect();
-    result.sort_by(|a, b| b.1.len().cmp(&a.1.len()));
-
-    Ok(result)
 }
 
 /// Categorize an issue title into a module name based on function path
@@ -226,43 +136,89 @@ fn categorize_by_path(title: &str) -> String {
     "misc".to_string()
 }
 
-/// Rerun all workflow runs waiting for approval (action_required)
-/// Uses API rerun since `gh run approve` only works for fork PRs
-pub fn approve_pending_workflows(repo_path: &Path) -> Result<Vec<(u64, bool)>> {
-    // Get workflow runs with action_required conclusion (waiting for approval)
-    let output = Command::new("gh")
-        .args([
-            "run",
-            "list",
-            "--json",
-            "databaseId,conclusion",
-            "--jq",
-            ".[] | select(.conclusion == \"action_required\") | .databaseId",
-        ])
-        .current_dir(repo_path)
-        .output()?;
-
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let run_ids: Vec<u64> = stdout
-        .lines()
-        .filter_map(|line| line.trim().parse().ok())
+/// Rerun the workflow runs waiting for approval (action_required) that the
+/// approval policy allows
+pub fn approve_pending_workflows(
+    forge: &dyn Forge,
+    config: &ApproveConfig,
+) -> Result<Vec<(WorkflowRun, Approval)>> {
+    let runs = forge.list_workflow_runs("action_required")?;
+    if runs.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let prs = forge.list_open_prs()?;
+    runs.into_iter()
+        .map(|run| {
+            let approval = approve_run(forge, config, &run, &prs)?;
+            Ok((run, approval))
+        })
+        .collect()
+}
+
+/// Rerun `run` if the approval policy allows it, checking the changed paths
+/// of its PR among `prs`
+pub fn approve_run(
+    forge: &dyn Forge,
+    config: &ApproveConfig,
+    run: &WorkflowRun,
+    prs: &[PullRequest],
+) -> Result<Approval> {
+    let pr = prs.iter().find(|pr| {
+        run.pr == Some(pr.number) || (!run.head_sha.is_empty() && pr.head_sha == run.head_sha)
+    });
+    if let Some(reason) = approval::skip_reason(config, run, pr) {
+        return Ok(Approval::Skipped(reason));
+    }
+
+    Ok(if forge.rerun_workflow(run.id)? {
+        Approval::Approved
+    } else {
+        Approval::Failed
+    })
+}
+
+/// Merge the open agent PRs the `[merge]` policy allows, oldest first, up to
+/// its `max_per_run`; a dry run only reports what it would merge
+pub fn merge_green_prs(
+    forge: &dyn Forge,
+    state: &StateStore,
+    config: &Config,
+    dry_run: bool,
+) -> Result<Vec<(PullRequest, Merge)>> {
+    let max = config.merge.max_per_run;
+    let repo = forge.repo_name()?;
+    let linker = Linker::new(&repo, &config.links)?;
+    let dispatched = |issue: u32| state.dispatched(&repo, issue);
+
+    let mut prs: Vec<PullRequest> = forge
+        .list_open_prs()?
+        .into_iter()
+        .filter(|pr| budget::is_agent(&pr.author, &config.budget.agent_authors))
         .collect();
+    prs.sort_by_key(|pr| pr.number);
 
+    let mut merged = 0;
     let mut results = Vec::new();
-
-    for run_id in run_ids {
-        // Use API rerun endpoint - works for Copilot actor runs
-        let rerun_result = Command::new("gh")
-            .args([
-                "api",
-                &format!("repos/{{owner}}/{{repo}}/actions/runs/{run_id}/rerun"),
-                "--method",
-                "POST",
-            ])
-            .current_dir(repo_path)
-            .output()?;
-
-        results.push((run_id, rerun_result.status.success()));
+    for pr in prs {
+        // Only issues GitHub will close count; a fallback pattern match
+        // could be a passing mention
+        let linked = linker.closing_issues(&pr);
+        let outcome =
+            if let Some(reason) = merge::skip_reason(&config.merge, &pr, &linked, dispatched) {
+                Merge::Skipped(reason)
+            } else if max > 0 && merged >= max {
+                Merge::Skipped(format!("the per-run merge cap ({max}) was reached"))
+            } else if dry_run {
+                merged += 1;
+                Merge::Ready
+            } else if forge.merge_pr(pr.number, config.merge.strategy, &pr.head_sha)? {
+                merged += 1;
+                Merge::Merged
+            } else {
+                Merge::Failed
+            };
+        results.push((pr, outcome));
     }
 
     Ok(results)
@@ -274,158 +230,109 @@ pub struct FailingPr {
     pub number: u32,
     pub title: String,
     pub author: String,
+    pub head_sha: String,
+    pub linked_issues: Vec<u32>,
+    pub failed_checks: Vec<CheckRun>,
 }
 
 /// List PRs with failing CI checks
-pub fn list_failing_prs(repo_path: &Path) -> Result<Vec<FailingPr>> {
-    let output = Command::new("gh")
-        .args([
-            "pr",
-            "list",
-            "--state",
-            "open",
-            "--limit",
-            "100",
-            "--json",
-            "number,title,author,headRefName,statusCheckRollup",
-        ])
-        .current_dir(repo_path)
-        .output()?;
-
-    if !output.status.success() {
-        return Ok(Vec::new());
-    }
-
-    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap_or_default();
-    let mut failing = Vec::new();
-
-    if let Some(prs) = json.as_array() {
-        for pr in prs {
-            let checks = pr.get("statusCheckRollup").and_then(|v| v.as_array());
-
-            // Check if any check has failed
-            let has_failure = checks.is_some_and(|checks| {
-                checks.iter().any(|check| {
-                    check.get("conclusion").and_then(|c| c.as_str()) == Some("FAILURE")
-                })
-            });
-
-            if has_failure {
-                failing.push(FailingPr {
-                    number: pr.get("number").and_then(|n| n.as_u64()).unwrap_or(0) as u32,
-                    title: pr
-                        .get("title")
-                        .and_then(|t| t.as_str())
-                        .unwrap_or("")
-                        .to_string(),
-                    author: pr
-                        .get("author")
-                        .and_then(|a| a.get("login"))
-                        .and_then(|l| l.as_str())
-                        .unwrap_or("")
-                        .to_string(),
-                });
-            }
-        }
-    }
+pub fn list_failing_prs(forge: &dyn Forge, links: &LinksConfig) -> Result<Vec<FailingPr>> {
+    let linker = Linker::new(&forge.repo_name()?, links)?;
+    let failing = forge
+        .list_open_prs()?
+        .into_iter()
+        .filter_map(|pr| {
+            let failed_checks: Vec<CheckRun> = pr
+                .checks
+                .iter()
+                .filter(|check| check.is_failure())
+                .cloned()
+                .collect();
+            (!failed_checks.is_empty()).then(|| FailingPr {
+                linked_issues: linker.linked_issues(&pr),
+                number: pr.number,
+                title: pr.title,
+                author: pr.author,
+                head_sha: pr.head_sha,
+                failed_checks,
+            })
+        })
+        .collect();
 
     Ok(failing)
 }
 
-/// Comment on a PR
-pub fn comment_on_pr(repo_path: &Path, pr_number: u32, comment: &str) -> Result<bool> {
-    let output = Command::new("gh")
-        .args(["pr", "comment", &pr_number.to_string(), "--body", comment])
-        .current_dir(repo_path)
-        .output()?;
-
-    Ok(output.status.success())
-}
-
 /// PR with merge conflict info
 #[derive(Debug)]
 pub struct ConflictingPr {
     pub number: u32,
     pub title: String,
     pub author: String,
+    pub head_sha: String,
+    pub head_ref: String,
+    pub base_ref: String,
     pub linked_issues: Vec<u32>,
 }
 
 /// List PRs with merge conflicts (mergeable state is CONFLICTING)
-pub fn list_conflicting_prs(repo_path: &Path) -> Result<Vec<ConflictingPr>> {
-    let output = Command::new("gh")
-        .args([
-            "pr",
-            "list",
-            "--state",
-            "open",
-            "--limit",
-            "100",
-            "--json",
-            "number,title,author,mergeable,body",
-        ])
-        .current_dir(repo_path)
-        .output()?;
-
-    if !output.status.success() {
-        return Ok(Vec::new());
-    }
-
-    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap_or_default();
-    let mut conflicting = Vec::new();
-
-    // Regex to find issue references like #123, closes #123, fixes #123
-    let re = regex::Regex::new(r"#(\d+)").unwrap();
-
-    if let Some(prs) = json.as_array() {
-        for pr in prs {
-            let mergeable = pr.get("mergeable").and_then(|m| m.as_str());
-
-            if mergeable == Some("CONFLICTING") {
-                let body = pr.get("body").and_then(|b| b.as_str()).unwrap_or("");
-                let title = pr.get("title").and_then(|t| t.as_str()).unwrap_or("");
-
-                // Extract linked issue numbers from body and title
-                let mut linked_issues: Vec<u32> = Vec::new();
-                for cap in re.captures_iter(body) {
-                    if let Some(num) = cap.get(1).and_then(|m| m.as_str().parse().ok()) {
-                        if !linked_issues.contains(&num) {
-                            linked_issues.push(num);
-                        }
-                    }
-                }
-                for cap in re.captures_iter(title) {
-                    if let Some(num) = cap.get(1).and_then(|m| m.as_str().parse().ok()) {
-                        if !linked_issues.contains(&num) {
-                            linked_issues.push(num);
-                        }
-                    }
-                }
-
-                conflicting.push(ConflictingPr {
-                    number: pr.get("number").and_then(|n| n.as_u64()).unwrap_or(0) as u32,
-                    title: title.to_string(),
-                    author: pr
-                        .get("author")
-                        .and_then(|a| a.get("login"))
-                        .and_then(|l| l.as_str())
-                        .unwrap_or("")
-                        .to_string(),
-                    linked_issues,
-                });
-            }
-        }
-    }
+pub fn list_conflicting_prs(forge: &dyn Forge, links: &LinksConfig) -> Result<Vec<ConflictingPr>> {
+    let linker = Linker::new(&forge.repo_name()?, links)?;
+    let conflicting = forge
+        .list_open_prs()?
+        .into_iter()
+        .filter(|pr| pr.mergeable == MergeState::Conflicting)
+        .map(|pr| ConflictingPr {
+            linked_issues: linker.linked_issues(&pr),
+            number: pr.number,
+            title: pr.title,
+            author: pr.author,
+            head_sha: pr.head_sha,
+            head_ref: pr.head_ref,
+            base_ref: pr.base_ref,
+        })
+        .collect();
 
     Ok(conflicting)
 }
 
-/// Close a PR
-pub fn close_pr(repo_path: &Path, pr_number: u32) -> Result<bool> {
-    let output = Command::new("gh")
-        .args(["pr", "close", &pr_number.to_string()])
-        .current_dir(repo_path)
-        .output()?;
+/// Workflow to respawn a closed PR's linked issue through: the first workflow
+/// whose label the issue carries, else the workflow `recorded` when it was
+/// last spawned, else the one named by the PR title's conventional-commit
+/// prefix (`test:`, `feat(parser):`, `fix:`, ...)
+pub fn respawn_workflow<'a>(
+    config: &'a Config,
+    issue: &Issue,
+    recorded: Option<&str>,
+    pr_title: &str,
+) -> Option<&'a str> {
+    let known = |name: &str| {
+        config
+            .workflows
+            .get_key_value(name)
+            .map(|(k, _)| k.as_str())
+    };
+
+    let by_label = config.workflows.iter().find(|(_, workflow)| {
+        issue.labels.contains(&workflow.label) && !workflow.excludes(&issue.labels)
+    });
+    if let Some((name, _)) = by_label {
+        return Some(name);
+    }
+    if let Some(name) = recorded.and_then(known) {
+        return Some(name);
+    }
 
-    Ok(output.status.success())
+    let (prefix, _) = pr_title.split_once(':')?;
+    let kind = prefix
+        .split('(')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .trim_end_matches('!')
+        .to_lowercase();
+    known(&kind).or_else(|| match kind.as_str() {
+        "feat" => known("feature"),
+        "fix" => known("bug"),
+        _ => None,
+    })
 }
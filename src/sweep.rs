@@ -0,0 +1,424 @@
+//! Multi-repository runs (`chore-bot sweep`)
+//!
+//! A manifest lists the repositories chore-bot looks after and the commands
+//! to run against each, so one scheduled invocation covers all of them:
+//!
+//! ```toml
+//! commands = ["approve", "nudge", "conflicts-close", "test"]
+//! clone_dir = "repos"
+//!
+//! [[repos]]
+//! name = "jade-codes/syster"
+//!
+//! [[repos]]
+//! name = "jade-codes/mother"
+//! path = "../mother"          # existing checkout instead of a clone
+//! commands = ["nudge"]
+//!
+//! [repos.config.workflows.test]
+//! max_agents = 2              # layered over the repo's chore-bot.toml
+//!
+//! [schedules]                 # for `chore-bot daemon`
+//! approve = "*/30 * * * *"
+//! test = "0 */6 * * *"
+//! ```
+
+use crate::cron::Cron;
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+
+/// One command run against a repository
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Step {
+    Approve,
+    Nudge,
+    Merge,
+    /// `conflicts`, or `conflicts-close` to close and respawn; with a
+    /// `-rebase` infix (`conflicts-rebase-close`) a rebase is tried first
+    Conflicts {
+        close: bool,
+        rebase: bool,
+    },
+    /// `prompts check`
+    PromptsCheck,
+    /// Any configured workflow, e.g. `test`
+    Workflow(String),
+}
+
+impl FromStr for Step {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "approve" => Self::Approve,
+            "nudge" => Self::Nudge,
+            "merge" => Self::Merge,
+            "conflicts" | "conflicts-close" | "conflicts-rebase" | "conflicts-rebase-close" => {
+                Self::Conflicts {
+                    close: s.ends_with("-close"),
+                    rebase: s.contains("-rebase"),
+                }
+            }
+            "prompts-check" => Self::PromptsCheck,
+            name if !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') =>
+            {
+                Self::Workflow(name.to_string())
+            }
+            _ => return Err(format!("Unknown sweep command {s:?}")),
+        })
+    }
+}
+
+impl TryFrom<String> for Step {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Approve => f.write_str("approve"),
+            Self::Nudge => f.write_str("nudge"),
+            Self::Merge => f.write_str("merge"),
+            Self::Conflicts { close, rebase } => {
+                f.write_str("conflicts")?;
+                if *rebase {
+                    f.write_str("-rebase")?;
+                }
+                if *close {
+                    f.write_str("-close")?;
+                }
+                Ok(())
+            }
+            Self::PromptsCheck => f.write_str("prompts-check"),
+            Self::Workflow(name) => f.write_str(name),
+        }
+    }
+}
+
+/// A repository listed in the manifest
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RepoEntry {
+    /// `owner/name`
+    pub name: String,
+    /// Existing checkout; cloned under `clone_dir` when unset
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+    /// Commands for this repository instead of the manifest's
+    #[serde(default)]
+    pub commands: Option<Vec<Step>>,
+    /// Config layered over the repository's own `chore-bot.toml`
+    #[serde(default)]
+    pub config: toml::Table,
+}
+
+/// The repositories to sweep and what to run against them
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    #[serde(default = "default_commands")]
+    pub commands: Vec<Step>,
+    /// Where repositories without a `path` are cloned
+    #[serde(default = "default_clone_dir")]
+    pub clone_dir: PathBuf,
+    pub repos: Vec<RepoEntry>,
+    /// Cron expression per command, for `daemon`
+    #[serde(default)]
+    pub schedules: BTreeMap<String, String>,
+    /// Random delay of up to this many seconds added to each scheduled run
+    #[serde(default)]
+    pub jitter_secs: u64,
+}
+
+/// What the scheduled GitHub workflow has always run
+fn default_commands() -> Vec<Step> {
+    ["approve", "nudge", "conflicts-close", "test"]
+        .iter()
+        .map(|s| s.parse().unwrap())
+        .collect()
+}
+
+fn default_clone_dir() -> PathBuf {
+    PathBuf::from("repos")
+}
+
+impl Manifest {
+    /// Load a manifest, resolving paths against its directory
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read manifest: {}", path.display()))?;
+        let mut manifest: Self = toml::from_str(&content)
+            .with_context(|| format!("Invalid manifest: {}", path.display()))?;
+
+        for repo in &manifest.repos {
+            if repo.name.split('/').filter(|s| !s.is_empty()).count() != 2 {
+                bail!("Manifest repo {:?} must be owner/name", repo.name);
+            }
+        }
+        manifest
+            .schedules()
+            .with_context(|| format!("Invalid manifest: {}", path.display()))?;
+
+        let base = path.parent().unwrap_or(Path::new("."));
+        manifest.clone_dir = base.join(&manifest.clone_dir);
+        for repo in &mut manifest.repos {
+            if let Some(path) = &repo.path {
+                repo.path = Some(base.join(path));
+            }
+        }
+
+        Ok(manifest)
+    }
+
+    /// Repositories to visit with their commands, limited to `repos` and
+    /// overridden by `only` when non-empty
+    pub fn plan(&self, repos: &[String], only: &[Step]) -> Result<Vec<(&RepoEntry, Vec<Step>)>> {
+        if let Some(unknown) = repos
+            .iter()
+            .find(|name| !self.repos.iter().any(|r| r.name == **name))
+        {
+            bail!("{unknown:?} is not in the manifest");
+        }
+
+        Ok(self
+            .repos
+            .iter()
+            .filter(|repo| repos.is_empty() || repos.contains(&repo.name))
+            .map(|repo| {
+                let steps = if only.is_empty() {
+                    repo.commands.as_ref().unwrap_or(&self.commands).clone()
+                } else {
+                    only.to_vec()
+                };
+                (repo, steps)
+            })
+            .collect())
+    }
+
+    /// The `[schedules]` table parsed into commands and their cron schedules
+    pub fn schedules(&self) -> Result<Vec<(Step, Cron)>> {
+        self.schedules
+            .iter()
+            .map(|(step, cron)| {
+                let step: Step = step.parse().map_err(anyhow::Error::msg)?;
+                let cron: Cron = cron
+                    .parse()
+                    .with_context(|| format!("Bad schedule for {step}"))?;
+                Ok((step, cron))
+            })
+            .collect()
+    }
+
+    /// Local checkout for `repo`: its `path`, else `<clone_dir>/<owner>/<name>`
+    pub fn checkout_path(&self, repo: &RepoEntry) -> PathBuf {
+        repo.path
+            .clone()
+            .unwrap_or_else(|| self.clone_dir.join(&repo.name))
+    }
+
+    /// Make sure `repo` is checked out, cloning it with `gh` or fast-forwarding
+    /// an earlier clone
+    pub fn checkout(&self, repo: &RepoEntry) -> Result<PathBuf> {
+        let path = self.checkout_path(repo);
+
+        if repo.path.is_some() {
+            if !path.is_dir() {
+                bail!("Checkout {} does not exist", path.display());
+            }
+            return Ok(path);
+        }
+
+        if path.is_dir() {
+            let pulled = Command::new("git")
+                .args(["pull", "--ff-only", "--quiet"])
+                .current_dir(&path)
+                .status()
+                .is_ok_and(|status| status.success());
+            if !pulled {
+                println!("⚠️  Could not update {}, using it as is", path.display());
+            }
+            return Ok(path);
+        }
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let status = Command::new("gh")
+            .args(["repo", "clone", &repo.name])
+            .arg(&path)
+            .status()
+            .context("Failed to run gh")?;
+        if !status.success() {
+            bail!("Failed to clone {}", repo.name);
+        }
+
+        Ok(path)
+    }
+}
+
+/// Outcome of one command against one repository
+#[derive(Debug)]
+pub struct StepResult {
+    pub repo: String,
+    pub step: Step,
+    /// Agents spawned, or the error
+    pub outcome: Result<usize, String>,
+}
+
+/// Results of a sweep, in the order they ran
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub results: Vec<StepResult>,
+}
+
+impl Summary {
+    pub fn record(&mut self, repo: &str, step: &Step, outcome: Result<usize>) {
+        self.results.push(StepResult {
+            repo: repo.to_string(),
+            step: step.clone(),
+            outcome: outcome.map_err(|e| format!("{e:#}")),
+        });
+    }
+
+    pub fn failures(&self) -> usize {
+        self.results.iter().filter(|r| r.outcome.is_err()).count()
+    }
+
+    pub fn spawned(&self) -> usize {
+        self.results
+            .iter()
+            .filter_map(|r| r.outcome.as_ref().ok())
+            .sum()
+    }
+
+    /// Per-repository table of every step and a totals line
+    pub fn render(&self) -> String {
+        let mut out = String::from("📊 Sweep Summary\n");
+        let mut repos = 0;
+
+        for (i, result) in self.results.iter().enumerate() {
+            if i == 0 || self.results[i - 1].repo != result.repo {
+                out.push_str(&format!("\n  {}\n", result.repo));
+                repos += 1;
+            }
+            let line = match &result.outcome {
+                Ok(0) => format!("    ✅ {}", result.step),
+                Ok(1) => format!("    ✅ {} (1 agent)", result.step),
+                Ok(n) => format!("    ✅ {} ({n} agents)", result.step),
+                Err(e) => format!("    ❌ {}: {e}", result.step),
+            };
+            out.push_str(&line);
+            out.push('\n');
+        }
+
+        out.push_str(&format!(
+            "\n{}/{} steps succeeded across {} repositories, {} agents spawned\n",
+            self.results.len() - self.failures(),
+            self.results.len(),
+            repos,
+            self.spawned()
+        ));
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_manifest_load_and_plan() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("repos.toml");
+        std::fs::write(
+            &path,
+            r#"
+            [[repos]]
+            name = "acme/widgets"
+
+            [[repos]]
+            name = "acme/gadgets"
+            path = "../gadgets"
+            commands = ["nudge", "docs", "merge"]
+
+            [repos.config.workflows.test]
+            max_agents = 1
+
+            [schedules]
+            conflicts-rebase-close = "@hourly"
+            test = "0 */6 * * *"
+            "#,
+        )
+        .unwrap();
+
+        let manifest = Manifest::load(&path).unwrap();
+        let widgets = &manifest.repos[0];
+        assert_eq!(
+            manifest.checkout_path(widgets),
+            dir.path().join("repos/acme/widgets")
+        );
+        assert_eq!(
+            manifest.checkout_path(&manifest.repos[1]),
+            dir.path().join("../gadgets")
+        );
+        assert!(manifest.repos[1].config.contains_key("workflows"));
+        let schedules = manifest.schedules().unwrap();
+        assert_eq!(
+            schedules[0].0,
+            Step::Conflicts {
+                close: true,
+                rebase: true
+            }
+        );
+        assert_eq!(schedules[0].0.to_string(), "conflicts-rebase-close");
+        assert_eq!(schedules[1].1.to_string(), "0 */6 * * *");
+
+        let plan = manifest.plan(&[], &[]).unwrap();
+        let steps: Vec<String> = plan[0].1.iter().map(|s| s.to_string()).collect();
+        assert_eq!(steps, ["approve", "nudge", "conflicts-close", "test"]);
+        assert_eq!(plan[1].1[1], Step::Workflow("docs".to_string()));
+        assert_eq!(plan[1].1[2], Step::Merge);
+
+        let only = manifest
+            .plan(&["acme/gadgets".to_string()], &[Step::Approve])
+            .unwrap();
+        assert_eq!(only.len(), 1);
+        assert_eq!(only[0].1, vec![Step::Approve]);
+        assert!(manifest.plan(&["acme/nope".to_string()], &[]).is_err());
+
+        std::fs::write(&path, "[[repos]]\nname = \"widgets\"\n").unwrap();
+        assert!(Manifest::load(&path).is_err());
+        std::fs::write(&path, "commands = [\"rm -rf\"]\nrepos = []\n").unwrap();
+        assert!(Manifest::load(&path).is_err());
+        std::fs::write(&path, "repos = []\n[schedules]\ntest = \"0 25 * * *\"\n").unwrap();
+        assert!(Manifest::load(&path).is_err());
+    }
+
+    #[test]
+    fn test_summary_render() {
+        let mut summary = Summary::default();
+        summary.record("acme/widgets", &Step::Nudge, Ok(0));
+        summary.record("acme/widgets", &"test".parse().unwrap(), Ok(2));
+        summary.record("acme/gadgets", &Step::Approve, Err(anyhow::anyhow!("boom")));
+
+        let rendered = summary.render();
+        assert!(rendered.contains("  acme/widgets\n    ✅ nudge\n    ✅ test (2 agents)\n"));
+        assert!(rendered.contains("    ❌ approve: boom\n"));
+        assert!(rendered.contains("2/3 steps succeeded across 2 repositories, 2 agents spawned"));
+        assert_eq!(summary.failures(), 1);
+    }
+}
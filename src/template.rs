@@ -0,0 +1,533 @@
+//! Prompt and comment templates
+//!
+//! A small Handlebars-style language rendered against a JSON context:
+//!
+//! - `{{name}}`, `{{issue.title}}` — variables, with dotted paths
+//! - `{{title | truncate 80}}`, `{{module | snake_case}}`,
+//!   `{{body | default "n/a"}}` — filters, applied left to right
+//! - `{{#each issues}}…{{/each}}` — loops; inside, fields of the item are in
+//!   scope alongside `this`, `@index`, `@first` and `@last`
+//! - `{{#if x}}…{{else}}…{{/if}}` and `{{#unless x}}…{{/unless}}` — conditionals
+//!
+//! Rendering is strict: a variable missing from the context is an error
+//! (unless it has a `default`), so a typo can't reach an agent unreplaced.
+//! Block tags alone on a line don't leave a blank line behind.
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::Value;
+use std::collections::BTreeSet;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Filter {
+    SnakeCase,
+    Truncate(usize),
+    Default(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Text(String),
+    Var {
+        path: String,
+        filters: Vec<Filter>,
+        line: usize,
+    },
+    Each {
+        path: String,
+        body: Vec<Node>,
+        line: usize,
+    },
+    If {
+        path: String,
+        negate: bool,
+        then: Vec<Node>,
+        otherwise: Vec<Node>,
+        line: usize,
+    },
+}
+
+/// Render `template` against `context`
+pub fn render(template: &str, context: &Value) -> Result<String> {
+    let nodes = parse(template)?;
+    let mut out = String::new();
+    render_nodes(&nodes, &mut vec![Frame::root(context)], &mut out)?;
+    Ok(out)
+}
+
+/// Check that `template` parses, without rendering it
+pub fn validate(template: &str) -> Result<()> {
+    parse(template).map(|_| ())
+}
+
+/// Top-level variables a template references
+#[derive(Debug, Default, PartialEq)]
+pub struct Variables {
+    /// Must be in the context
+    pub required: BTreeSet<String>,
+    /// Used only with a `default`
+    pub optional: BTreeSet<String>,
+    /// Used inside `#each` bodies, where they may be item fields instead
+    pub nested: BTreeSet<String>,
+}
+
+impl Variables {
+    fn add(&mut self, path: &str, nested: bool, optional: bool) {
+        let head = path.split('.').next().unwrap_or(path);
+        if head == "this" || head.starts_with('@') {
+            return;
+        }
+        let set = match (nested, optional) {
+            (true, _) => &mut self.nested,
+            (false, true) => &mut self.optional,
+            (false, false) => &mut self.required,
+        };
+        set.insert(head.to_string());
+    }
+
+    /// Whether `name` is referenced anywhere
+    pub fn uses(&self, name: &str) -> bool {
+        self.required.contains(name) || self.optional.contains(name) || self.nested.contains(name)
+    }
+}
+
+/// Collect the variables `template` references, by first path segment
+pub fn variables(template: &str) -> Result<Variables> {
+    let mut vars = Variables::default();
+    collect(&parse(template)?, false, &mut vars);
+
+    // Anything required somewhere isn't optional
+    vars.optional = &vars.optional - &vars.required;
+    Ok(vars)
+}
+
+fn collect(nodes: &[Node], nested: bool, vars: &mut Variables) {
+    for node in nodes {
+        match node {
+            Node::Text(_) => {}
+            Node::Var { path, filters, .. } => {
+                let optional = filters.iter().any(|f| matches!(f, Filter::Default(_)));
+                vars.add(path, nested, optional);
+            }
+            Node::Each { path, body, .. } => {
+                vars.add(path, nested, false);
+                collect(body, true, vars);
+            }
+            Node::If {
+                path,
+                then,
+                otherwise,
+                ..
+            } => {
+                vars.add(path, nested, false);
+                collect(then, nested, vars);
+                collect(otherwise, nested, vars);
+            }
+        }
+    }
+}
+
+// --- Parsing ---------------------------------------------------------------
+
+#[derive(Debug)]
+enum Token {
+    Text(String),
+    Tag(String, usize),
+}
+
+/// Split a template into text and `{{tag}}` tokens, dropping the line around
+/// block tags that stand alone on it
+fn tokenize(template: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut rest = template;
+    let mut line = 1;
+    // Whether `rest` starts at the beginning of a line
+    let mut at_line_start = true;
+
+    while let Some(start) = rest.find("{{") {
+        let end = rest[start..]
+            .find("}}")
+            .map(|i| start + i)
+            .ok_or_else(|| anyhow!("line {line}: unclosed `{{{{`"))?;
+
+        let mut text = &rest[..start];
+        let tag = rest[start + 2..end].trim();
+        let mut after = &rest[end + 2..];
+        let tag_line = line + text.matches('\n').count();
+
+        let mut standalone = false;
+        if is_block_tag(tag) {
+            let line_start = text.rfind('\n').map(|i| i + 1);
+            let line_end = after.find('\n');
+            let before_blank = text[line_start.unwrap_or(0)..].trim().is_empty();
+            let after_blank = after[..line_end.unwrap_or(after.len())].trim().is_empty();
+            if before_blank && after_blank && (line_start.is_some() || at_line_start) {
+                text = &text[..line_start.unwrap_or(0)];
+                after = line_end.map_or("", |i| &after[i + 1..]);
+                line += 1;
+                standalone = true;
+            }
+        }
+        at_line_start = standalone;
+
+        line += text.matches('\n').count();
+        if !text.is_empty() {
+            tokens.push(Token::Text(text.to_string()));
+        }
+        tokens.push(Token::Tag(tag.to_string(), tag_line));
+        line += rest[start..end].matches('\n').count();
+        rest = after;
+    }
+
+    if !rest.is_empty() {
+        tokens.push(Token::Text(rest.to_string()));
+    }
+
+    Ok(tokens)
+}
+
+fn is_block_tag(tag: &str) -> bool {
+    tag.starts_with('#') || tag.starts_with('/') || tag == "else"
+}
+
+fn parse(template: &str) -> Result<Vec<Node>> {
+    let mut tokens = tokenize(template)?.into_iter();
+    let (nodes, end) = parse_block(&mut tokens)?;
+    if let Some((tag, line)) = end {
+        bail!("line {line}: unexpected `{{{{{tag}}}}}`");
+    }
+    Ok(nodes)
+}
+
+/// A closing tag (`/…` or `else`) and its line
+type Closing = Option<(String, usize)>;
+
+/// Parse nodes until a closing tag, returning it
+fn parse_block(tokens: &mut impl Iterator<Item = Token>) -> Result<(Vec<Node>, Closing)> {
+    let mut nodes = Vec::new();
+
+    while let Some(token) = tokens.next() {
+        let (tag, line) = match token {
+            Token::Text(text) => {
+                nodes.push(Node::Text(text));
+                continue;
+            }
+            Token::Tag(tag, line) => (tag, line),
+        };
+
+        if tag.starts_with('/') || tag == "else" {
+            return Ok((nodes, Some((tag, line))));
+        }
+
+        if let Some(block) = tag.strip_prefix('#') {
+            let (kind, path) = block
+                .split_once(char::is_whitespace)
+                .map(|(k, p)| (k, p.trim().to_string()))
+                .ok_or_else(|| anyhow!("line {line}: `{{{{#{block}}}}}` needs a variable"))?;
+
+            let (body, end) = parse_block(tokens)?;
+            let closing = format!("/{kind}");
+            match (kind, end) {
+                ("each", Some((end, _))) if end == closing => {
+                    nodes.push(Node::Each { path, body, line })
+                }
+                ("if" | "unless", Some((end, _))) => {
+                    let otherwise = if end == "else" {
+                        let (otherwise, end) = parse_block(tokens)?;
+                        if end.as_ref().map(|(e, _)| e.as_str()) != Some(closing.as_str()) {
+                            bail!("line {line}: `{{{{#{kind}}}}}` is missing `{{{{{closing}}}}}`");
+                        }
+                        otherwise
+                    } else if end == closing {
+                        Vec::new()
+                    } else {
+                        bail!("line {line}: `{{{{#{kind}}}}}` closed by `{{{{{end}}}}}`");
+                    };
+                    nodes.push(Node::If {
+                        path,
+                        negate: kind == "unless",
+                        then: body,
+                        otherwise,
+                        line,
+                    });
+                }
+                ("each" | "if" | "unless", _) => {
+                    bail!("line {line}: `{{{{#{kind}}}}}` is missing `{{{{{closing}}}}}`")
+                }
+                _ => bail!("line {line}: unknown block `{{{{#{kind}}}}}`"),
+            }
+            continue;
+        }
+
+        let mut parts = tag.split('|').map(str::trim);
+        let path = parts.next().unwrap_or_default().to_string();
+        if path.is_empty() {
+            bail!("line {line}: empty `{{{{}}}}`");
+        }
+        let filters = parts
+            .map(parse_filter)
+            .collect::<Result<_>>()
+            .with_context(|| format!("line {line}"))?;
+        nodes.push(Node::Var {
+            path,
+            filters,
+            line,
+        });
+    }
+
+    Ok((nodes, None))
+}
+
+fn parse_filter(filter: &str) -> Result<Filter> {
+    let (name, arg) = filter
+        .split_once(char::is_whitespace)
+        .map_or((filter, ""), |(n, a)| (n, a.trim()));
+
+    match name {
+        "snake_case" => Ok(Filter::SnakeCase),
+        "truncate" => arg
+            .parse()
+            .map(Filter::Truncate)
+            .map_err(|_| anyhow!("`truncate` needs a length, got {arg:?}")),
+        "default" => {
+            let value = arg
+                .strip_prefix('"')
+                .and_then(|a| a.strip_suffix('"'))
+                .ok_or_else(|| anyhow!("`default` needs a quoted value, got {arg:?}"))?;
+            Ok(Filter::Default(value.to_string()))
+        }
+        _ => bail!("unknown filter `{name}`"),
+    }
+}
+
+// --- Rendering -------------------------------------------------------------
+
+/// A scope: the root context or the current `#each` item
+struct Frame {
+    value: Value,
+    /// Position and length of the enclosing loop
+    index: Option<(usize, usize)>,
+}
+
+impl Frame {
+    fn root(value: &Value) -> Self {
+        Self {
+            value: value.clone(),
+            index: None,
+        }
+    }
+}
+
+/// Look `path` up from the innermost scope outwards
+fn lookup(path: &str, scopes: &[Frame]) -> Option<Value> {
+    let (head, tail) = path.split_once('.').unwrap_or((path, ""));
+    let frame = scopes.last()?;
+
+    let value = match head {
+        "this" => frame.value.clone(),
+        "@index" => return frame.index.map(|(i, _)| i.into()),
+        "@first" => return frame.index.map(|(i, _)| (i == 0).into()),
+        "@last" => return frame.index.map(|(i, n)| (i + 1 == n).into()),
+        _ => scopes
+            .iter()
+            .rev()
+            .find_map(|frame| frame.value.get(head))?
+            .clone(),
+    };
+
+    tail.split('.')
+        .filter(|key| !key.is_empty())
+        .try_fold(value, |value, key| value.get(key).cloned())
+}
+
+fn truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::Number(n) => n.as_f64() != Some(0.0),
+        Value::String(s) => !s.is_empty(),
+        Value::Array(a) => !a.is_empty(),
+        Value::Object(_) => true,
+    }
+}
+
+fn render_nodes(nodes: &[Node], scopes: &mut Vec<Frame>, out: &mut String) -> Result<()> {
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(text),
+            Node::Var {
+                path,
+                filters,
+                line,
+            } => {
+                let value = lookup(path, scopes);
+                let has_default = filters.iter().any(|f| matches!(f, Filter::Default(_)));
+                let mut text = match value {
+                    Some(Value::String(s)) => s,
+                    Some(Value::Null) | None if has_default => String::new(),
+                    None => bail!("line {line}: undefined variable `{path}`"),
+                    Some(Value::Null) => String::new(),
+                    Some(v @ (Value::Bool(_) | Value::Number(_))) => v.to_string(),
+                    Some(_) => {
+                        bail!("line {line}: `{path}` is a list or object; use `{{{{#each}}}}`")
+                    }
+                };
+                for filter in filters {
+                    text = apply(filter, text);
+                }
+                out.push_str(&text);
+            }
+            Node::Each { path, body, line } => {
+                let items = match lookup(path, scopes) {
+                    Some(Value::Array(items)) => items,
+                    Some(Value::Null) => Vec::new(),
+                    Some(_) => bail!("line {line}: `{path}` is not a list"),
+                    None => bail!("line {line}: undefined variable `{path}`"),
+                };
+                let len = items.len();
+                for (i, item) in items.into_iter().enumerate() {
+                    scopes.push(Frame {
+                        value: item,
+                        index: Some((i, len)),
+                    });
+                    let result = render_nodes(body, scopes, out);
+                    scopes.pop();
+                    result?;
+                }
+            }
+            Node::If {
+                path,
+                negate,
+                then,
+                otherwise,
+                line,
+            } => {
+                let value = lookup(path, scopes)
+                    .ok_or_else(|| anyhow!("line {line}: undefined variable `{path}`"))?;
+                let branch = if truthy(&value) != *negate {
+                    then
+                } else {
+                    otherwise
+                };
+                render_nodes(branch, scopes, out)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn apply(filter: &Filter, text: String) -> String {
+    match filter {
+        Filter::SnakeCase => snake_case(&text),
+        Filter::Truncate(len) if text.chars().count() > *len => {
+            let mut truncated: String = text.chars().take(*len).collect();
+            truncated.push('…');
+            truncated
+        }
+        Filter::Truncate(_) => text,
+        Filter::Default(value) if text.is_empty() => value.clone(),
+        Filter::Default(_) => text,
+    }
+}
+
+/// `parser-lexer`, `Parser Lexer` and `parserLexer` all become `parser_lexer`
+fn snake_case(text: &str) -> String {
+    let mut out = String::new();
+    let mut prev_lower = false;
+
+    for c in text.chars() {
+        if c.is_alphanumeric() {
+            if c.is_uppercase() && prev_lower {
+                out.push('_');
+            }
+            out.extend(c.to_lowercase());
+            prev_lower = c.is_lowercase() || c.is_ascii_digit();
+        } else {
+            if !out.is_empty() && !out.ends_with('_') {
+                out.push('_');
+            }
+            prev_lower = false;
+        }
+    }
+
+    out.trim_end_matches('_').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_variables_and_filters() {
+        let context = json!({
+            "module": "parser-lexer",
+            "issue": { "title": "A very long issue title" },
+            "body": "",
+        });
+
+        let out = render(
+            "{{module | snake_case}} {{issue.title | truncate 6}} {{body | default \"none\"}}",
+            &context,
+        )
+        .unwrap();
+        assert_eq!(out, "parser_lexer A very… none");
+        assert_eq!(snake_case("parserLexer Module"), "parser_lexer_module");
+    }
+
+    #[test]
+    fn test_each_and_conditionals() {
+        let context = json!({
+            "issues": [{ "number": 4, "title": "a" }, { "number": 7, "title": "b" }],
+            "draft": false,
+        });
+
+        let template = "\
+{{#each issues}}
+- #{{number}}: {{title}}
+{{/each}}
+{{#each issues}}closes #{{number}}{{#unless @last}}, {{/unless}}{{/each}}
+{{#if draft}}draft{{else}}ready{{/if}}";
+
+        assert_eq!(
+            render(template, &context).unwrap(),
+            "- #4: a\n- #7: b\ncloses #4, closes #7\nready"
+        );
+    }
+
+    #[test]
+    fn test_undefined_variables_fail() {
+        let err = render("ok\n{{closes_str}}", &json!({})).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("line 2: undefined variable `closes_str`"));
+
+        assert!(render("{{#each missing}}{{/each}}", &json!({})).is_err());
+        assert!(render("{{#each xs}}{{nope}}{{/each}}", &json!({ "xs": [1] })).is_err());
+        assert_eq!(render("{{x | default \"y\"}}", &json!({})).unwrap(), "y");
+    }
+
+    #[test]
+    fn test_variables_are_collected_by_scope() {
+        let vars = variables(
+            "{{module}} {{issue.title}} {{body | default \"x\"}}\n\
+             {{#each issues}}{{number}}{{#if @last}}.{{/if}}{{/each}}",
+        )
+        .unwrap();
+
+        let set = |names: &[&str]| names.iter().map(|n| n.to_string()).collect();
+        assert_eq!(vars.required, set(&["issue", "issues", "module"]));
+        assert_eq!(vars.optional, set(&["body"]));
+        assert_eq!(vars.nested, set(&["number"]));
+        assert!(vars.uses("number") && !vars.uses("count"));
+    }
+
+    #[test]
+    fn test_malformed_templates_are_rejected() {
+        assert!(validate("{{#if x}}unclosed").is_err());
+        assert!(validate("{{#each xs}}{{/if}}").is_err());
+        assert!(validate("{{x | shout}}").is_err());
+        assert!(validate("{{x").is_err());
+        assert!(validate("{{/each}}").is_err());
+    }
+}
@@ -0,0 +1,388 @@
+//! GitHub webhook receiver (`chore-bot webhook`)
+//!
+//! Instead of listing every open PR on a schedule, GitHub tells chore-bot
+//! what changed: failed checks, pushes to PRs, newly labelled issues and
+//! workflow runs waiting for approval. Deliveries are verified against the
+//! shared secret (`X-Hub-Signature-256`) and handled one at a time, so the
+//! reaction to one event never races another. Since the body has to be read
+//! before its signature can be checked, bodies larger than GitHub ever sends
+//! are refused and a client gets a few seconds to send one, so nobody can
+//! hold up deliveries without the secret.
+
+use anyhow::{bail, Context, Result};
+use hmac::{Hmac, Mac};
+use serde_json::Value;
+use sha2::Sha256;
+use std::io::Read;
+use std::net::SocketAddr;
+use std::sync::mpsc;
+use std::time::Duration;
+use tiny_http::{Request, Response, Server};
+
+/// GitHub caps webhook payloads at 25 MB
+const MAX_BODY_BYTES: u64 = 25 * 1024 * 1024;
+
+/// How long a client has to send the whole body
+const BODY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Why a request was turned away: the status to answer with and the reason
+type Rejection = (u16, &'static str);
+
+/// A verified webhook delivery
+#[derive(Debug)]
+pub struct Delivery {
+    /// `X-GitHub-Event`, e.g. `check_suite`
+    pub event: String,
+    /// `X-GitHub-Delivery`, for log lines
+    pub id: String,
+    pub payload: Value,
+}
+
+impl Delivery {
+    /// `owner/name` of the repository the event belongs to
+    pub fn repository(&self) -> Option<&str> {
+        self.payload["repository"]["full_name"].as_str()
+    }
+}
+
+/// What a delivery asks chore-bot to do
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// A check suite or run failed on these PRs
+    ChecksFailed { prs: Vec<u32> },
+    /// New commits were pushed to a PR
+    PrSynchronized { pr: u32 },
+    /// An issue gained a label
+    IssueLabeled { issue: u32, label: String },
+    /// A workflow run is waiting for approval
+    RunAwaitingApproval { run_id: u64 },
+    /// Nothing to do, and why
+    Ignored(String),
+}
+
+impl Event {
+    pub fn parse(event: &str, payload: &Value) -> Result<Self> {
+        let action = payload["action"].as_str().unwrap_or_default();
+        let number = |value: &Value| {
+            value["number"]
+                .as_u64()
+                .and_then(|n| u32::try_from(n).ok())
+                .context("Payload is missing a number")
+        };
+
+        Ok(match (event, action) {
+            ("check_suite" | "check_run", "completed") => {
+                let check = &payload[event];
+                if check["conclusion"].as_str() != Some("failure") {
+                    return Ok(Self::Ignored(format!("{event} did not fail")));
+                }
+                let prs = check["pull_requests"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .map(number)
+                    .collect::<Result<Vec<_>>>()?;
+                if prs.is_empty() {
+                    return Ok(Self::Ignored(format!("{event} is not on a PR")));
+                }
+                Self::ChecksFailed { prs }
+            }
+            ("pull_request", "synchronize") => Self::PrSynchronized {
+                pr: number(&payload["pull_request"])?,
+            },
+            ("issues", "labeled") => Self::IssueLabeled {
+                issue: number(&payload["issue"])?,
+                label: payload["label"]["name"]
+                    .as_str()
+                    .context("Payload is missing the label")?
+                    .to_string(),
+            },
+            ("workflow_run", "requested") => {
+                let run = &payload["workflow_run"];
+                let waiting = [&run["status"], &run["conclusion"]]
+                    .iter()
+                    .any(|v| v.as_str() == Some("action_required"));
+                if !waiting {
+                    return Ok(Self::Ignored("workflow run needs no approval".to_string()));
+                }
+                Self::RunAwaitingApproval {
+                    run_id: run["id"]
+                        .as_u64()
+                        .context("Payload is missing the run id")?,
+                }
+            }
+            ("ping", _) => Self::Ignored("pong".to_string()),
+            _ => Self::Ignored(format!("{event} {action} is not handled")),
+        })
+    }
+}
+
+/// Whether `signature` (`sha256=<hex>`) is the HMAC-SHA256 of `body`
+pub fn verify_signature(secret: &[u8], body: &[u8], signature: &str) -> bool {
+    let Some(digest) = signature
+        .strip_prefix("sha256=")
+        .and_then(|hex| hex::decode(hex).ok())
+    else {
+        return false;
+    };
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC takes any key length");
+    mac.update(body);
+    mac.verify_slice(&digest).is_ok()
+}
+
+/// `X-Hub-Signature-256` value for `body`, as GitHub would send it
+#[cfg(test)]
+pub fn sign(secret: &[u8], body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).unwrap();
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// HTTP server accepting signed deliveries
+pub struct Listener {
+    server: Server,
+    secret: Vec<u8>,
+    body_timeout: Duration,
+}
+
+impl Listener {
+    pub fn bind(addr: &str, secret: &str) -> Result<Self> {
+        if secret.is_empty() {
+            bail!("A webhook secret is required");
+        }
+        let server =
+            Server::http(addr).map_err(|e| anyhow::anyhow!("Failed to listen on {addr}: {e}"))?;
+        Ok(Self {
+            server,
+            secret: secret.as_bytes().to_vec(),
+            body_timeout: BODY_TIMEOUT,
+        })
+    }
+
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.server.server_addr().to_ip()
+    }
+
+    /// Handle deliveries until the server stops
+    pub fn serve(&self, handle: &mut dyn FnMut(&Delivery) -> Result<String>) -> Result<()> {
+        loop {
+            self.handle_next(handle)?;
+        }
+    }
+
+    /// Wait for one request and answer it, passing it to `handle` if it is a
+    /// verified delivery
+    pub fn handle_next(&self, handle: &mut dyn FnMut(&Delivery) -> Result<String>) -> Result<()> {
+        let request = self.server.recv().context("Webhook server stopped")?;
+        let (signature, event, id) = match headers(&request) {
+            Ok(headers) => headers,
+            Err(rejection) => {
+                reject(request, rejection);
+                return Ok(());
+            }
+        };
+        let Some((request, body)) = self.read_body(request) else {
+            // The reader still owns the request and drops it once the client
+            // gives up
+            println!("   ❌ body not received within {:?}", self.body_timeout);
+            return Ok(());
+        };
+        let body = match body {
+            Ok(body) if !verify_signature(&self.secret, &body, &signature) => {
+                Err((401, "bad signature"))
+            }
+            Ok(body) => Ok(body),
+            Err(rejection) => Err(rejection),
+        };
+        let payload = match body
+            .and_then(|body| serde_json::from_slice(&body).map_err(|_| (400, "body is not JSON")))
+        {
+            Ok(payload) => payload,
+            Err(rejection) => {
+                reject(request, rejection);
+                return Ok(());
+            }
+        };
+
+        let delivery = Delivery { event, id, payload };
+        println!("📨 {} {}", delivery.event, delivery.id);
+        let (status, message) = match handle(&delivery) {
+            Ok(message) => (200, message),
+            Err(e) => (500, format!("{e:#}")),
+        };
+        let icon = if status == 200 { "✅" } else { "❌" };
+        println!("   {icon} {status} {message}");
+        request
+            .respond(Response::from_string(message).with_status_code(status))
+            .context("Failed to answer webhook")
+    }
+
+    /// Read the body of `request`, or `None` if it didn't arrive within
+    /// `body_timeout`
+    fn read_body(&self, request: Request) -> Option<(Request, Result<Vec<u8>, Rejection>)> {
+        // A blocked read can't be interrupted, so it happens on its own thread
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            let mut request = request;
+            let mut body = Vec::new();
+            let read = request
+                .as_reader()
+                .take(MAX_BODY_BYTES + 1)
+                .read_to_end(&mut body);
+            let body = match read {
+                Err(_) => Err((400, "unreadable body")),
+                Ok(_) if body.len() as u64 > MAX_BODY_BYTES => Err((413, "body too large")),
+                Ok(_) => Ok(body),
+            };
+            let _ = sender.send((request, body));
+        });
+        receiver.recv_timeout(self.body_timeout).ok()
+    }
+}
+
+/// Answer a rejected request without waiting on the client: answering first
+/// drains whatever of the body is unread, which may never arrive
+fn reject(request: Request, (status, message): Rejection) {
+    println!("   ❌ {status} {message}");
+    std::thread::spawn(move || {
+        let _ = request.respond(Response::from_string(message).with_status_code(status));
+    });
+}
+
+/// Signature, event and delivery id of a request that may carry a delivery
+fn headers(request: &Request) -> Result<(String, String, String), Rejection> {
+    if *request.method() != tiny_http::Method::Post {
+        return Err((405, "only POST is accepted"));
+    }
+    if request
+        .body_length()
+        .is_some_and(|length| length as u64 > MAX_BODY_BYTES)
+    {
+        return Err((413, "body too large"));
+    }
+    let header = |name: &'static str| {
+        request
+            .headers()
+            .iter()
+            .find(|h| h.field.equiv(name))
+            .map(|h| h.value.as_str().to_string())
+    };
+    let signature = header("X-Hub-Signature-256").ok_or((401, "missing signature"))?;
+    let event = header("X-GitHub-Event").ok_or((400, "missing X-GitHub-Event"))?;
+    let id = header("X-GitHub-Delivery").unwrap_or_default();
+
+    Ok((signature, event, id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> Value {
+        let path = format!(
+            "{}/tests/fixtures/webhooks/{name}.json",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_verify_signature() {
+        // Example from GitHub's "Validating webhook deliveries" docs
+        let secret = b"It's a Secret to Everybody";
+        let signature = "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17";
+        assert!(verify_signature(secret, b"Hello, World!", signature));
+        assert_eq!(sign(secret, b"Hello, World!"), signature);
+
+        assert!(!verify_signature(secret, b"Hello, World?", signature));
+        assert!(!verify_signature(b"guess", b"Hello, World!", signature));
+        assert!(!verify_signature(secret, b"Hello, World!", &signature[7..]));
+        assert!(!verify_signature(secret, b"Hello, World!", "sha256=zz"));
+    }
+
+    #[test]
+    fn test_parse_recorded_payloads() {
+        let parse = |event: &str, name: &str| Event::parse(event, &fixture(name)).unwrap();
+
+        assert_eq!(
+            parse("check_suite", "check_suite_completed"),
+            Event::ChecksFailed { prs: vec![15] }
+        );
+        assert_eq!(
+            parse("pull_request", "pull_request_synchronize"),
+            Event::PrSynchronized { pr: 12 }
+        );
+        assert_eq!(
+            parse("issues", "issues_labeled"),
+            Event::IssueLabeled {
+                issue: 30,
+                label: "bug".to_string()
+            }
+        );
+        assert_eq!(
+            parse("workflow_run", "workflow_run_requested"),
+            Event::RunAwaitingApproval { run_id: 100 }
+        );
+
+        let mut passed = fixture("check_suite_completed");
+        passed["check_suite"]["conclusion"] = "success".into();
+        assert!(matches!(
+            Event::parse("check_suite", &passed).unwrap(),
+            Event::Ignored(_)
+        ));
+        assert!(matches!(
+            Event::parse("star", &passed).unwrap(),
+            Event::Ignored(_)
+        ));
+    }
+
+    #[test]
+    fn test_slow_or_oversized_bodies_dont_hold_up_deliveries() {
+        use std::io::Write;
+        use std::net::TcpStream;
+
+        let mut listener = Listener::bind("127.0.0.1:0", "s3cret").unwrap();
+        listener.body_timeout = Duration::from_millis(200);
+        let addr = listener.local_addr().unwrap();
+        let post = |length: u64, body: &[u8]| {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            write!(
+                stream,
+                "POST / HTTP/1.1\r\nHost: x\r\nX-GitHub-Event: ping\r\n\
+                 X-Hub-Signature-256: sha256=00\r\nContent-Length: {length}\r\n\r\n"
+            )
+            .unwrap();
+            stream.write_all(body).unwrap();
+            stream
+        };
+        let mut handled = 0;
+        let mut handle = |_: &Delivery| {
+            handled += 1;
+            Ok(String::new())
+        };
+
+        // Stops sending early in its body and keeps the connection open;
+        // tiny_http itself reads bodies of up to 1 KB before passing them on
+        let _slow = post(4096, b"{");
+        listener.handle_next(&mut handle).unwrap();
+
+        let mut huge = post(MAX_BODY_BYTES + 1, b"");
+        listener.handle_next(&mut handle).unwrap();
+        let mut status = [0; 12];
+        huge.read_exact(&mut status).unwrap();
+        assert_eq!(&status, b"HTTP/1.1 413");
+
+        let body = b"{}";
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "POST / HTTP/1.1\r\nHost: x\r\nConnection: close\r\nX-GitHub-Event: ping\r\n\
+             X-Hub-Signature-256: {}\r\nContent-Length: 2\r\n\r\n",
+            sign(b"s3cret", body)
+        )
+        .unwrap();
+        stream.write_all(body).unwrap();
+        listener.handle_next(&mut handle).unwrap();
+        assert_eq!(handled, 1);
+    }
+}
@@ -0,0 +1,4 @@
+pub mod cli;
+pub mod config;
+pub mod parser;
+pub mod server;
@@ -0,0 +1,3 @@
+pub fn lexer(input: &str) -> Vec<&str> {
+    input.split_whitespace().collect()
+}